@@ -0,0 +1,787 @@
+//! Assembler for the textual listing format used by the `.dis` files.
+//!
+//! Each line holds at most one statement, optionally preceded by the
+//! address column found in listings (`0012` or `????`), which is ignored:
+//!
+//! ```text
+//! print:
+//!   0092   loadimm r8 <- #ite_then_1
+//!   0096   move r0 <- r8 if r11 != 0
+//! str_1:
+//!   ???? b'Hello, world!\n'
+//! ```
+//!
+//! Data is given either as a Python-like byte string (`b'...'` or `b"..."`)
+//! or as a list of bytes (`[0, 0, 0, 0]`). A `;` starts a comment.
+//!
+//! Pseudo-instructions expand to the sequences used by the listings, with
+//! r2 as the stack pointer and a scratch register, r3 unless changed by a
+//! `.scratch rN` directive:
+//!
+//! | Pseudo-instruction | Expansion |
+//! |--------------------|-----------|
+//! | `push rX` | `loadimm S <- #4; sub r2 <- r2 - S; store [r2] <- rX` |
+//! | `pop rX` | `loadimm S <- #-4; sub r2 <- r2 - S; loadimm S <- #4; sub S <- r2 - S; load rX <- [S]` |
+//! | `call f` | `loadimm S <- #4; sub r2 <- r2 - S; loadimm S <- #ret; store [r2] <- S; loadimm r0 <- #f` |
+//! | `ret` | `pop r0` |
+//! | `jmp l` | `loadimm r0 <- #l` |
+//! | `jnz rX, l` | `loadimm S <- #l; move r0 <- S if rX != 0` |
+//!
+//! where `ret` is the address following the call. Targets are labels, or
+//! immediates such as `#24`.
+//!
+//! A listing can also be assembled into a relocatable [`Object`], see
+//! [`object`](crate::object). `.export name` and `.import name` directives
+//! then tell which labels are shared with other modules.
+
+use crate::object::{Object, Relocation, Symbol};
+use crate::{ArithOp, Instruction, Width};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+
+/// Error raised while assembling a listing. Lines and columns are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// An assembled program.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    /// Memory image, to be loaded at address 0.
+    pub bytes: Vec<u8>,
+    /// Address of every label defined in the listing.
+    pub labels: BTreeMap<String, u32>,
+    /// Address of the instruction found on each line holding one, by line
+    /// number.
+    pub lines: BTreeMap<usize, u32>,
+    /// Bytes emitted by each line holding an instruction, by line number.
+    /// They span several instructions for pseudo-instructions.
+    pub ranges: BTreeMap<usize, Range<u32>>,
+}
+
+impl Program {
+    /// Instructions emitted by the listing with their address and size,
+    /// including those of pseudo-instructions, in address order.
+    #[must_use]
+    pub fn instructions(&self) -> Vec<(u32, Instruction, usize)> {
+        let mut instructions = Vec::new();
+        for range in self.ranges.values() {
+            let mut address = range.start;
+            while address < range.end {
+                let Ok((instruction, size)) =
+                    Instruction::decode(&self.bytes[address as usize..range.end as usize])
+                else {
+                    break;
+                };
+                instructions.push((address, instruction, size));
+                address += size as u32;
+            }
+        }
+        instructions
+    }
+}
+
+/// Assemble a listing into a memory image.
+///
+/// # Errors
+/// The first syntax error, unknown label or out of range value found in
+/// the listing is returned.
+pub fn assemble(source: &str) -> Result<Program> {
+    let Assembly {
+        mut program,
+        fixups,
+        ..
+    } = Assembly::parse(source)?;
+    for fixup in fixups {
+        let Some(&address) = program.labels.get(&fixup.label) else {
+            return fixup.error(format!("undefined label `{}`", fixup.label));
+        };
+        fixup.patch(&mut program.bytes, address)?;
+    }
+    Ok(program)
+}
+
+/// Assemble a listing into a relocatable object, to be linked with
+/// [`object::link`](crate::object::link).
+///
+/// # Errors
+/// Like [`assemble`], except that labels given to `.import` may be
+/// undefined. Labels given to `.export` must be defined.
+pub fn assemble_object(source: &str) -> Result<Object> {
+    let Assembly {
+        mut program,
+        fixups,
+        relocations,
+        exports,
+        imports,
+        ..
+    } = Assembly::parse(source)?;
+    let mut relocations: Vec<Relocation> = relocations
+        .into_iter()
+        .map(|offset| Relocation {
+            offset: offset as u32,
+            import: None,
+        })
+        .collect();
+    for fixup in fixups {
+        let import = match program.labels.get(&fixup.label) {
+            Some(&address) => {
+                fixup.patch(&mut program.bytes, address)?;
+                None
+            }
+            None if imports.contains_key(&fixup.label) => Some(fixup.label),
+            None => return fixup.error(format!("undefined label `{}`", fixup.label)),
+        };
+        relocations.push(Relocation {
+            offset: fixup.offset as u32,
+            import,
+        });
+    }
+    relocations.sort_by_key(|relocation| relocation.offset);
+    for (name, &(line, column)) in &imports {
+        if program.labels.contains_key(name) {
+            return Err(Error {
+                line,
+                column,
+                message: format!("imported label `{name}` is also defined"),
+            });
+        }
+    }
+    for (name, &(line, column)) in &exports {
+        if !program.labels.contains_key(name) {
+            return Err(Error {
+                line,
+                column,
+                message: format!("exported label `{name}` is not defined"),
+            });
+        }
+    }
+    let symbols = program
+        .labels
+        .into_iter()
+        .map(|(name, address)| {
+            let exported = exports.contains_key(&name);
+            (name, Symbol { address, exported })
+        })
+        .collect();
+    Ok(Object {
+        bytes: program.bytes,
+        symbols,
+        relocations,
+    })
+}
+
+/// Scratch register of the pseudo-instructions, unless changed by a
+/// `.scratch` directive.
+pub const DEFAULT_SCRATCH: u8 = 3;
+
+/// Register used as stack pointer by the pseudo-instructions.
+const SP: u8 = 2;
+
+/// State of the assembly, carried from line to line.
+struct Assembly {
+    program: Program,
+    fixups: Vec<Fixup>,
+    /// Offsets of the immediates holding an address other than a label.
+    relocations: Vec<usize>,
+    scratch: u8,
+    /// Labels given to `.export` and `.import`, with the position of the
+    /// directive.
+    exports: BTreeMap<String, (usize, usize)>,
+    imports: BTreeMap<String, (usize, usize)>,
+}
+
+impl Assembly {
+    fn parse(source: &str) -> Result<Self> {
+        let mut asm = Assembly {
+            program: Program::default(),
+            fixups: Vec::new(),
+            relocations: Vec::new(),
+            scratch: DEFAULT_SCRATCH,
+            exports: BTreeMap::new(),
+            imports: BTreeMap::new(),
+        };
+        for (index, text) in source.lines().enumerate() {
+            let mut line = Line::new(text, index + 1);
+            line.statement(&mut asm)?;
+        }
+        Ok(asm)
+    }
+
+    fn address(&self) -> u32 {
+        u32::try_from(self.program.bytes.len()).expect("program too large")
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.program.bytes.extend(instruction.encode());
+    }
+
+    /// Emit a `loadimm`, registering a fixup if the immediate is a label.
+    fn loadimm(&mut self, dst: u8, imm: Immediate, line: usize) {
+        let imm = match imm {
+            Immediate::Value(value) => value,
+            Immediate::Label(label, column) => {
+                self.fixups.push(Fixup {
+                    offset: self.program.bytes.len() + 2,
+                    label,
+                    line,
+                    column,
+                });
+                0
+            }
+        };
+        self.emit(Instruction::LoadImm { dst, imm });
+    }
+}
+
+/// A `loadimm` immediate referring to a label, patched once every label
+/// is known.
+struct Fixup {
+    offset: usize,
+    label: String,
+    line: usize,
+    column: usize,
+}
+
+impl Fixup {
+    fn error<T>(&self, message: String) -> Result<T> {
+        Err(Error {
+            line: self.line,
+            column: self.column,
+            message,
+        })
+    }
+
+    /// Write the address of the label in the immediate.
+    fn patch(&self, bytes: &mut [u8], address: u32) -> Result<()> {
+        let Ok(imm) = i16::try_from(address) else {
+            return self.error(format!(
+                "address of label `{}` ({address}) does not fit in an immediate",
+                self.label
+            ));
+        };
+        bytes[self.offset..self.offset + 2].copy_from_slice(&imm.to_le_bytes());
+        Ok(())
+    }
+}
+
+/// An immediate operand, before label resolution.
+enum Immediate {
+    Value(i16),
+    Label(String, usize),
+}
+
+/// Cursor over a single line of the listing.
+struct Line<'a> {
+    text: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Line<'a> {
+    fn new(text: &'a str, line: usize) -> Self {
+        Line { text, pos: 0, line }
+    }
+
+    fn error_at<T>(&self, pos: usize, message: impl Into<String>) -> Result<T> {
+        Err(Error {
+            line: self.line,
+            column: self.column(pos),
+            message: message.into(),
+        })
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T> {
+        self.error_at(self.pos, message)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Skip spaces and consume `token` if it comes next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_spaces();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            self.error(format!("expected `{token}`"))
+        }
+    }
+
+    /// Check that only spaces or a comment remain.
+    fn end(&mut self) -> Result<()> {
+        self.skip_spaces();
+        match self.peek() {
+            None | Some(';') => Ok(()),
+            Some(_) => self.error("unexpected trailing characters"),
+        }
+    }
+
+    fn word(&mut self) -> Option<&'a str> {
+        self.skip_spaces();
+        let rest = self.rest();
+        if !rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            return None;
+        }
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        self.pos += len;
+        Some(&rest[..len])
+    }
+
+    fn statement(&mut self, asm: &mut Assembly) -> Result<()> {
+        self.skip_spaces();
+        if self.peek().is_some_and(|c| c.is_ascii_digit() || c == '?') {
+            self.address_column()?;
+        }
+        self.skip_spaces();
+        let start = self.pos;
+        match self.peek() {
+            None | Some(';') => return Ok(()),
+            Some('[') => return self.byte_list(&mut asm.program.bytes),
+            Some('b') if self.rest()[1..].starts_with(['\'', '"']) => {
+                return self.byte_string(&mut asm.program.bytes);
+            }
+            Some('.') => {
+                self.bump();
+                let directive = self.word();
+                let labels = match directive {
+                    Some("scratch") => {
+                        asm.scratch = self.register()?;
+                        return self.end();
+                    }
+                    Some("export") => &mut asm.exports,
+                    Some("import") => &mut asm.imports,
+                    _ => return self.error_at(start, "unknown directive"),
+                };
+                self.skip_spaces();
+                let column = self.column(self.pos);
+                let Some(label) = self.word() else {
+                    return self.error("expected a label");
+                };
+                labels.insert(label.to_owned(), (self.line, column));
+                return self.end();
+            }
+            _ => (),
+        }
+        let Some(word) = self.word() else {
+            return self.error("expected a label, an instruction or data");
+        };
+        if self.eat(":") {
+            let address = asm.address();
+            if asm
+                .program
+                .labels
+                .insert(word.to_owned(), address)
+                .is_some()
+            {
+                return self.error_at(start, format!("label `{word}` is defined twice"));
+            }
+            return self.statement(asm);
+        }
+        let address = asm.address();
+        asm.program.lines.insert(self.line, address);
+        if self.pseudo(word, asm)? {
+            self.end()?;
+        } else {
+            self.instruction(start, word, asm)?;
+        }
+        asm.program.ranges.insert(self.line, address..asm.address());
+        Ok(())
+    }
+
+    /// Skip the address column of a listing line.
+    fn address_column(&mut self) -> Result<()> {
+        let rest = self.rest();
+        let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let column = &rest[..len];
+        if column != "????" && !column.bytes().all(|b| b.is_ascii_digit()) {
+            return self.error("invalid address column");
+        }
+        self.pos += len;
+        Ok(())
+    }
+
+    /// Expand the pseudo-instruction `mnemonic`, if it is one.
+    fn pseudo(&mut self, mnemonic: &str, asm: &mut Assembly) -> Result<bool> {
+        let scratch = asm.scratch;
+        match mnemonic {
+            "push" => {
+                let start = self.pos;
+                let src = self.register()?;
+                if src == scratch {
+                    return self.error_at(start, "cannot push the scratch register");
+                }
+                asm.emit(Instruction::LoadImm {
+                    dst: scratch,
+                    imm: 4,
+                });
+                asm.emit(Instruction::Sub {
+                    dst: SP,
+                    lhs: SP,
+                    rhs: scratch,
+                });
+                asm.emit(Instruction::Store { addr: SP, src });
+            }
+            "pop" => {
+                let dst = self.register()?;
+                pop(asm, dst);
+            }
+            "ret" => pop(asm, 0),
+            "call" => {
+                let target = self.target()?;
+                // Address following the 19 bytes of the expansion
+                let ret = asm.address() + 19;
+                let Ok(ret) = i16::try_from(ret) else {
+                    return self
+                        .error(format!("return address {ret} does not fit in an immediate"));
+                };
+                asm.emit(Instruction::LoadImm {
+                    dst: scratch,
+                    imm: 4,
+                });
+                asm.emit(Instruction::Sub {
+                    dst: SP,
+                    lhs: SP,
+                    rhs: scratch,
+                });
+                asm.relocations.push(asm.program.bytes.len() + 2);
+                asm.emit(Instruction::LoadImm {
+                    dst: scratch,
+                    imm: ret,
+                });
+                asm.emit(Instruction::Store {
+                    addr: SP,
+                    src: scratch,
+                });
+                asm.loadimm(0, target, self.line);
+            }
+            "jmp" => {
+                let target = self.target()?;
+                asm.loadimm(0, target, self.line);
+            }
+            "jnz" => {
+                let start = self.pos;
+                let cond = self.register()?;
+                if cond == scratch {
+                    return self.error_at(start, "cannot test the scratch register");
+                }
+                self.expect(",")?;
+                let target = self.target()?;
+                asm.loadimm(scratch, target, self.line);
+                asm.emit(Instruction::MoveIf {
+                    dst: 0,
+                    src: scratch,
+                    cond,
+                });
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Parse the target of a jump: a label, or an immediate.
+    fn target(&mut self) -> Result<Immediate> {
+        self.skip_spaces();
+        if self.peek() == Some('#') {
+            return self.immediate();
+        }
+        let start = self.pos;
+        match self.word() {
+            Some(label) => Ok(Immediate::Label(label.to_owned(), self.column(start))),
+            None => self.error("expected a label"),
+        }
+    }
+
+    fn instruction(&mut self, start: usize, mnemonic: &str, asm: &mut Assembly) -> Result<()> {
+        let instruction = match mnemonic {
+            "move" => {
+                let dst = self.register()?;
+                self.expect("<-")?;
+                let src = self.register()?;
+                self.expect("if")?;
+                let cond = self.register()?;
+                self.expect("!=")?;
+                self.expect("0")?;
+                Instruction::MoveIf { dst, src, cond }
+            }
+            "store" => {
+                self.expect("[")?;
+                let addr = self.register()?;
+                self.expect("]")?;
+                self.expect("<-")?;
+                let src = self.register()?;
+                Instruction::Store { addr, src }
+            }
+            "load" => {
+                let dst = self.register()?;
+                self.expect("<-")?;
+                self.expect("[")?;
+                let addr = self.register()?;
+                self.expect("]")?;
+                Instruction::Load { dst, addr }
+            }
+            "loadimm" => {
+                let dst = self.register()?;
+                self.expect("<-")?;
+                let imm = self.immediate()?;
+                asm.loadimm(dst, imm, self.line);
+                return self.end();
+            }
+            "sub" => {
+                let dst = self.register()?;
+                self.expect("<-")?;
+                let lhs = self.register()?;
+                self.expect("-")?;
+                let rhs = self.register()?;
+                Instruction::Sub { dst, lhs, rhs }
+            }
+            "out" => Instruction::Out {
+                src: self.register()?,
+            },
+            "exit" => {
+                self.skip_spaces();
+                match self.peek() {
+                    None | Some(';') => Instruction::Exit,
+                    Some(_) => Instruction::ExitWith {
+                        src: self.register()?,
+                    },
+                }
+            }
+            "out_number" => Instruction::OutNumber {
+                src: self.register()?,
+            },
+            "in" => Instruction::In {
+                dst: self.register()?,
+            },
+            "in_number" => Instruction::InNumber {
+                dst: self.register()?,
+            },
+            "load8" | "load8s" | "load16" | "load16s" => {
+                let dst = self.register()?;
+                self.expect("<-")?;
+                self.expect("[")?;
+                let addr = self.register()?;
+                self.expect("]")?;
+                Instruction::LoadNarrow {
+                    width: if mnemonic.starts_with("load8") {
+                        Width::Byte
+                    } else {
+                        Width::Half
+                    },
+                    signed: mnemonic.ends_with('s'),
+                    dst,
+                    addr,
+                }
+            }
+            "store8" | "store16" => {
+                self.expect("[")?;
+                let addr = self.register()?;
+                self.expect("]")?;
+                self.expect("<-")?;
+                let src = self.register()?;
+                let width = if mnemonic == "store8" {
+                    Width::Byte
+                } else {
+                    Width::Half
+                };
+                Instruction::StoreNarrow { width, addr, src }
+            }
+            _ => match ArithOp::ALL
+                .into_iter()
+                .find(|op| op.mnemonic() == mnemonic)
+            {
+                Some(op) => {
+                    let dst = self.register()?;
+                    self.expect("<-")?;
+                    let lhs = self.register()?;
+                    self.expect(op.operator())?;
+                    let rhs = self.register()?;
+                    Instruction::Arith { op, dst, lhs, rhs }
+                }
+                None => return self.error_at(start, format!("unknown instruction `{mnemonic}`")),
+            },
+        };
+        asm.emit(instruction);
+        self.end()
+    }
+
+    fn register(&mut self) -> Result<u8> {
+        self.skip_spaces();
+        let start = self.pos;
+        match self.word() {
+            Some(word) if word.starts_with('r') && word.len() > 1 => {
+                match word[1..].parse::<u8>() {
+                    Ok(reg) if reg < 16 => Ok(reg),
+                    Ok(_) => self.error_at(start, format!("register `{word}` does not exist")),
+                    Err(_) => self.error_at(start, "expected a register"),
+                }
+            }
+            _ => self.error_at(start, "expected a register"),
+        }
+    }
+
+    fn immediate(&mut self) -> Result<Immediate> {
+        self.expect("#")?;
+        let start = self.pos;
+        if let Some(label) = self.word() {
+            return Ok(Immediate::Label(label.to_owned(), self.column(start)));
+        }
+        let value = self.number()?;
+        match i16::try_from(value) {
+            Ok(value) => Ok(Immediate::Value(value)),
+            Err(_) => self.error_at(start, format!("immediate {value} does not fit in 16 bits")),
+        }
+    }
+
+    fn column(&self, pos: usize) -> usize {
+        self.text[..pos].chars().count() + 1
+    }
+
+    /// Parse a decimal or `0x` hexadecimal number with an optional sign.
+    fn number(&mut self) -> Result<i64> {
+        let start = self.pos;
+        let negative = self.eat("-");
+        let (radix, digits_start) = if self.rest().starts_with("0x") {
+            (16, self.pos + 2)
+        } else {
+            (10, self.pos)
+        };
+        let digits = &self.text[digits_start..];
+        let len = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        let Ok(value) = i64::from_str_radix(&digits[..len], radix) else {
+            return self.error_at(start, "expected a number");
+        };
+        self.pos = digits_start + len;
+        Ok(if negative { -value } else { value })
+    }
+
+    /// Parse a `[1, 2, 3]` list of bytes.
+    fn byte_list(&mut self, bytes: &mut Vec<u8>) -> Result<()> {
+        self.expect("[")?;
+        if !self.eat("]") {
+            loop {
+                self.skip_spaces();
+                let start = self.pos;
+                let value = self.number()?;
+                let Ok(byte) = u8::try_from(value) else {
+                    return self.error_at(start, format!("{value} does not fit in a byte"));
+                };
+                bytes.push(byte);
+                if self.eat("]") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        self.end()
+    }
+
+    /// Parse a `b'...'` or `b"..."` byte string.
+    fn byte_string(&mut self, bytes: &mut Vec<u8>) -> Result<()> {
+        let start = self.pos;
+        self.bump();
+        let quote = self.bump();
+        loop {
+            let pos = self.pos;
+            match self.bump() {
+                None => return self.error_at(start, "unterminated byte string"),
+                Some(c) if Some(c) == quote => break,
+                Some('\\') => bytes.push(self.escape()?),
+                Some(c) if c.is_ascii() => bytes.push(c as u8),
+                Some(_) => return self.error_at(pos, "non-ASCII character in byte string"),
+            }
+        }
+        self.end()
+    }
+
+    fn escape(&mut self) -> Result<u8> {
+        let pos = self.pos;
+        Ok(match self.bump() {
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('\'') => b'\'',
+            Some('"') => b'"',
+            Some('x') => {
+                let digits = self.rest().get(..2).unwrap_or_default();
+                match u8::from_str_radix(digits, 16) {
+                    Ok(byte) if digits.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                        self.pos += 2;
+                        byte
+                    }
+                    _ => return self.error_at(pos, "invalid `\\x` escape"),
+                }
+            }
+            _ => return self.error_at(pos, "invalid escape sequence"),
+        })
+    }
+}
+
+/// Emit the expansion of `pop dst`.
+fn pop(asm: &mut Assembly, dst: u8) {
+    let scratch = asm.scratch;
+    asm.emit(Instruction::LoadImm {
+        dst: scratch,
+        imm: -4,
+    });
+    asm.emit(Instruction::Sub {
+        dst: SP,
+        lhs: SP,
+        rhs: scratch,
+    });
+    asm.emit(Instruction::LoadImm {
+        dst: scratch,
+        imm: 4,
+    });
+    asm.emit(Instruction::Sub {
+        dst: scratch,
+        lhs: SP,
+        rhs: scratch,
+    });
+    asm.emit(Instruction::Load { dst, addr: scratch });
+}
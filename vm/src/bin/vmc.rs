@@ -0,0 +1,44 @@
+use clap::Parser;
+use interpreter::{asm, compiler};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Compile a vmc program into a program image, or into a listing if the
+/// output has a `.dis` extension.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    source: PathBuf,
+    /// Output file, the source with a `.bin` extension by default
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Memory size in bytes of the machine running the program, at the top
+    /// of which the stack starts
+    #[arg(long, value_name = "BYTES", default_value_t = interpreter::MEMORY_SIZE)]
+    memory_size: usize,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let output = cli
+        .output
+        .unwrap_or_else(|| cli.source.with_extension("bin"));
+    if let Err(e) = compile(&cli.source, &output, cli.memory_size) {
+        eprintln!("Error: {e}");
+        process::exit(1);
+    }
+}
+
+fn compile(source: &Path, output: &Path, memory_size: usize) -> Result<(), String> {
+    let text = std::fs::read_to_string(source).map_err(|e| format!("{}: {e}", source.display()))?;
+    let listing = compiler::compile_with_memory_size(&text, memory_size)
+        .map_err(|e| format!("{}:{e}", source.display()))?;
+    let contents = if output.extension().is_some_and(|ext| ext == "dis") {
+        listing.into_bytes()
+    } else {
+        asm::assemble(&listing)
+            .expect("the compiler generates valid listings")
+            .bytes
+    };
+    std::fs::write(output, contents).map_err(|e| format!("{}: {e}", output.display()))
+}
@@ -0,0 +1,460 @@
+//! Static verification of programs, and their control-flow graph.
+//!
+//! [`check`] follows the control flow of a program from its entry point,
+//! decoding every reachable instruction before the program runs. The IP
+//! being r0, jumps are the instructions writing r0, and their target is
+//! known when it is a constant: `loadimm r0 <- #target`, or
+//! `loadimm rX <- #target` followed by `move r0 <- rX if rC != 0`.
+//! Constants are tracked through the straight-line code of a basic block
+//! only. Jumps to unknown targets, such as the return `load r0 <- [r3]`,
+//! end their path, and a jump is taken as a call returning to the next
+//! instruction when the address of that instruction has been stored in
+//! memory beforehand, as the calling convention of the listings does.
+//!
+//! The problems found are invalid instructions in reachable code, jumps
+//! outside the memory or into the middle of an instruction, and blocks of
+//! code which are never reached. A program with sections or its entry point
+//! past the end of the memory is not checked further.
+
+use crate::executable::{Executable, SectionKind};
+use crate::machine::NREGS;
+use crate::{ErrorKind, Instruction};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+
+/// Control-flow graph of the reachable code of a program.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cfg {
+    pub entry: u32,
+    /// Basic blocks, by address.
+    pub blocks: BTreeMap<u32, Block>,
+}
+
+/// A sequence of instructions only entered at its start, and left at its
+/// end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: u32,
+    /// Address following the last instruction.
+    pub end: u32,
+    /// Instructions of the block, with their address.
+    pub instructions: Vec<(u32, Instruction)>,
+    /// Blocks which may run next, the next block in memory first if the
+    /// execution may continue there.
+    pub successors: Vec<u32>,
+    /// Whether the block ends with a jump to an unknown target, such as a
+    /// return.
+    pub indirect: bool,
+}
+
+/// A problem found in a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// Address of the faulty instruction, or of the unreachable code.
+    pub address: u32,
+    pub kind: ProblemKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// Reachable instruction referring to a register out of r0 to r15
+    InvalidRegister { reg: usize },
+    /// Reachable unknown opcode, or instruction of a disabled extension
+    UnknownOpcode { opcode: u8 },
+    /// Reachable instruction cut by the end of the memory
+    TruncatedInstruction,
+    /// Jump to an address past the end of the memory
+    JumpOutOfMemory { target: u32 },
+    /// Jump to an address inside the instruction at `instruction`
+    JumpIntoInstruction { target: u32, instruction: u32 },
+    /// Valid instructions up to `end` which are never executed
+    Unreachable { end: u32 },
+    /// Section ending at `end`, past the end of the memory
+    SectionOutOfMemory { end: u64 },
+    /// Entry point past the end of the memory
+    EntryOutOfMemory,
+}
+
+impl ProblemKind {
+    /// Whether the problem makes the program fault or misbehave when its
+    /// code is reached, rather than being a warning.
+    #[must_use]
+    pub fn is_error(&self) -> bool {
+        !matches!(self, ProblemKind::Unreachable { .. })
+    }
+}
+
+impl fmt::Display for ProblemKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProblemKind::InvalidRegister { reg } => write!(f, "invalid register r{reg}"),
+            ProblemKind::UnknownOpcode { opcode } => write!(f, "unknown opcode {opcode}"),
+            ProblemKind::TruncatedInstruction => {
+                write!(f, "instruction truncated by the end of memory")
+            }
+            ProblemKind::JumpOutOfMemory { target } => {
+                write!(f, "jump to {target}, out of memory")
+            }
+            ProblemKind::JumpIntoInstruction {
+                target,
+                instruction,
+            } => write!(
+                f,
+                "jump to {target:04}, inside the instruction at {instruction:04}"
+            ),
+            ProblemKind::Unreachable { end } => write!(f, "unreachable code up to {end:04}"),
+            ProblemKind::SectionOutOfMemory { end } => {
+                write!(f, "section ending at {end}, out of memory")
+            }
+            ProblemKind::EntryOutOfMemory => write!(f, "entry point out of memory"),
+        }
+    }
+}
+
+/// Result of [`check`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Analysis {
+    pub cfg: Cfg,
+    /// Problems found, by address.
+    pub problems: Vec<Problem>,
+}
+
+/// A reachable instruction and where the execution goes after it.
+struct Node {
+    instruction: Instruction,
+    size: u32,
+    /// Next instruction in memory, if the execution may continue there.
+    next: Option<u32>,
+    /// Known jump targets.
+    targets: Vec<u32>,
+    /// Whether the instruction jumps to an unknown target.
+    indirect: bool,
+}
+
+/// Check the program of `executable`, loaded in a memory of `memory_size`
+/// bytes. The arithmetic extension is enabled if the executable needs it.
+/// Only the code sections are searched for unreachable code.
+#[must_use]
+pub fn check(executable: &Executable, memory_size: usize) -> Analysis {
+    let entry_outside = executable.entry as usize >= memory_size;
+    if entry_outside || executable.fits_in(memory_size).is_err() {
+        // Nothing is checked in a memory too small for the program
+        let mut problems: Vec<Problem> = executable
+            .sections
+            .iter()
+            .filter(|section| section.end() > memory_size as u64)
+            .map(|section| Problem {
+                address: section.address,
+                kind: ProblemKind::SectionOutOfMemory { end: section.end() },
+            })
+            .collect();
+        if entry_outside {
+            problems.push(Problem {
+                address: executable.entry,
+                kind: ProblemKind::EntryOutOfMemory,
+            });
+            problems.sort_by_key(|problem| problem.address);
+        }
+        return Analysis {
+            cfg: Cfg {
+                entry: executable.entry,
+                ..Cfg::default()
+            },
+            problems,
+        };
+    }
+    let mut memory = executable.memory(memory_size).unwrap();
+    memory.resize(memory_size, 0);
+
+    // Walk the code again each time new jump targets split blocks, until
+    // every block is walked from its start with no known constant
+    let mut leaders = BTreeSet::from([executable.entry]);
+    let (nodes, mut problems) = loop {
+        let (nodes, problems, found) = walk(executable, &memory, memory_size, &leaders);
+        if found == leaders {
+            break (nodes, problems);
+        }
+        leaders = found;
+    };
+
+    for (&address, node) in &nodes {
+        for &target in &node.targets {
+            let inside = nodes
+                .range(..target)
+                .next_back()
+                .filter(|&(&start, other)| start + other.size > target);
+            if let Some((&instruction, _)) = inside {
+                problems.push(Problem {
+                    address,
+                    kind: ProblemKind::JumpIntoInstruction {
+                        target,
+                        instruction,
+                    },
+                });
+            }
+        }
+    }
+
+    let covered = covered(&memory, &nodes, &problems);
+    for section in &executable.sections {
+        if section.kind == SectionKind::Code {
+            let end = section.end() as u32;
+            unreachable(&memory, &covered, section.address..end, &mut problems);
+        }
+    }
+    problems.sort_by_key(|problem| problem.address);
+
+    Analysis {
+        cfg: Cfg {
+            entry: executable.entry,
+            blocks: blocks(&nodes, &leaders),
+        },
+        problems,
+    }
+}
+
+/// Decode the code reachable from `leaders`, starting every leader with no
+/// known constant. Return the instructions, the problems found and the
+/// leaders including the newly found ones.
+fn walk(
+    executable: &Executable,
+    memory: &[u8],
+    memory_size: usize,
+    leaders: &BTreeSet<u32>,
+) -> (BTreeMap<u32, Node>, Vec<Problem>, BTreeSet<u32>) {
+    let mut nodes = BTreeMap::new();
+    let mut problems = Vec::new();
+    let mut found = leaders.clone();
+    let mut pending: Vec<u32> = leaders.iter().copied().collect();
+    let mut walked = BTreeSet::new();
+    while let Some(leader) = pending.pop() {
+        if !walked.insert(leader) {
+            continue;
+        }
+        let mut known = [None; NREGS];
+        let mut stored = BTreeSet::new();
+        let mut address = leader;
+        loop {
+            let code = memory.get(address as usize..).unwrap_or_default();
+            let (instruction, size) =
+                match Instruction::decode_for(code, executable.arith_extension) {
+                    Ok((instruction, size)) => (instruction, size as u32),
+                    Err(e) => {
+                        let kind = match *e.kind() {
+                            ErrorKind::InvalidRegister { reg } => {
+                                ProblemKind::InvalidRegister { reg }
+                            }
+                            ErrorKind::UnknownOpcode { opcode } => {
+                                ProblemKind::UnknownOpcode { opcode }
+                            }
+                            _ => ProblemKind::TruncatedInstruction,
+                        };
+                        problems.push(Problem { address, kind });
+                        break;
+                    }
+                };
+            let mut node = flow(instruction, address + size, &mut known, &mut stored);
+            node.size = size;
+            node.targets.retain(|&target| {
+                let inside = (target as usize) < memory_size;
+                if !inside {
+                    let kind = ProblemKind::JumpOutOfMemory { target };
+                    problems.push(Problem { address, kind });
+                }
+                inside
+            });
+            let ends_block = node.indirect || !node.targets.is_empty();
+            let next = node.next;
+            for &target in node
+                .targets
+                .iter()
+                .chain(next.iter().filter(|_| ends_block))
+            {
+                found.insert(target);
+                pending.push(target);
+            }
+            nodes.insert(address, node);
+            match next {
+                Some(next) if !ends_block => {
+                    if found.contains(&next) {
+                        // Joining the block of another leader
+                        break;
+                    }
+                    address = next;
+                }
+                _ => break,
+            }
+        }
+    }
+    (nodes, problems, found)
+}
+
+/// Follow the execution of `instruction`, followed by the instruction at
+/// `next`, given the `known` constants in registers and the constants
+/// `stored` in memory by the block, which are updated.
+fn flow(
+    instruction: Instruction,
+    next: u32,
+    known: &mut [Option<u32>; NREGS],
+    stored: &mut BTreeSet<u32>,
+) -> Node {
+    let mut node = Node {
+        instruction,
+        size: 0,
+        next: Some(next),
+        targets: Vec::new(),
+        indirect: false,
+    };
+    known[0] = Some(next);
+    let reg = |known: &[Option<u32>; NREGS], r: u8| known[r as usize];
+    let (dst, value, conditional) = match instruction {
+        Instruction::MoveIf { dst, src, cond } => match reg(known, cond) {
+            Some(0) => return node,
+            Some(_) => (dst, reg(known, src), false),
+            None => (dst, reg(known, src), true),
+        },
+        Instruction::LoadImm { dst, imm } => (dst, Some(imm as u32), false),
+        Instruction::Sub { dst, lhs, rhs } => {
+            let value = reg(known, lhs)
+                .zip(reg(known, rhs))
+                .map(|(l, r)| l.wrapping_sub(r));
+            (dst, value, false)
+        }
+        Instruction::Arith { op, dst, lhs, rhs } => {
+            let value = reg(known, lhs)
+                .zip(reg(known, rhs))
+                .and_then(|(l, r)| op.apply(l, r));
+            (dst, value, false)
+        }
+        Instruction::Load { dst, .. }
+        | Instruction::LoadNarrow { dst, .. }
+        | Instruction::In { dst }
+        | Instruction::InNumber { dst } => (dst, None, false),
+        Instruction::Store { src, .. } => {
+            stored.extend(reg(known, src));
+            return node;
+        }
+        Instruction::Exit | Instruction::ExitWith { .. } => {
+            node.next = None;
+            return node;
+        }
+        Instruction::StoreNarrow { .. }
+        | Instruction::Out { .. }
+        | Instruction::OutNumber { .. } => {
+            return node;
+        }
+    };
+    if dst != 0 {
+        // A conditional move keeps a constant only if it moves the same one
+        known[dst as usize] = value.filter(|&v| !conditional || known[dst as usize] == Some(v));
+        return node;
+    }
+    match (value, conditional) {
+        // Continuing at the next instruction is no jump
+        (Some(target), true) if target == next => (),
+        (Some(target), _) => node.targets.push(target),
+        (None, _) => node.indirect = true,
+    }
+    if !conditional && !stored.contains(&next) {
+        node.next = None;
+    }
+    node
+}
+
+/// Group the instructions into basic blocks, starting at `leaders` and
+/// after jumps.
+fn blocks(nodes: &BTreeMap<u32, Node>, leaders: &BTreeSet<u32>) -> BTreeMap<u32, Block> {
+    let mut blocks: BTreeMap<u32, Block> = BTreeMap::new();
+    let mut current: Option<u32> = None;
+    for (&address, node) in nodes {
+        let continues = current
+            .and_then(|start| blocks.get(&start))
+            .is_some_and(|block| {
+                block.end == address && !block.indirect && block.successors == [address]
+            });
+        if leaders.contains(&address) || !continues {
+            current = Some(address);
+            blocks.insert(
+                address,
+                Block {
+                    start: address,
+                    end: address,
+                    instructions: Vec::new(),
+                    successors: Vec::new(),
+                    indirect: false,
+                },
+            );
+        }
+        let block = blocks.get_mut(&current.unwrap()).unwrap();
+        block.end = address + node.size;
+        block.instructions.push((address, node.instruction));
+        block.successors = node.next.iter().chain(&node.targets).copied().collect();
+        block.successors.dedup();
+        block.indirect = node.indirect;
+    }
+    blocks
+}
+
+/// Bytes of the reachable instructions, valid `nodes` or faulty ones.
+fn covered(memory: &[u8], nodes: &BTreeMap<u32, Node>, problems: &[Problem]) -> Vec<bool> {
+    let mut covered = vec![false; memory.len()];
+    let faulty = problems.iter().filter_map(|problem| {
+        let code = &memory[problem.address as usize..];
+        let size = match problem.kind {
+            ProblemKind::UnknownOpcode { .. } => {
+                Instruction::decode(code).map_or_else(|e| e.bytes().len().max(1), |(_, size)| size)
+            }
+            ProblemKind::InvalidRegister { .. } | ProblemKind::TruncatedInstruction => {
+                Instruction::decode(code).err()?.bytes().len().max(1)
+            }
+            _ => return None,
+        };
+        Some((problem.address, size as u32))
+    });
+    let valid = nodes.iter().map(|(&address, node)| (address, node.size));
+    for (address, size) in valid.chain(faulty) {
+        let instruction = address as usize..(address + size) as usize;
+        if let Some(bytes) = covered.get_mut(instruction) {
+            bytes.fill(true);
+        }
+    }
+    covered
+}
+
+/// Report the runs of valid instructions in `range` which are not
+/// `covered` by reachable instructions.
+fn unreachable(memory: &[u8], covered: &[bool], range: Range<u32>, problems: &mut Vec<Problem>) {
+    let mut address = range.start as usize;
+    while address < range.end as usize {
+        // Sweep the gap between reachable instructions starting here
+        let end = covered[address..range.end as usize]
+            .iter()
+            .position(|&c| c)
+            .map_or(range.end as usize, |len| address + len);
+        let mut run = None;
+        while address < end {
+            match Instruction::decode(&memory[address..end]) {
+                Ok((_, size)) => {
+                    run.get_or_insert(address);
+                    address += size;
+                    continue;
+                }
+                Err(_) => address += 1,
+            }
+            report_run(run.take(), address - 1, problems);
+        }
+        report_run(run, end, problems);
+        address = end + covered[end..].iter().take_while(|&&c| c).count();
+    }
+}
+
+/// Report the unreachable instructions from `start` up to `end`, if any.
+fn report_run(start: Option<usize>, end: usize, problems: &mut Vec<Problem>) {
+    if let Some(start) = start {
+        problems.push(Problem {
+            address: start as u32,
+            kind: ProblemKind::Unreachable { end: end as u32 },
+        });
+    }
+}
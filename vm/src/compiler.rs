@@ -0,0 +1,1273 @@
+//! Compiler for vmc, a small structured language, to listings in the
+//! format of the `.dis` files.
+//!
+//! ```text
+//! // Recursive factorial
+//! fn fact(n) {
+//!     if n <= 1 {
+//!         return 1;
+//!     }
+//!     return n * fact(n - 1);
+//! }
+//!
+//! fn main() {
+//!     var i = 1;
+//!     while i <= 10 {
+//!         print "fact(", i, ") = ", fact(i), "\n";
+//!         i = i + 1;
+//!     }
+//! }
+//! ```
+//!
+//! A program is a list of functions. Execution starts with `main`, which
+//! takes no parameter and whose return value is the exit status of the
+//! program. The only type is the 32-bit integer. Statements are:
+//!
+//! - `var x = e;`, declaring a variable visible in the rest of the function
+//! - `x = e;`
+//! - `if e { ... } else { ... }`, with `else if` chains, `e` being true
+//!   when not zero
+//! - `while e { ... }`
+//! - `return e;` or `return;`, which returns 0 as does the end of a function
+//! - `print item, ...;`, printing string literals and numbers
+//! - `exit e;` or `exit;`, stopping the machine with the given status
+//! - `e;`, usually a function call
+//!
+//! Operators are, by increasing precedence, `||` and `&&` (both
+//! short-circuiting), the comparisons `==` `!=` `<` `<=` `>` `>=` (giving
+//! 0 or 1), `+` `-`, `*` `/` `%` (truncating), and the unary `-` and `!`.
+//! `input()` reads a number. Comments start with `//`.
+//!
+//! Functions are named by labels of the listing, and may not take the names
+//! of the labels of the generated code: names ending with `_end`, starting
+//! with `return_from_`, or numbered labels such as `ite_then_1` or `str_2`.
+//!
+//! The generated code only uses the original instruction set, `load8`,
+//! `in_number` when reading a number, and `exit rN`. It
+//! follows the stack convention of the listings: r2 is the stack pointer,
+//! starting at the top of the memory, and a call pushes the return address
+//! before jumping to the function. Arguments are pushed from left to right
+//! before the call, and the result is returned in r4. r1 is always 0, r3
+//! is a scratch register, r4 to r14 hold the temporaries of expressions
+//! and r15 is the frame pointer, saved by the called function.
+//!
+//! Ordering comparisons look at the sign of the difference of their
+//! operands, so they are wrong when it overflows. Multiplication and
+//! division are done by functions written in vmc, found at the end of the
+//! listing when used.
+
+use crate::asm;
+use crate::MEMORY_SIZE;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Error raised while compiling a program. Lines and columns are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Compile a program into a listing, which can be given to
+/// [`asm::assemble`], for a machine with the default memory size.
+///
+/// # Errors
+/// The first syntax error, undefined name, call with a wrong number of
+/// arguments or too complex expression found in the program is returned.
+pub fn compile(source: &str) -> Result<String> {
+    compile_with_memory_size(source, MEMORY_SIZE)
+}
+
+/// Like [`compile`], for a machine with `memory_size` bytes of memory,
+/// at the top of which the stack starts.
+///
+/// # Errors
+/// See [`compile`].
+pub fn compile_with_memory_size(source: &str, memory_size: usize) -> Result<String> {
+    let program = Parser::new(source, false)?.program()?;
+    let runtime = Parser::new(RUNTIME, true)
+        .and_then(|mut parser| parser.program())
+        .expect("invalid runtime");
+    let mut functions = HashMap::new();
+    for function in &program {
+        if functions.insert(function.name.as_str(), function).is_some() {
+            return function
+                .pos
+                .error(format!("function `{}` is defined twice", function.name));
+        }
+    }
+    match functions.get("main") {
+        None => return Pos { line: 1, column: 1 }.error("no `main` function"),
+        Some(main) if !main.params.is_empty() => {
+            return main.pos.error("`main` takes no parameter");
+        }
+        Some(_) => (),
+    }
+    functions.extend(runtime.iter().map(|f| (f.name.as_str(), f)));
+
+    let mut codegen = Codegen {
+        functions,
+        lines: Vec::new(),
+        origins: Vec::new(),
+        pos: Pos { line: 1, column: 1 },
+        counters: HashMap::new(),
+        strings: Vec::new(),
+        runtime: BTreeSet::new(),
+        uses_sign: false,
+        uses_puts: false,
+    };
+    codegen.entry(memory_size);
+    for function in &program {
+        codegen.function(function)?;
+    }
+    let mut done = BTreeSet::new();
+    while let Some(name) = codegen.runtime.difference(&done).next().cloned() {
+        codegen.function(codegen.functions[name.as_str()])?;
+        done.insert(name);
+    }
+    codegen.support();
+    codegen.listing()
+}
+
+/// Functions used by the generated code, written in vmc. `__ult` compares
+/// its arguments as unsigned numbers.
+const RUNTIME: &str = r#"
+fn __mul(a, b) {
+    var r = 0;
+    var i = 0;
+    while i < 32 {
+        r = r + r;
+        if b < 0 {
+            r = r + a;
+        }
+        b = b + b;
+        i = i + 1;
+    }
+    return r;
+}
+
+fn __div(a, b) {
+    return __divmod(a, b, 0);
+}
+
+fn __mod(a, b) {
+    return __divmod(a, b, 1);
+}
+
+fn __divmod(a, b, remainder) {
+    if b == 0 {
+        print "division by zero\n";
+        exit 255;
+    }
+    var negative_q = 0;
+    var negative_r = 0;
+    if a < 0 {
+        a = -a;
+        negative_q = 1;
+        negative_r = 1;
+    }
+    if b < 0 {
+        b = -b;
+        negative_q = !negative_q;
+    }
+    var q = 0;
+    var r = 0;
+    var i = 0;
+    while i < 32 {
+        r = r + r + (a < 0);
+        a = a + a;
+        q = q + q;
+        if !__ult(r, b) {
+            r = r - b;
+            q = q + 1;
+        }
+        i = i + 1;
+    }
+    if remainder {
+        q = r;
+        negative_q = negative_r;
+    }
+    if negative_q {
+        return -q;
+    }
+    return q;
+}
+
+fn __ult(x, y) {
+    if (x < 0) == (y < 0) {
+        return x - y < 0;
+    }
+    return y < 0;
+}
+"#;
+
+const ZERO: u8 = 1;
+const SCRATCH: u8 = 3;
+const RESULT: u8 = 4;
+const LAST_TEMP: u8 = 14;
+const FP: u8 = 15;
+
+const KEYWORDS: [&str; 9] = [
+    "fn", "var", "if", "else", "while", "return", "print", "exit", "input",
+];
+
+/// Kinds of the numbered labels of the generated code, such as `ite_then_1`.
+const NUMBERED_LABELS: [&str; 9] = [
+    "str",
+    "ite_then",
+    "ite_end",
+    "while_loop",
+    "while_body",
+    "while_end",
+    "and_rhs",
+    "and_end",
+    "or_end",
+];
+
+/// Whether a function named `name` could clash with a label of the
+/// generated code: `{function}_end`, `return_from_{function}_{n}`, or a
+/// numbered label.
+fn is_generated_label(name: &str) -> bool {
+    let numbered = name.rsplit_once('_').is_some_and(|(kind, n)| {
+        NUMBERED_LABELS.contains(&kind) && !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())
+    });
+    numbered || name.ends_with("_end") || name.starts_with("return_from_")
+}
+
+/// Position of a token in the source.
+#[derive(Debug, Clone, Copy)]
+struct Pos {
+    line: usize,
+    column: usize,
+}
+
+impl Pos {
+    fn error<T>(self, message: impl Into<String>) -> Result<T> {
+        Err(Error {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Number(u32),
+    Str(Vec<u8>),
+    Punct(&'static str),
+    End,
+}
+
+const PUNCTUATION: [&str; 21] = [
+    "==", "!=", "<=", ">=", "&&", "||", "(", ")", "{", "}", ",", ";", "=", "<", ">", "+", "-", "*",
+    "/", "%", "!",
+];
+
+struct Lexer<'a> {
+    source: &'a str,
+    chars: Peekable<Chars<'a>>,
+    offset: usize,
+    pos: Pos,
+}
+
+impl<'a> Lexer<'a> {
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.column = 1;
+        } else {
+            self.pos.column += 1;
+        }
+        Some(c)
+    }
+
+    /// Skip `len` bytes of ASCII characters.
+    fn skip(&mut self, len: usize) {
+        for _ in 0..len {
+            self.bump();
+        }
+    }
+
+    fn tokens(mut self) -> Result<Vec<(Token, Pos)>> {
+        let mut tokens = Vec::new();
+        loop {
+            while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+                self.bump();
+            }
+            let pos = self.pos;
+            let rest = &self.source[self.offset..];
+            let Some(&c) = self.chars.peek() else {
+                tokens.push((Token::End, pos));
+                return Ok(tokens);
+            };
+            let token = if rest.starts_with("//") {
+                while self.chars.peek().is_some_and(|&c| c != '\n') {
+                    self.bump();
+                }
+                continue;
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                self.skip(len);
+                Token::Name(rest[..len].to_owned())
+            } else if c.is_ascii_digit() {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric())
+                    .unwrap_or(rest.len());
+                self.skip(len);
+                match rest[..len].parse() {
+                    Ok(value) => Token::Number(value),
+                    Err(_) => return pos.error(format!("invalid number `{}`", &rest[..len])),
+                }
+            } else if c == '"' {
+                self.bump();
+                Token::Str(self.string(pos)?)
+            } else if let Some(punct) = PUNCTUATION.into_iter().find(|p| rest.starts_with(p)) {
+                self.skip(punct.len());
+                Token::Punct(punct)
+            } else {
+                return pos.error(format!("unexpected character `{c}`"));
+            };
+            tokens.push((token, pos));
+        }
+    }
+
+    /// Parse the rest of a string literal, starting at `start`.
+    fn string(&mut self, start: Pos) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        loop {
+            let pos = self.pos;
+            match self.bump() {
+                None | Some('\n') => return start.error("unterminated string"),
+                Some('"') => return Ok(bytes),
+                Some('\\') => bytes.push(match self.bump() {
+                    Some('n') => b'\n',
+                    Some('r') => b'\r',
+                    Some('t') => b'\t',
+                    Some('\\') => b'\\',
+                    Some('"') => b'"',
+                    _ => return pos.error("invalid escape sequence"),
+                }),
+                Some(c) => bytes.extend(c.encode_utf8(&mut [0; 4]).bytes()),
+            }
+        }
+    }
+}
+
+struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Statement>,
+    pos: Pos,
+}
+
+enum Statement {
+    Var(String, Expr, Pos),
+    Assign(String, Expr, Pos),
+    If(Expr, Vec<Statement>, Vec<Statement>),
+    While(Expr, Vec<Statement>),
+    Return(Option<Expr>),
+    Print(Vec<Item>),
+    Exit(Option<Expr>),
+    Expr(Expr),
+}
+
+enum Item {
+    Str(Vec<u8>),
+    Expr(Expr),
+}
+
+impl Statement {
+    /// Position of the statement, or of its first expression if it does
+    /// not keep its own.
+    fn pos(&self) -> Option<Pos> {
+        match self {
+            Statement::Var(_, _, pos) | Statement::Assign(_, _, pos) => Some(*pos),
+            Statement::If(cond, ..) | Statement::While(cond, _) => Some(cond.pos),
+            Statement::Return(value) | Statement::Exit(value) => value.as_ref().map(|e| e.pos),
+            Statement::Print(items) => items.iter().find_map(|item| match item {
+                Item::Expr(value) => Some(value.pos),
+                Item::Str(_) => None,
+            }),
+            Statement::Expr(value) => Some(value.pos),
+        }
+    }
+}
+
+struct Expr {
+    kind: ExprKind,
+    pos: Pos,
+}
+
+enum ExprKind {
+    Number(u32),
+    Var(String),
+    Call(String, Vec<Expr>),
+    Input,
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+/// Binary operators by increasing precedence.
+const PRECEDENCE: [&[(&str, BinOp)]; 6] = [
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[
+        ("==", BinOp::Eq),
+        ("!=", BinOp::Ne),
+        ("<", BinOp::Lt),
+        ("<=", BinOp::Le),
+        (">", BinOp::Gt),
+        (">=", BinOp::Ge),
+    ],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Mod)],
+    &[],
+];
+
+struct Parser {
+    tokens: Vec<(Token, Pos)>,
+    index: usize,
+    /// Whether names starting with `__`, reserved to the runtime, are
+    /// allowed.
+    runtime: bool,
+}
+
+impl Parser {
+    fn new(source: &str, runtime: bool) -> Result<Self> {
+        let lexer = Lexer {
+            source,
+            chars: source.chars().peekable(),
+            offset: 0,
+            pos: Pos { line: 1, column: 1 },
+        };
+        Ok(Parser {
+            tokens: lexer.tokens()?,
+            index: 0,
+            runtime,
+        })
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.index].0
+    }
+
+    fn pos(&self) -> Pos {
+        self.tokens[self.index].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token != Token::End {
+            self.index += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T> {
+        self.pos().error(message)
+    }
+
+    /// Consume `punct` or the `punct` keyword if it comes next.
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = match self.peek() {
+            Token::Punct(p) => *p == punct,
+            Token::Name(name) => name == punct && KEYWORDS.contains(&punct),
+            _ => false,
+        };
+        if found {
+            self.index += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<()> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            self.error(format!("expected `{punct}`"))
+        }
+    }
+
+    fn name(&mut self) -> Result<String> {
+        match self.peek() {
+            Token::Name(name) if KEYWORDS.contains(&name.as_str()) => {
+                self.error(format!("`{name}` is a keyword"))
+            }
+            Token::Name(name) if name.starts_with("__") && !self.runtime => {
+                self.error(format!("`{name}`: names starting with `__` are reserved"))
+            }
+            Token::Name(_) => match self.next() {
+                Token::Name(name) => Ok(name),
+                _ => unreachable!(),
+            },
+            _ => self.error("expected a name"),
+        }
+    }
+
+    fn program(&mut self) -> Result<Vec<Function>> {
+        let mut functions = Vec::new();
+        while *self.peek() != Token::End {
+            functions.push(self.function()?);
+        }
+        Ok(functions)
+    }
+
+    fn function(&mut self) -> Result<Function> {
+        self.expect("fn")?;
+        let pos = self.pos();
+        let name = self.name()?;
+        if is_generated_label(&name) && !self.runtime {
+            return pos.error(format!(
+                "`{name}`: function names of the form of generated labels are reserved"
+            ));
+        }
+        self.expect("(")?;
+        let mut params = Vec::new();
+        if !self.eat(")") {
+            loop {
+                params.push(self.name()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        let body = self.block()?;
+        Ok(Function {
+            name,
+            params,
+            body,
+            pos,
+        })
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>> {
+        self.expect("{")?;
+        let mut statements = Vec::new();
+        while !self.eat("}") {
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Statement> {
+        let pos = self.pos();
+        let statement = if self.eat("var") {
+            let name = self.name()?;
+            self.expect("=")?;
+            Statement::Var(name, self.expr()?, pos)
+        } else if self.eat("if") {
+            return self.if_statement();
+        } else if self.eat("while") {
+            let cond = self.expr()?;
+            return Ok(Statement::While(cond, self.block()?));
+        } else if self.eat("return") {
+            Statement::Return(self.optional_expr()?)
+        } else if self.eat("exit") {
+            Statement::Exit(self.optional_expr()?)
+        } else if self.eat("print") {
+            let mut items = Vec::new();
+            loop {
+                items.push(match self.peek() {
+                    Token::Str(_) => match self.next() {
+                        Token::Str(bytes) => Item::Str(bytes),
+                        _ => unreachable!(),
+                    },
+                    _ => Item::Expr(self.expr()?),
+                });
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            Statement::Print(items)
+        } else if matches!(
+            self.tokens.get(self.index + 1),
+            Some((Token::Punct("="), _))
+        ) {
+            let name = self.name()?;
+            self.expect("=")?;
+            Statement::Assign(name, self.expr()?, pos)
+        } else {
+            Statement::Expr(self.expr()?)
+        };
+        self.expect(";")?;
+        Ok(statement)
+    }
+
+    /// Parse an `if` statement, after the `if` keyword.
+    fn if_statement(&mut self) -> Result<Statement> {
+        let cond = self.expr()?;
+        let then = self.block()?;
+        let otherwise = if !self.eat("else") {
+            Vec::new()
+        } else if self.eat("if") {
+            vec![self.if_statement()?]
+        } else {
+            self.block()?
+        };
+        Ok(Statement::If(cond, then, otherwise))
+    }
+
+    fn optional_expr(&mut self) -> Result<Option<Expr>> {
+        if *self.peek() == Token::Punct(";") {
+            Ok(None)
+        } else {
+            self.expr().map(Some)
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        self.binary(0)
+    }
+
+    /// Parse an expression made of operators of at least the precedence
+    /// `level`. Operators of the same precedence associate to the left.
+    fn binary(&mut self, level: usize) -> Result<Expr> {
+        if level == PRECEDENCE.len() - 1 {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            let pos = self.pos();
+            let Some(&(_, op)) = PRECEDENCE[level]
+                .iter()
+                .find(|(punct, _)| *self.peek() == Token::Punct(punct))
+            else {
+                return Ok(lhs);
+            };
+            self.next();
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr {
+                kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+                pos,
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let pos = self.pos();
+        let kind = if self.eat("-") {
+            match self.unary()? {
+                Expr {
+                    kind: ExprKind::Number(value),
+                    ..
+                } => ExprKind::Number(value.wrapping_neg()),
+                operand => ExprKind::Neg(Box::new(operand)),
+            }
+        } else if self.eat("!") {
+            ExprKind::Not(Box::new(self.unary()?))
+        } else if self.eat("(") {
+            let expr = self.expr()?;
+            self.expect(")")?;
+            return Ok(expr);
+        } else if self.eat("input") {
+            self.expect("(")?;
+            self.expect(")")?;
+            ExprKind::Input
+        } else if let Token::Number(value) = *self.peek() {
+            self.next();
+            ExprKind::Number(value)
+        } else {
+            let name = self.name()?;
+            if self.eat("(") {
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                ExprKind::Call(name, args)
+            } else {
+                ExprKind::Var(name)
+            }
+        };
+        Ok(Expr { kind, pos })
+    }
+}
+
+enum Line {
+    Label(String),
+    Code(String),
+    Data(String),
+}
+
+/// Variables of the function being compiled, by offset from the frame
+/// pointer.
+struct Frame<'a> {
+    function: &'a Function,
+    vars: HashMap<&'a str, i32>,
+    locals: i32,
+}
+
+impl<'a> Frame<'a> {
+    /// Parameters are above the saved frame pointer and the return
+    /// address, the last one first. Local variables are below.
+    fn new(function: &'a Function) -> Result<Self> {
+        let mut vars = HashMap::new();
+        let count = function.params.len() as i32;
+        for (index, param) in function.params.iter().enumerate() {
+            if vars
+                .insert(param.as_str(), 8 + 4 * (count - 1 - index as i32))
+                .is_some()
+            {
+                return function
+                    .pos
+                    .error(format!("parameter `{param}` is given twice"));
+            }
+        }
+        Ok(Frame {
+            function,
+            vars,
+            locals: 0,
+        })
+    }
+
+    fn declare(&mut self, name: &'a str, pos: Pos) -> Result<i32> {
+        self.locals += 1;
+        let offset = -4 * self.locals;
+        if self.vars.insert(name, offset).is_some() {
+            return pos.error(format!("variable `{name}` is already declared"));
+        }
+        Ok(offset)
+    }
+
+    fn lookup(&self, name: &str, pos: Pos) -> Result<i32> {
+        match self.vars.get(name) {
+            Some(&offset) => Ok(offset),
+            None => pos.error(format!("undefined variable `{name}`")),
+        }
+    }
+}
+
+/// Number of variables declared in `statements`.
+fn count_vars(statements: &[Statement]) -> usize {
+    statements
+        .iter()
+        .map(|statement| match statement {
+            Statement::Var(..) => 1,
+            Statement::If(_, then, otherwise) => count_vars(then) + count_vars(otherwise),
+            Statement::While(_, body) => count_vars(body),
+            _ => 0,
+        })
+        .sum()
+}
+
+struct Codegen<'a> {
+    functions: HashMap<&'a str, &'a Function>,
+    lines: Vec<Line>,
+    /// Position in the source of the code generating each line.
+    origins: Vec<Pos>,
+    /// Position of the code being generated.
+    pos: Pos,
+    /// Last number used by each kind of generated label.
+    counters: HashMap<String, usize>,
+    strings: Vec<Vec<u8>>,
+    /// Runtime functions called so far.
+    runtime: BTreeSet<String>,
+    uses_sign: bool,
+    uses_puts: bool,
+}
+
+impl<'a> Codegen<'a> {
+    fn line(&mut self, line: Line) {
+        self.lines.push(line);
+        self.origins.push(self.pos);
+    }
+
+    fn label(&mut self, label: impl Into<String>) {
+        self.line(Line::Label(label.into()));
+    }
+
+    fn code(&mut self, code: impl Into<String>) {
+        self.line(Line::Code(code.into()));
+    }
+
+    fn data(&mut self, data: impl Into<String>) {
+        self.line(Line::Data(data.into()));
+    }
+
+    /// Number a new label of the given kind, starting from 1.
+    fn number(&mut self, kind: &str) -> usize {
+        let counter = self.counters.entry(kind.to_owned()).or_default();
+        *counter += 1;
+        *counter
+    }
+
+    fn push(&mut self, reg: u8) {
+        self.code(format!("loadimm r{SCRATCH} <- #4"));
+        self.code(format!("sub r2 <- r2 - r{SCRATCH}"));
+        self.code(format!("store [r2] <- r{reg}"));
+    }
+
+    fn pop(&mut self, reg: u8) {
+        self.code(format!("load r{reg} <- [r2]"));
+        self.code(format!("loadimm r{SCRATCH} <- #-4"));
+        self.code(format!("sub r2 <- r2 - r{SCRATCH}"));
+    }
+
+    /// Push the return address and jump to `target`.
+    fn call_label(&mut self, target: &str) {
+        let ret = format!(
+            "return_from_{target}_{}",
+            self.number(&format!("call {target}"))
+        );
+        self.code(format!("loadimm r{SCRATCH} <- #4"));
+        self.code(format!("sub r2 <- r2 - r{SCRATCH}"));
+        self.code(format!("loadimm r{SCRATCH} <- #{ret}"));
+        self.code(format!("store [r2] <- r{SCRATCH}"));
+        self.code(format!("loadimm r0 <- #{target}"));
+        self.label(ret);
+    }
+
+    /// Pop the return address and jump to it.
+    fn ret(&mut self) {
+        self.code(format!("loadimm r{SCRATCH} <- #-4"));
+        self.code(format!("sub r2 <- r2 - r{SCRATCH}"));
+        self.code(format!("loadimm r{SCRATCH} <- #4"));
+        self.code(format!("sub r{SCRATCH} <- r2 - r{SCRATCH}"));
+        self.code(format!("load r0 <- [r{SCRATCH}]"));
+    }
+
+    fn entry(&mut self, memory_size: usize) {
+        // With 4 GiB of memory, the stack pointer wraps around from 0
+        self.constant(2, memory_size as u32);
+        self.call_label("main");
+        self.code(format!("exit r{RESULT}"));
+    }
+
+    fn function(&mut self, function: &'a Function) -> Result<()> {
+        let mut frame = Frame::new(function)?;
+        self.pos = function.pos;
+        self.label(function.name.clone());
+        self.push(FP);
+        self.code(format!("move r{FP} <- r2 if r0 != 0"));
+        let locals = count_vars(&function.body);
+        if locals > 0 {
+            self.code(format!("loadimm r{SCRATCH} <- #{}", 4 * locals));
+            self.code(format!("sub r2 <- r2 - r{SCRATCH}"));
+        }
+        for statement in &function.body {
+            self.statement(&mut frame, statement)?;
+        }
+        self.code(format!("loadimm r{RESULT} <- #0"));
+        self.label(format!("{}_end", function.name));
+        self.code(format!("move r2 <- r{FP} if r0 != 0"));
+        self.pop(FP);
+        self.ret();
+        Ok(())
+    }
+
+    fn statement(&mut self, frame: &mut Frame<'a>, statement: &'a Statement) -> Result<()> {
+        if let Some(pos) = statement.pos() {
+            self.pos = pos;
+        }
+        match statement {
+            Statement::Var(name, value, pos) => {
+                self.expr(frame, value, RESULT)?;
+                let offset = frame.declare(name, *pos)?;
+                self.store_var(offset, RESULT);
+            }
+            Statement::Assign(name, value, pos) => {
+                let offset = frame.lookup(name, *pos)?;
+                self.expr(frame, value, RESULT)?;
+                self.store_var(offset, RESULT);
+            }
+            Statement::If(cond, then, otherwise) => {
+                let n = self.number("ite");
+                self.expr(frame, cond, RESULT)?;
+                self.code(format!("loadimm r{SCRATCH} <- #ite_then_{n}"));
+                self.code(format!("move r0 <- r{SCRATCH} if r{RESULT} != 0"));
+                for statement in otherwise {
+                    self.statement(frame, statement)?;
+                }
+                self.code(format!("loadimm r0 <- #ite_end_{n}"));
+                self.label(format!("ite_then_{n}"));
+                for statement in then {
+                    self.statement(frame, statement)?;
+                }
+                self.label(format!("ite_end_{n}"));
+            }
+            Statement::While(cond, body) => {
+                let n = self.number("while");
+                self.label(format!("while_loop_{n}"));
+                self.expr(frame, cond, RESULT)?;
+                self.code(format!("loadimm r{SCRATCH} <- #while_body_{n}"));
+                self.code(format!("move r0 <- r{SCRATCH} if r{RESULT} != 0"));
+                self.code(format!("loadimm r0 <- #while_end_{n}"));
+                self.label(format!("while_body_{n}"));
+                for statement in body {
+                    self.statement(frame, statement)?;
+                }
+                self.code(format!("loadimm r0 <- #while_loop_{n}"));
+                self.label(format!("while_end_{n}"));
+            }
+            Statement::Return(value) => {
+                match value {
+                    Some(value) => self.expr(frame, value, RESULT)?,
+                    None => self.code(format!("loadimm r{RESULT} <- #0")),
+                }
+                self.code(format!("loadimm r0 <- #{}_end", frame.function.name));
+            }
+            Statement::Print(items) => {
+                for item in items {
+                    match item {
+                        Item::Str(bytes) if bytes.len() == 1 => {
+                            self.code(format!("loadimm r{RESULT} <- #{}", bytes[0]));
+                            self.code(format!("out r{RESULT}"));
+                        }
+                        Item::Str(bytes) => {
+                            self.strings.push(bytes.clone());
+                            let label = format!("str_{}", self.strings.len());
+                            self.code(format!("loadimm r{RESULT} <- #{label}"));
+                            self.call_label("__puts");
+                            self.uses_puts = true;
+                        }
+                        Item::Expr(value) => {
+                            self.expr(frame, value, RESULT)?;
+                            self.code(format!("out_number r{RESULT}"));
+                        }
+                    }
+                }
+            }
+            Statement::Exit(None) => self.code("exit"),
+            Statement::Exit(Some(status)) => {
+                self.expr(frame, status, RESULT)?;
+                self.code(format!("exit r{RESULT}"));
+            }
+            Statement::Expr(value) => self.expr(frame, value, RESULT)?,
+        }
+        Ok(())
+    }
+
+    /// Compute the address of the variable at `offset` in the scratch
+    /// register.
+    fn var_address(&mut self, offset: i32) {
+        self.code(format!("loadimm r{SCRATCH} <- #{}", -offset));
+        self.code(format!("sub r{SCRATCH} <- r{FP} - r{SCRATCH}"));
+    }
+
+    fn store_var(&mut self, offset: i32, reg: u8) {
+        self.var_address(offset);
+        self.code(format!("store [r{SCRATCH}] <- r{reg}"));
+    }
+
+    /// Generate the code computing `expr` into `reg`, which may use the
+    /// registers from `reg` to [`LAST_TEMP`].
+    fn expr(&mut self, frame: &Frame<'a>, expr: &'a Expr, reg: u8) -> Result<()> {
+        if reg > LAST_TEMP {
+            return expr.pos.error("expression too complex");
+        }
+        // The code of the expression comes from it, once its operands are
+        // computed as well
+        let outer = std::mem::replace(&mut self.pos, expr.pos);
+        match &expr.kind {
+            &ExprKind::Number(value) => self.constant(reg, value),
+            ExprKind::Var(name) => {
+                let offset = frame.lookup(name, expr.pos)?;
+                self.var_address(offset);
+                self.code(format!("load r{reg} <- [r{SCRATCH}]"));
+            }
+            ExprKind::Call(name, args) => {
+                let args: Vec<_> = args.iter().collect();
+                self.call(frame, name, &args, expr.pos, reg)?;
+            }
+            ExprKind::Input => self.code(format!("in_number r{reg}")),
+            ExprKind::Neg(operand) => {
+                self.expr(frame, operand, reg)?;
+                self.code(format!("sub r{reg} <- r{ZERO} - r{reg}"));
+            }
+            ExprKind::Not(operand) => {
+                self.expr(frame, operand, reg)?;
+                self.code(format!("loadimm r{SCRATCH} <- #1"));
+                self.code(format!("move r{SCRATCH} <- r{ZERO} if r{reg} != 0"));
+                self.code(format!("move r{reg} <- r{SCRATCH} if r0 != 0"));
+            }
+            ExprKind::Binary(op, lhs, rhs) => self.binary(frame, *op, lhs, rhs, expr.pos, reg)?,
+        }
+        self.pos = outer;
+        Ok(())
+    }
+
+    /// Load `value` into `reg`. Values not fitting in an immediate are
+    /// built from their upper half, doubled 16 times.
+    fn constant(&mut self, reg: u8, value: u32) {
+        if let Ok(imm) = i16::try_from(value as i32) {
+            self.code(format!("loadimm r{reg} <- #{imm}"));
+            return;
+        }
+        let low = value as i16;
+        let high = ((value as i32).wrapping_sub(low.into()) >> 16) as i16;
+        self.code(format!("loadimm r{reg} <- #{high}"));
+        for _ in 0..16 {
+            self.code(format!("sub r{SCRATCH} <- r{ZERO} - r{reg}"));
+            self.code(format!("sub r{reg} <- r{reg} - r{SCRATCH}"));
+        }
+        self.code(format!("loadimm r{SCRATCH} <- #{low}"));
+        self.code(format!("sub r{SCRATCH} <- r{ZERO} - r{SCRATCH}"));
+        self.code(format!("sub r{reg} <- r{reg} - r{SCRATCH}"));
+    }
+
+    /// Call `name`, saving the temporaries below `reg` around the call.
+    fn call(
+        &mut self,
+        frame: &Frame<'a>,
+        name: &str,
+        args: &[&'a Expr],
+        pos: Pos,
+        reg: u8,
+    ) -> Result<()> {
+        let Some(function) = self.functions.get(name) else {
+            return pos.error(format!("undefined function `{name}`"));
+        };
+        if function.params.len() != args.len() {
+            return pos.error(format!(
+                "`{name}` takes {} arguments but {} are given",
+                function.params.len(),
+                args.len()
+            ));
+        }
+        if name.starts_with("__") {
+            self.runtime.insert(name.to_owned());
+        }
+        for saved in RESULT..reg {
+            self.push(saved);
+        }
+        for arg in args {
+            self.expr(frame, arg, RESULT)?;
+            self.push(RESULT);
+        }
+        self.call_label(name);
+        if !args.is_empty() {
+            self.code(format!("loadimm r{SCRATCH} <- #{}", -4 * args.len() as i32));
+            self.code(format!("sub r2 <- r2 - r{SCRATCH}"));
+        }
+        if reg != RESULT {
+            self.code(format!("move r{reg} <- r{RESULT} if r0 != 0"));
+        }
+        for saved in (RESULT..reg).rev() {
+            self.pop(saved);
+        }
+        Ok(())
+    }
+
+    fn binary(
+        &mut self,
+        frame: &Frame<'a>,
+        op: BinOp,
+        lhs: &'a Expr,
+        rhs: &'a Expr,
+        pos: Pos,
+        reg: u8,
+    ) -> Result<()> {
+        let function = match op {
+            BinOp::Mul => "__mul",
+            BinOp::Div => "__div",
+            BinOp::Mod => "__mod",
+            BinOp::And | BinOp::Or => return self.logical(frame, op, lhs, rhs, reg),
+            _ => "",
+        };
+        if !function.is_empty() {
+            return self.call(frame, function, &[lhs, rhs], pos, reg);
+        }
+        self.expr(frame, lhs, reg)?;
+        self.expr(frame, rhs, reg + 1)?;
+        let other = reg + 1;
+        match op {
+            BinOp::Add => {
+                self.code(format!("sub r{SCRATCH} <- r{ZERO} - r{other}"));
+                self.code(format!("sub r{reg} <- r{reg} - r{SCRATCH}"));
+            }
+            BinOp::Sub => self.code(format!("sub r{reg} <- r{reg} - r{other}")),
+            BinOp::Eq => {
+                self.code(format!("sub r{SCRATCH} <- r{reg} - r{other}"));
+                self.code(format!("loadimm r{reg} <- #1"));
+                self.code(format!("move r{reg} <- r{ZERO} if r{SCRATCH} != 0"));
+            }
+            BinOp::Ne => {
+                self.code(format!("sub r{reg} <- r{reg} - r{other}"));
+                self.code(format!("loadimm r{other} <- #1"));
+                self.code(format!("move r{reg} <- r{other} if r{reg} != 0"));
+            }
+            BinOp::Lt | BinOp::Ge => {
+                self.code(format!("sub r{reg} <- r{reg} - r{other}"));
+                self.sign(reg, other);
+            }
+            BinOp::Gt | BinOp::Le => {
+                self.code(format!("sub r{reg} <- r{other} - r{reg}"));
+                self.sign(reg, other);
+            }
+            _ => unreachable!(),
+        }
+        if matches!(op, BinOp::Ge | BinOp::Le) {
+            self.code(format!("loadimm r{SCRATCH} <- #1"));
+            self.code(format!("sub r{reg} <- r{SCRATCH} - r{reg}"));
+        }
+        Ok(())
+    }
+
+    /// Generate a short-circuiting `&&` or `||`, giving 0 or 1.
+    fn logical(
+        &mut self,
+        frame: &Frame<'a>,
+        op: BinOp,
+        lhs: &'a Expr,
+        rhs: &'a Expr,
+        reg: u8,
+    ) -> Result<()> {
+        let kind = if op == BinOp::And { "and" } else { "or" };
+        let n = self.number(kind);
+        self.expr(frame, lhs, reg)?;
+        if op == BinOp::And {
+            self.code(format!("loadimm r{SCRATCH} <- #and_rhs_{n}"));
+            self.code(format!("move r0 <- r{SCRATCH} if r{reg} != 0"));
+            self.code(format!("loadimm r0 <- #and_end_{n}"));
+            self.label(format!("and_rhs_{n}"));
+        } else {
+            self.code(format!("loadimm r{SCRATCH} <- #1"));
+            self.code(format!("move r{reg} <- r{SCRATCH} if r{reg} != 0"));
+            self.code(format!("loadimm r{SCRATCH} <- #or_end_{n}"));
+            self.code(format!("move r0 <- r{SCRATCH} if r{reg} != 0"));
+        }
+        self.expr(frame, rhs, reg)?;
+        self.code(format!("loadimm r{SCRATCH} <- #1"));
+        self.code(format!("move r{reg} <- r{SCRATCH} if r{reg} != 0"));
+        self.label(format!("{kind}_end_{n}"));
+        Ok(())
+    }
+
+    /// Replace the value of `reg` by its sign bit, using `other` as well.
+    /// The value is spilled to memory, and 128 is added to its top byte:
+    /// the carry out of that byte is the sign bit.
+    fn sign(&mut self, reg: u8, other: u8) {
+        self.uses_sign = true;
+        self.code(format!("loadimm r{SCRATCH} <- #__word"));
+        self.code(format!("store [r{SCRATCH}] <- r{reg}"));
+        self.code(format!("loadimm r{SCRATCH} <- #__word_3"));
+        self.code(format!("load8 r{reg} <- [r{SCRATCH}]"));
+        self.code(format!("loadimm r{other} <- #-128"));
+        self.code(format!("sub r{reg} <- r{reg} - r{other}"));
+        self.code(format!("loadimm r{SCRATCH} <- #__word"));
+        self.code(format!("store [r{SCRATCH}] <- r{reg}"));
+        self.code(format!("loadimm r{SCRATCH} <- #__word_1"));
+        self.code(format!("load8 r{reg} <- [r{SCRATCH}]"));
+    }
+
+    /// Generate the routines and data used by the code.
+    fn support(&mut self) {
+        if self.uses_puts {
+            // Print the NUL-terminated string at the address in r4
+            self.label("__puts");
+            self.code(format!("load8 r{SCRATCH} <- [r{RESULT}]"));
+            self.code("loadimm r5 <- #__puts_char");
+            self.code(format!("move r0 <- r5 if r{SCRATCH} != 0"));
+            self.ret();
+            self.label("__puts_char");
+            self.code(format!("out r{SCRATCH}"));
+            self.code(format!("loadimm r{SCRATCH} <- #-1"));
+            self.code(format!("sub r{RESULT} <- r{RESULT} - r{SCRATCH}"));
+            self.code("loadimm r0 <- #__puts");
+        }
+        if self.uses_sign {
+            self.label("__word");
+            self.data("[0]");
+            self.label("__word_1");
+            self.data("[0, 0]");
+            self.label("__word_3");
+            self.data("[0]");
+        }
+        for (index, bytes) in std::mem::take(&mut self.strings).into_iter().enumerate() {
+            self.label(format!("str_{}", index + 1));
+            self.data(byte_string(&bytes));
+        }
+    }
+
+    /// Render the listing, with the address column of each instruction.
+    fn listing(&self) -> Result<String> {
+        let render = |line: &Line| match line {
+            Line::Label(label) => format!("{label}:"),
+            Line::Code(code) | Line::Data(code) => format!("  {code}"),
+        };
+        let source: String = self.lines.iter().map(|l| render(l) + "\n").collect();
+        let program = asm::assemble(&source).map_err(|e| {
+            let origin = e.line.checked_sub(1).and_then(|i| self.origins.get(i));
+            let pos = origin.copied().unwrap_or(Pos { line: 1, column: 1 });
+            Error {
+                line: pos.line,
+                column: pos.column,
+                message: format!("invalid generated code: {}", e.message),
+            }
+        })?;
+        let mut listing = String::new();
+        for (index, line) in self.lines.iter().enumerate() {
+            match line {
+                Line::Label(_) => listing += &render(line),
+                Line::Code(code) => {
+                    listing += &format!("  {:04}   {code}", program.lines[&(index + 1)]);
+                }
+                Line::Data(data) => listing += &format!("  ???? {data}"),
+            }
+            listing.push('\n');
+        }
+        Ok(listing)
+    }
+}
+
+/// Format `bytes` as a NUL-terminated byte string of a listing.
+fn byte_string(bytes: &[u8]) -> String {
+    let mut s = String::from("b'");
+    for &b in bytes.iter().chain(&[0]) {
+        match b {
+            b'\n' => s += "\\n",
+            b'\r' => s += "\\r",
+            b'\t' => s += "\\t",
+            b'\\' => s += "\\\\",
+            b'\'' => s += "\\'",
+            0 => s += "\\0",
+            b' '..=b'~' => s.push(b as char),
+            _ => s += &format!("\\x{b:02x}"),
+        }
+    }
+    s.push('\'');
+    s
+}
@@ -0,0 +1,158 @@
+//! Code coverage of programs, mapped onto their `.dis` listings.
+//!
+//! A [`Coverage`] given to [`Machine::set_coverage`](crate::Machine::set_coverage)
+//! records the address of every executed instruction. The same collector
+//! can be handed from machine to machine, or several collectors merged, to
+//! accumulate the coverage of several runs. A [`Report`] then maps it onto
+//! the listing of the program:
+//!
+//! ```text
+//!         3:   0087   loadimm r8 <- #1
+//!         3:   0091   sub r8 <- r10 - r8
+//!         -: ite_then_1:
+//!     #####:   0111   loadimm r3 <- #4
+//! ```
+
+use crate::asm::{self, Program};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// Execution count of instruction addresses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    counts: BTreeMap<u32, u64>,
+}
+
+impl Coverage {
+    #[must_use]
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    /// Record an execution of the instruction at `addr`.
+    pub fn record(&mut self, addr: u32) {
+        *self.counts.entry(addr).or_default() += 1;
+    }
+
+    /// Add the executions recorded by `other`.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (&addr, &count) in &other.counts {
+            *self.counts.entry(addr).or_default() += count;
+        }
+    }
+
+    /// Number of executions of the instruction at `addr`.
+    #[must_use]
+    pub fn count(&self, addr: u32) -> u64 {
+        self.counts.get(&addr).copied().unwrap_or(0)
+    }
+
+    /// Executed addresses with their execution count, in address order.
+    pub fn counts(&self) -> impl Iterator<Item = (u32, u64)> + '_ {
+        self.counts.iter().map(|(&addr, &count)| (addr, count))
+    }
+}
+
+/// Coverage of a listing.
+pub struct Report<'a> {
+    source: &'a str,
+    program: Program,
+    coverage: &'a Coverage,
+}
+
+impl<'a> Report<'a> {
+    /// Map `coverage` onto the listing `source`.
+    ///
+    /// # Errors
+    /// Errors assembling the listing are returned.
+    pub fn new(source: &'a str, coverage: &'a Coverage) -> Result<Self, asm::Error> {
+        Ok(Report {
+            source,
+            program: asm::assemble(source)?,
+            coverage,
+        })
+    }
+
+    /// Execution count of every instruction line, by line number.
+    pub fn line_counts(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.program
+            .lines
+            .iter()
+            .map(|(&line, &addr)| (line, self.coverage.count(addr)))
+    }
+
+    /// Number of instruction lines, and of those that were executed.
+    #[must_use]
+    pub fn lines(&self) -> (usize, usize) {
+        let hit = self.line_counts().filter(|&(_, count)| count > 0).count();
+        (self.program.lines.len(), hit)
+    }
+
+    /// Number of labels of instructions, and of those that were reached.
+    #[must_use]
+    pub fn labels(&self) -> (usize, usize) {
+        let instructions: BTreeSet<u32> = self.program.lines.values().copied().collect();
+        let code: Vec<u32> = self
+            .program
+            .labels
+            .values()
+            .copied()
+            .filter(|addr| instructions.contains(addr))
+            .collect();
+        let hit = code
+            .iter()
+            .filter(|&&addr| self.coverage.count(addr) > 0)
+            .count();
+        (code.len(), hit)
+    }
+
+    /// The listing with each line prefixed by its execution count, `#####`
+    /// for instructions never executed, or `-` for lines without an
+    /// instruction.
+    #[must_use]
+    pub fn annotated(&self) -> String {
+        let mut annotated = String::new();
+        for (index, text) in self.source.lines().enumerate() {
+            let prefix = match self.program.lines.get(&(index + 1)) {
+                Some(&addr) => match self.coverage.count(addr) {
+                    0 => "#####".to_owned(),
+                    count => count.to_string(),
+                },
+                None => "-".to_owned(),
+            };
+            writeln!(annotated, "{prefix:>9}: {text}").unwrap();
+        }
+        annotated
+    }
+
+    /// Percentages of instructions executed and of labels reached.
+    #[must_use]
+    pub fn summary(&self) -> String {
+        let percent = |(found, hit): (usize, usize)| {
+            let percent = if found == 0 {
+                100.0
+            } else {
+                100.0 * hit as f64 / found as f64
+            };
+            format!("{percent:.2}% of {found}")
+        };
+        format!(
+            "Instructions executed: {}\nLabels reached: {}\n",
+            percent(self.lines()),
+            percent(self.labels())
+        )
+    }
+
+    /// The coverage in the line-oriented LCOV tracefile format, `path`
+    /// being the name of the listing.
+    #[must_use]
+    pub fn lcov(&self, path: &str) -> String {
+        let mut lcov = format!("TN:\nSF:{path}\n");
+        for (line, count) in self.line_counts() {
+            writeln!(lcov, "DA:{line},{count}").unwrap();
+        }
+        let (found, hit) = self.lines();
+        writeln!(lcov, "LF:{found}\nLH:{hit}\nend_of_record").unwrap();
+        lcov
+    }
+}
@@ -0,0 +1,520 @@
+//! Interactive debugger driving a [`Machine`].
+//!
+//! Commands are read one per line, either from a terminal or from a
+//! command file, which makes sessions replayable:
+//!
+//! ```text
+//! break rfact
+//! continue
+//! regs
+//! mem r2 8
+//! set r10 3
+//! next
+//! ```
+//!
+//! `back` and `rewind` need the undo journal of the machine to be enabled
+//! with [`Machine::set_journal_capacity`].
+//!
+//! Locations are given as a decimal or `0x` hexadecimal address, a label,
+//! or a register (`r2`) whose value is used as the address.
+
+use crate::machine::NREGS;
+use crate::symbols::SymbolTable;
+use crate::{Instruction, Machine};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, Write};
+
+const PROMPT: &str = "(vmdb) ";
+
+const HELP: &str = "\
+break <loc>          set a breakpoint (alias: b)
+delete [<loc>]       delete a breakpoint, or all of them (alias: d)
+breakpoints          list breakpoints
+step [<n>]           execute n instructions, 1 by default (alias: s)
+next                 execute one instruction, stepping over calls (alias: n)
+continue             run until a breakpoint or the end (alias: c)
+back [<n>]           undo n instructions, 1 by default
+rewind <loc>         undo instructions until the one at a location
+regs                 show registers (alias: r)
+mem <loc> [<len>]    show memory, 16 bytes by default (alias: x)
+set r<i> <value>     set a register
+set [<loc>] <value>  set the memory word at a location
+set byte [<loc>] <value>
+                     set the memory byte at a location
+list [<loc>]         disassemble around the IP or a location (alias: l)
+help                 show this help
+quit                 end the session (alias: q)";
+
+/// Number of instructions shown before and after the IP by `list`.
+const LIST_CONTEXT: usize = 4;
+
+/// Why execution stopped.
+enum Stop {
+    Breakpoint,
+    Exited,
+    Done,
+}
+
+/// Debugging session over a machine.
+pub struct Debugger {
+    machine: Machine,
+    labels: BTreeMap<String, u32>,
+    symbols: SymbolTable,
+    breakpoints: BTreeSet<u32>,
+    /// Addresses of the instructions in the initial program.
+    instructions: Vec<u32>,
+    exited: bool,
+}
+
+impl Debugger {
+    /// Create a debugger for `machine`, where the first `program_len` bytes
+    /// of memory hold the program. `labels` are used to name addresses.
+    #[must_use]
+    pub fn new(machine: Machine, program_len: usize, labels: BTreeMap<String, u32>) -> Self {
+        let symbols = SymbolTable::new(&labels);
+        let program = &machine.memory()[..program_len.min(machine.memory().len())];
+        let mut instructions = Vec::new();
+        let mut addr = 0;
+        while addr < program.len() {
+            match Instruction::decode(&program[addr..]) {
+                Ok((_, size)) => {
+                    instructions.push(addr as u32);
+                    addr += size;
+                }
+                Err(_) => addr += 1,
+            }
+        }
+        Debugger {
+            machine,
+            labels,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            instructions,
+            exited: false,
+        }
+    }
+
+    /// The machine being debugged.
+    #[must_use]
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Read and execute commands from `input` until `quit` or the end of
+    /// the input. The prompt is written to `out` before each command, and
+    /// when `echo` is set the command itself is written as well, so that
+    /// scripted sessions read like interactive ones.
+    ///
+    /// # Errors
+    /// Errors reading commands or writing to `out` are returned.
+    pub fn run<R: BufRead, W: Write>(
+        &mut self,
+        input: R,
+        out: &mut W,
+        echo: bool,
+    ) -> io::Result<()> {
+        write!(out, "{PROMPT}")?;
+        out.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            if echo {
+                writeln!(out, "{line}")?;
+            }
+            if !self.execute(&line, out)? {
+                return Ok(());
+            }
+            write!(out, "{PROMPT}")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+
+    /// Execute a single command, writing its result and the program output
+    /// to `out`. Return `false` if the session should end.
+    ///
+    /// # Errors
+    /// Errors writing to `out` are returned.
+    pub fn execute<W: Write>(&mut self, command: &str, out: &mut W) -> io::Result<bool> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first() else {
+            return Ok(true);
+        };
+        let result = match name {
+            "break" | "b" => self.set_breakpoint(args, out),
+            "delete" | "d" => self.delete_breakpoint(args, out),
+            "breakpoints" => self.list_breakpoints(out),
+            "step" | "s" => self.step(args, out),
+            "next" | "n" => self.next(out),
+            "continue" | "c" => self.resume(out, |_| false),
+            "back" => self.back(args, out),
+            "rewind" => self.rewind(args, out),
+            "regs" | "r" => self.show_regs(out),
+            "mem" | "x" => self.show_memory(args, out),
+            "set" => self.set(args, out),
+            "list" | "l" => self.list(args, out),
+            "help" | "h" => writeln!(out, "{HELP}").map_err(Into::into),
+            "quit" | "q" => return Ok(false),
+            _ => Err(Failure::Usage(format!(
+                "unknown command `{name}`, try `help`"
+            ))),
+        };
+        match result {
+            Ok(()) => Ok(true),
+            Err(Failure::Usage(message)) => {
+                writeln!(out, "{message}")?;
+                Ok(true)
+            }
+            Err(Failure::Io(e)) => Err(e),
+        }
+    }
+
+    fn set_breakpoint<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<()> {
+        let [location] = args else {
+            return usage("break <loc>");
+        };
+        let addr = self.location(location)?;
+        self.breakpoints.insert(addr);
+        writeln!(out, "Breakpoint at {}", self.name(addr))?;
+        Ok(())
+    }
+
+    fn delete_breakpoint<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<()> {
+        match args {
+            [] => self.breakpoints.clear(),
+            [location] => {
+                let addr = self.location(location)?;
+                if !self.breakpoints.remove(&addr) {
+                    writeln!(out, "No breakpoint at {}", self.name(addr))?;
+                }
+            }
+            _ => return usage("delete [<loc>]"),
+        }
+        Ok(())
+    }
+
+    fn list_breakpoints<W: Write>(&self, out: &mut W) -> Result<()> {
+        if self.breakpoints.is_empty() {
+            writeln!(out, "No breakpoints")?;
+        }
+        for &addr in &self.breakpoints {
+            writeln!(out, "Breakpoint at {}", self.name(addr))?;
+        }
+        Ok(())
+    }
+
+    fn step<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<()> {
+        let count = match args {
+            [] => 1,
+            [count] => match count.parse() {
+                Ok(count) if count > 0 => count,
+                _ => return Err(Failure::Usage(format!("invalid count `{count}`"))),
+            },
+            _ => return usage("step [<n>]"),
+        };
+        let mut remaining: u64 = count;
+        self.resume(out, |_| {
+            remaining -= 1;
+            remaining == 0
+        })
+    }
+
+    /// Execute one instruction, unless it is a call: in that case, run
+    /// until the call returns. A call is a jump done while the word on top
+    /// of the stack (pointed to by r2) is the address following the jump.
+    fn next<W: Write>(&mut self, out: &mut W) -> Result<()> {
+        let ip = self.machine.regs()[0];
+        let sp = self.machine.regs()[2];
+        let ret = match self.decode(ip) {
+            Some((
+                Instruction::LoadImm { dst: 0, .. } | Instruction::MoveIf { dst: 0, .. },
+                size,
+            )) => ip + size as u32,
+            _ => return self.step(&[], out),
+        };
+        if self.word(sp) != Some(ret) {
+            return self.step(&[], out);
+        }
+        self.resume(out, |machine| {
+            machine.regs()[0] == ret && machine.regs()[2] > sp
+        })
+    }
+
+    /// Execute instructions until `done` returns true after a step, a
+    /// breakpoint is reached, the program exits or an error happens.
+    fn resume<W: Write>(
+        &mut self,
+        out: &mut W,
+        mut done: impl FnMut(&Machine) -> bool,
+    ) -> Result<()> {
+        if self.exited {
+            writeln!(out, "The program has exited")?;
+            return Ok(());
+        }
+        let stop = loop {
+            match self.machine.step_on(out) {
+                Ok(true) => break Stop::Exited,
+                Ok(false) => (),
+                Err(e) => {
+                    writeln!(out, "Error: {}", e.symbolize(&self.symbols))?;
+                    break Stop::Done;
+                }
+            }
+            if done(&self.machine) {
+                break Stop::Done;
+            }
+            if self.breakpoints.contains(&self.machine.regs()[0]) {
+                break Stop::Breakpoint;
+            }
+        };
+        match stop {
+            Stop::Exited => {
+                self.exited = true;
+                writeln!(out, "The program has exited")?;
+                return Ok(());
+            }
+            Stop::Breakpoint => {
+                let ip = self.machine.regs()[0];
+                writeln!(out, "Breakpoint reached at {}", self.name(ip))?;
+            }
+            Stop::Done => (),
+        }
+        self.show_line(self.machine.regs()[0], out)
+    }
+
+    fn back<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<()> {
+        let count = match args {
+            [] => 1,
+            [count] => match count.parse() {
+                Ok(count) if count > 0 => count,
+                _ => return Err(Failure::Usage(format!("invalid count `{count}`"))),
+            },
+            _ => return usage("back [<n>]"),
+        };
+        let mut undone = 0;
+        while undone < count && self.machine.step_back() {
+            undone += 1;
+        }
+        self.rewound(undone > 0, out)
+    }
+
+    fn rewind<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<()> {
+        let [location] = args else {
+            return usage("rewind <loc>");
+        };
+        let addr = self.location(location)?;
+        let before = self.machine.executed_instructions();
+        let found = self.machine.run_back_to(addr);
+        if !found {
+            writeln!(out, "{} not found in the history", self.name(addr))?;
+        }
+        self.rewound(self.machine.executed_instructions() != before, out)
+    }
+
+    /// Report the position after going back in the history, `moved`
+    /// telling whether any instruction was undone.
+    fn rewound<W: Write>(&mut self, moved: bool, out: &mut W) -> Result<()> {
+        if !moved {
+            writeln!(out, "No more history")?;
+            return Ok(());
+        }
+        self.exited = false;
+        self.show_line(self.machine.regs()[0], out)
+    }
+
+    fn show_regs<W: Write>(&self, out: &mut W) -> Result<()> {
+        write!(out, "{}", format_regs(self.machine.regs()))?;
+        Ok(())
+    }
+
+    fn show_memory<W: Write>(&self, args: &[&str], out: &mut W) -> Result<()> {
+        let (location, len) = match args {
+            [location] => (location, 16),
+            [location, len] => (location, number(len)? as usize),
+            _ => return usage("mem <loc> [<len>]"),
+        };
+        let start = self.location(location)? as usize;
+        let memory = self.machine.memory();
+        let end = start.saturating_add(len).min(memory.len());
+        if start >= end {
+            return Err(Failure::Usage(format!("address {start} is out of memory")));
+        }
+        for line in (start..end).step_by(16) {
+            write!(out, "{line:04}:")?;
+            for byte in &memory[line..end.min(line + 16)] {
+                write!(out, " {byte:02x}")?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    fn set<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<()> {
+        let (target, value, width) = match args {
+            ["byte", target, value] => (*target, *value, 1),
+            [target, value] => (*target, *value, 4),
+            _ => return usage("set r<i> <value> | set [byte] [<loc>] <value>"),
+        };
+        let value = number(value)?;
+        if let Some(location) = target.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            let addr = self.location(location)? as usize;
+            let bytes = value.to_le_bytes();
+            if self.machine.set_memory(addr, &bytes[..width]).is_err() {
+                return Err(Failure::Usage(format!("address {addr} is out of memory")));
+            }
+        } else if let (Some(reg), 4) = (parse_register(target), width) {
+            self.machine
+                .set_reg(reg, value)
+                .expect("register index is checked");
+        } else {
+            return usage("set r<i> <value> | set [byte] [<loc>] <value>");
+        }
+        if target == "r0" {
+            self.show_line(value, out)?;
+        }
+        Ok(())
+    }
+
+    fn list<W: Write>(&self, args: &[&str], out: &mut W) -> Result<()> {
+        let ip = self.machine.regs()[0];
+        let center = match args {
+            [] => ip,
+            [location] => self.location(location)?,
+            _ => return usage("list [<loc>]"),
+        };
+        let index = self.instructions.partition_point(|&addr| addr < center);
+        let mut addr = match self.instructions.get(index) {
+            Some(&addr) if addr == center => self.instructions[index.saturating_sub(LIST_CONTEXT)],
+            _ => center,
+        };
+        for _ in 0..2 * LIST_CONTEXT + 1 {
+            if let Some(name) = self.symbols.label(addr) {
+                writeln!(out, "{name}:")?;
+            }
+            let marker = match (addr == ip, self.breakpoints.contains(&addr)) {
+                (true, _) => "=>",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            match self.decode(addr) {
+                Some((instruction, size)) => {
+                    writeln!(out, "{marker}{addr:04}   {}", self.render(instruction))?;
+                    addr += size as u32;
+                }
+                None => {
+                    writeln!(out, "{marker}{addr:04}   ???")?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Show the instruction at `addr`.
+    fn show_line<W: Write>(&self, addr: u32, out: &mut W) -> Result<()> {
+        match self.decode(addr) {
+            Some((instruction, _)) => {
+                writeln!(out, "=> {}   {}", self.name(addr), self.render(instruction))?;
+            }
+            None => writeln!(out, "=> {}   ???", self.name(addr))?,
+        }
+        Ok(())
+    }
+
+    /// Format an instruction, naming the immediate of `loadimm` when it is
+    /// the address of a label.
+    fn render(&self, instruction: Instruction) -> String {
+        match instruction {
+            Instruction::LoadImm { dst, imm } => match self.symbols.label(imm as u32) {
+                Some(name) if imm >= 0 => format!("loadimm r{dst} <- #{name}"),
+                _ => instruction.to_string(),
+            },
+            _ => instruction.to_string(),
+        }
+    }
+
+    /// Format an address along with its label, if any.
+    fn name(&self, addr: u32) -> String {
+        match self.symbols.label(addr) {
+            Some(name) => format!("{addr:04} <{name}>"),
+            None => format!("{addr:04}"),
+        }
+    }
+
+    fn decode(&self, addr: u32) -> Option<(Instruction, usize)> {
+        let memory = self.machine.memory().get(addr as usize..)?;
+        Instruction::decode(memory).ok()
+    }
+
+    fn word(&self, addr: u32) -> Option<u32> {
+        let bytes = self
+            .machine
+            .memory()
+            .get(addr as usize..addr as usize + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Resolve a location given as a number, a label or a register.
+    fn location(&self, location: &str) -> Result<u32> {
+        if let Some(&addr) = self.labels.get(location) {
+            Ok(addr)
+        } else if let Some(reg) = parse_register(location) {
+            Ok(self.machine.regs()[reg])
+        } else {
+            parse_number(location)
+                .ok_or_else(|| Failure::Usage(format!("unknown location `{location}`")))
+        }
+    }
+}
+
+/// Failure of a command: either a user error, reported and ignored, or an
+/// I/O error which ends the session.
+enum Failure {
+    Usage(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure::Io(e)
+    }
+}
+
+type Result<T> = std::result::Result<T, Failure>;
+
+fn usage<T>(syntax: &str) -> Result<T> {
+    Err(Failure::Usage(format!("usage: {syntax}")))
+}
+
+fn number(text: &str) -> Result<u32> {
+    parse_number(text).ok_or_else(|| Failure::Usage(format!("invalid number `{text}`")))
+}
+
+/// Parse a register name such as `r3`.
+#[must_use]
+pub fn parse_register(text: &str) -> Option<usize> {
+    let reg: usize = text.strip_prefix('r')?.parse().ok()?;
+    (reg < NREGS).then_some(reg)
+}
+
+/// Parse a decimal, possibly negative, or `0x` hexadecimal number.
+#[must_use]
+pub fn parse_number(text: &str) -> Option<u32> {
+    if let Some(hex) = text.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(negative) = text.strip_prefix('-') {
+        negative.parse::<u32>().ok().map(u32::wrapping_neg)
+    } else {
+        text.parse().ok()
+    }
+}
+
+/// Format registers four per line, as the `regs` command shows them.
+#[must_use]
+pub fn format_regs(regs: &[u32]) -> String {
+    let mut text = String::new();
+    for (i, value) in regs.iter().enumerate() {
+        let separator = if i % 4 == 3 { "\n" } else { "  " };
+        text += &format!("r{i:<2} = 0x{value:08x}{separator}");
+    }
+    text
+}
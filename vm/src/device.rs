@@ -0,0 +1,184 @@
+//! Memory-mapped I/O devices.
+//!
+//! A [`Device`] mapped with [`Machine::map_device`](crate::Machine::map_device)
+//! receives the loads and stores falling in its address range, at an offset
+//! relative to the start of the range. Three devices are provided:
+//!
+//! - [`Console`], a character console at offset 0;
+//! - [`CycleCounter`], counting executed instructions;
+//! - [`Rng`], a deterministic pseudo-random number generator.
+
+use std::io::{self, Read, Write};
+
+/// A device mapped in the address space of a machine.
+///
+/// Byte accesses are mandatory, and word accesses are composed of byte
+/// accesses in little-endian order unless the device overrides them.
+pub trait Device {
+    /// Read the byte at `offset`.
+    ///
+    /// # Errors
+    /// An error stops the machine with a `ReadFailure`.
+    fn read_byte(&mut self, offset: u32) -> io::Result<u8>;
+
+    /// Write the byte at `offset`.
+    ///
+    /// # Errors
+    /// An error stops the machine with a `WriteFailure`.
+    fn write_byte(&mut self, offset: u32, value: u8) -> io::Result<()>;
+
+    /// Read the word at `offset`.
+    ///
+    /// # Errors
+    /// An error stops the machine with a `ReadFailure`.
+    fn read_word(&mut self, offset: u32) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.read_byte(offset + i as u32)?;
+        }
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Write the word at `offset`.
+    ///
+    /// # Errors
+    /// An error stops the machine with a `WriteFailure`.
+    fn write_word(&mut self, offset: u32, value: u32) -> io::Result<()> {
+        for (i, byte) in value.to_le_bytes().into_iter().enumerate() {
+            self.write_byte(offset + i as u32, byte)?;
+        }
+        Ok(())
+    }
+
+    /// Called after every instruction executed by the machine.
+    fn tick(&mut self) {}
+}
+
+/// Character console. Storing to offset 0 writes the low byte of the value
+/// on the output, and loading from offset 0 reads a byte from the input,
+/// a word load returning -1 at the end of the input like `in` does. Other
+/// offsets read as 0 and ignore writes.
+pub struct Console<R: Read, W: Write> {
+    input: R,
+    output: W,
+}
+
+impl<R: Read, W: Write> Console<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Console { input, output }
+    }
+
+    fn read_input(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = 0;
+        loop {
+            match self.input.read(std::slice::from_mut(&mut byte)) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte)),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<R: Read, W: Write> Device for Console<R, W> {
+    fn read_byte(&mut self, offset: u32) -> io::Result<u8> {
+        if offset != 0 {
+            return Ok(0);
+        }
+        Ok(self.read_input()?.unwrap_or(u8::MAX))
+    }
+
+    fn write_byte(&mut self, offset: u32, value: u8) -> io::Result<()> {
+        if offset == 0 {
+            self.output.write_all(&[value])?;
+            self.output.flush()?;
+        }
+        Ok(())
+    }
+
+    fn read_word(&mut self, offset: u32) -> io::Result<u32> {
+        if offset != 0 {
+            return Ok(0);
+        }
+        Ok(self.read_input()?.map_or(u32::MAX, u32::from))
+    }
+
+    fn write_word(&mut self, offset: u32, value: u32) -> io::Result<()> {
+        self.write_byte(offset, value as u8)
+    }
+}
+
+/// Counter of the instructions executed since the device was mapped, as a
+/// little-endian 64-bit value at offset 0. Writes are ignored.
+#[derive(Debug, Default)]
+pub struct CycleCounter {
+    cycles: u64,
+}
+
+impl CycleCounter {
+    #[must_use]
+    pub fn new() -> Self {
+        CycleCounter::default()
+    }
+}
+
+impl Device for CycleCounter {
+    fn read_byte(&mut self, offset: u32) -> io::Result<u8> {
+        let bytes = self.cycles.to_le_bytes();
+        Ok(bytes.get(offset as usize).copied().unwrap_or(0))
+    }
+
+    fn write_byte(&mut self, _offset: u32, _value: u8) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+    }
+}
+
+/// Deterministic pseudo-random number generator (SplitMix64). Every word
+/// loaded from offset 0 is a new random value, and storing a word at
+/// offset 0 reseeds the generator. Other offsets read as 0 and ignore
+/// writes.
+#[derive(Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    fn next(&mut self) -> u32 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        ((z ^ (z >> 31)) >> 32) as u32
+    }
+}
+
+impl Device for Rng {
+    fn read_byte(&mut self, offset: u32) -> io::Result<u8> {
+        Ok(if offset == 0 { self.next() as u8 } else { 0 })
+    }
+
+    fn write_byte(&mut self, offset: u32, value: u8) -> io::Result<()> {
+        self.write_word(offset, u32::from(value))
+    }
+
+    fn read_word(&mut self, offset: u32) -> io::Result<u32> {
+        Ok(if offset == 0 { self.next() } else { 0 })
+    }
+
+    fn write_word(&mut self, offset: u32, value: u32) -> io::Result<()> {
+        if offset == 0 {
+            self.state = u64::from(value);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,428 @@
+//! Disassembler producing listings in the `.dis` format accepted by
+//! [`asm`](crate::asm).
+//!
+//! Code is found by following the control flow from address 0. Jump
+//! targets are recognized when a `loadimm` value ends up in r0, either
+//! directly or through a `move r0 <- rX if ...`, and return addresses when
+//! a `loadimm` value is stored in memory. Bytes following the last
+//! reachable instruction are listed as data, split at every address loaded
+//! by a `loadimm`.
+//!
+//! [`disassemble_folded`] also lists the expansions of the pseudo-instructions
+//! of the assembler as the pseudo-instructions themselves.
+
+use crate::asm::DEFAULT_SCRATCH;
+use crate::Instruction;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// What the analysis learned from a `loadimm` immediate.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Target {
+    Code,
+    Data,
+}
+
+/// What is known along straight-line code: the value loaded in each
+/// register by a `loadimm` with the address of that `loadimm`, and the last
+/// such value stored in memory.
+#[derive(Clone, Copy, Default)]
+struct Known {
+    regs: [Option<(u32, usize)>; 16],
+    stored: Option<(u32, usize)>,
+}
+
+struct Analysis<'a> {
+    bytes: &'a [u8],
+    /// Address of every reachable instruction, with its size.
+    reachable: BTreeMap<usize, usize>,
+    /// Addresses of the `loadimm` whose immediate is a code or data address,
+    /// with that immediate.
+    symbolic: BTreeMap<usize, (u32, Target)>,
+}
+
+impl<'a> Analysis<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        let mut analysis = Analysis {
+            bytes,
+            reachable: BTreeMap::new(),
+            symbolic: BTreeMap::new(),
+        };
+        analysis.explore();
+        analysis
+    }
+
+    /// Follow the control flow from address 0.
+    fn explore(&mut self) {
+        let mut pending = vec![0];
+        while let Some(mut addr) = pending.pop() {
+            let mut known = Known::default();
+            while addr < self.bytes.len() && !self.reachable.contains_key(&addr) {
+                let Ok((instruction, size)) = Instruction::decode(&self.bytes[addr..]) else {
+                    break;
+                };
+                self.reachable.insert(addr, size);
+                let (next, targets) = self.track(addr, instruction, size, &mut known);
+                pending.extend(targets.into_iter().filter(|&t| t < self.bytes.len()));
+                if !next {
+                    break;
+                }
+                addr += size;
+            }
+        }
+    }
+
+    /// Record that the immediate of the `loadimm` at `source` is a code
+    /// address, and return that address.
+    fn code(&mut self, (value, source): (u32, usize)) -> usize {
+        self.symbolic.insert(source, (value, Target::Code));
+        value as usize
+    }
+
+    /// Update what is known after executing `instruction`, located at `addr`.
+    /// Returns whether execution may fall through to the next instruction
+    /// and the code addresses that may be executed later: jump targets, and
+    /// the return address stored in memory before a call.
+    fn track(
+        &mut self,
+        addr: usize,
+        instruction: Instruction,
+        size: usize,
+        known: &mut Known,
+    ) -> (bool, Vec<usize>) {
+        let (next, target) = match instruction {
+            Instruction::LoadImm { dst: 0, imm } => (false, Some((imm as u32, addr))),
+            Instruction::MoveIf { dst: 0, src, cond } => (cond != 0, known.regs[src as usize]),
+            Instruction::LoadImm { dst, imm } => {
+                known.regs[dst as usize] = Some((imm as u32, addr));
+                return (true, vec![]);
+            }
+            Instruction::MoveIf { dst, src, cond } => {
+                known.regs[dst as usize] = if cond == 0 {
+                    known.regs[src as usize]
+                } else {
+                    None
+                };
+                return (true, vec![]);
+            }
+            Instruction::Store { src, .. } => {
+                known.stored = known.regs[src as usize];
+                return (true, vec![]);
+            }
+            Instruction::StoreNarrow { .. } => {
+                known.stored = None;
+                return (true, vec![]);
+            }
+            Instruction::Load { dst: 0, .. }
+            | Instruction::LoadNarrow { dst: 0, .. }
+            | Instruction::Sub { dst: 0, .. }
+            | Instruction::In { dst: 0 }
+            | Instruction::InNumber { dst: 0 }
+            | Instruction::Arith { dst: 0, .. }
+            | Instruction::Exit
+            | Instruction::ExitWith { .. } => return (false, vec![]),
+            Instruction::Load { dst, .. }
+            | Instruction::LoadNarrow { dst, .. }
+            | Instruction::Sub { dst, .. }
+            | Instruction::In { dst }
+            | Instruction::InNumber { dst }
+            | Instruction::Arith { dst, .. } => {
+                known.regs[dst as usize] = None;
+                return (true, vec![]);
+            }
+            Instruction::Out { .. } | Instruction::OutNumber { .. } => return (true, vec![]),
+        };
+        let mut targets: Vec<usize> = target.map(|t| self.code(t)).into_iter().collect();
+        if let Some(stored) = known.stored.take() {
+            if stored.0 as usize == addr + size {
+                targets.push(self.code(stored));
+            }
+        }
+        (next, targets)
+    }
+
+    /// End of the code region: everything after the last reachable
+    /// instruction is data.
+    fn code_end(&self) -> usize {
+        self.reachable
+            .iter()
+            .next_back()
+            .map_or(0, |(addr, size)| addr + size)
+    }
+
+    /// Decode the code region sequentially, reachable or not. Undecodable
+    /// bytes, or bytes overlapping a reachable instruction, are returned one
+    /// by one as `None`.
+    fn sweep(&self) -> Vec<(usize, Option<(Instruction, usize)>)> {
+        let end = self.code_end();
+        let mut items = Vec::new();
+        let mut addr = 0;
+        while addr < end {
+            match Instruction::decode(&self.bytes[addr..end]) {
+                Ok((instruction, size))
+                    if self.reachable.range(addr + 1..addr + size).next().is_none() =>
+                {
+                    items.push((addr, Some((instruction, size))));
+                    addr += size;
+                }
+                _ => {
+                    items.push((addr, None));
+                    addr += 1;
+                }
+            }
+        }
+        items
+    }
+
+    /// Collect symbolic immediates in unreachable code as well, and data
+    /// addresses loaded anywhere in the code.
+    fn scan(&mut self, items: &[(usize, Option<(Instruction, usize)>)]) {
+        let data = self.code_end()..self.bytes.len();
+        let mut known = Known::default();
+        for &(addr, instruction) in items {
+            let Some((instruction, size)) = instruction else {
+                known = Known::default();
+                continue;
+            };
+            if let Instruction::LoadImm { imm, .. } = instruction {
+                if data.contains(&(imm as u32 as usize)) {
+                    self.symbolic.insert(addr, (imm as u32, Target::Data));
+                }
+            }
+            if !self.track(addr, instruction, size, &mut known).0 {
+                known = Known::default();
+            }
+        }
+    }
+}
+
+/// Disassemble a memory image into a listing.
+#[must_use]
+pub fn disassemble(bytes: &[u8]) -> String {
+    listing(bytes, None)
+}
+
+/// Like [`disassemble`], listing the instruction sequences generated by
+/// the pseudo-instructions `push`, `pop`, `call`, `ret`, `jmp` and `jnz`
+/// with `scratch` as scratch register as these pseudo-instructions.
+#[must_use]
+pub fn disassemble_folded(bytes: &[u8], scratch: u8) -> String {
+    listing(bytes, Some(scratch))
+}
+
+type Item = (usize, Option<(Instruction, usize)>);
+
+fn listing(bytes: &[u8], fold: Option<u8>) -> String {
+    let mut analysis = Analysis::new(bytes);
+    let items = analysis.sweep();
+    analysis.scan(&items);
+    let code_end = analysis.code_end();
+
+    // Only addresses where a line starts can be labelled.
+    let line_starts: BTreeSet<usize> = items.iter().map(|&(addr, _)| addr).collect();
+    let mut targets = BTreeMap::new();
+    for &(value, target) in analysis.symbolic.values() {
+        let addr = value as usize;
+        if line_starts.contains(&addr) || (code_end..bytes.len()).contains(&addr) {
+            targets.entry(addr).or_insert(target);
+        }
+    }
+    let data_starts: BTreeSet<usize> = targets
+        .iter()
+        .filter(|&(_, &target)| target == Target::Data)
+        .map(|(&addr, _)| addr)
+        .collect();
+
+    // Name labels in address order, code and data being numbered separately.
+    let (mut code_count, mut str_count, mut data_count) = (0, 0, 0);
+    let mut labels = BTreeMap::new();
+    for (&addr, &target) in &targets {
+        let name = if target == Target::Code {
+            code_count += 1;
+            format!("label_{code_count}")
+        } else if is_text(data_chunk(bytes, &data_starts, addr)) {
+            str_count += 1;
+            format!("str_{str_count}")
+        } else {
+            data_count += 1;
+            format!("data_{data_count}")
+        };
+        labels.insert(addr, name);
+    }
+
+    let mut listing = String::new();
+    if fold.is_some_and(|scratch| scratch != DEFAULT_SCRATCH) {
+        writeln!(listing, ".scratch r{}", fold.unwrap()).unwrap();
+    }
+    // Label of the immediate of the `loadimm` at `addr`, if it is symbolic
+    let target = |addr: usize| {
+        analysis
+            .symbolic
+            .get(&addr)
+            .and_then(|&(value, _)| labels.get(&(value as usize)))
+            .cloned()
+    };
+    let mut pending_data = Vec::new();
+    let mut index = 0;
+    while index < items.len() {
+        let (addr, instruction) = items[index];
+        index += 1;
+        let label = labels.get(&addr);
+        if (label.is_some() || instruction.is_some()) && !pending_data.is_empty() {
+            data_line(&mut listing, &std::mem::take(&mut pending_data));
+        }
+        if let Some(name) = label {
+            writeln!(listing, "{name}:").unwrap();
+        }
+        let Some((instruction, _)) = instruction else {
+            pending_data.push(bytes[addr]);
+            continue;
+        };
+        let folded = fold.and_then(|scratch| {
+            let run = instruction_run(&items[index - 1..], &labels);
+            pseudo(&run, scratch, |addr, imm| {
+                target(addr).unwrap_or_else(|| format!("#{imm}"))
+            })
+        });
+        if let Some((pseudo, count)) = folded {
+            writeln!(listing, "  {addr:04}   {pseudo}").unwrap();
+            index += count - 1;
+            continue;
+        }
+        match (instruction, target(addr)) {
+            (Instruction::LoadImm { dst, .. }, Some(name)) => {
+                writeln!(listing, "  {addr:04}   loadimm r{dst} <- #{name}").unwrap();
+            }
+            _ => writeln!(listing, "  {addr:04}   {instruction}").unwrap(),
+        }
+    }
+    if !pending_data.is_empty() {
+        data_line(&mut listing, &pending_data);
+    }
+
+    let mut addr = code_end;
+    while addr < bytes.len() {
+        if let Some(name) = labels.get(&addr) {
+            writeln!(listing, "{name}:").unwrap();
+        }
+        let chunk = data_chunk(bytes, &data_starts, addr);
+        data_line(&mut listing, chunk);
+        addr += chunk.len();
+    }
+    listing
+}
+
+/// The instructions starting `items`, up to the length of the longest
+/// pseudo-instruction expansion, stopping at undecodable bytes and labels.
+fn instruction_run(items: &[Item], labels: &BTreeMap<usize, String>) -> Vec<(usize, Instruction)> {
+    let mut run = Vec::new();
+    for (i, &(addr, item)) in items.iter().take(5).enumerate() {
+        match item {
+            Some((instruction, _)) if i == 0 || !labels.contains_key(&addr) => {
+                run.push((addr, instruction));
+            }
+            _ => break,
+        }
+    }
+    run
+}
+
+/// Recognize the expansion of a pseudo-instruction at the start of `run`,
+/// returning that pseudo-instruction and the number of instructions of its
+/// expansion. `target` names the immediate of the `loadimm` at an address.
+fn pseudo(
+    run: &[(usize, Instruction)],
+    s: u8,
+    target: impl Fn(usize, i16) -> String,
+) -> Option<(String, usize)> {
+    use Instruction::{Load, LoadImm, MoveIf, Store, Sub};
+    let instructions: Vec<Instruction> = run.iter().map(|&(_, instruction)| instruction).collect();
+    let sub_sp = Sub {
+        dst: 2,
+        lhs: 2,
+        rhs: s,
+    };
+    match instructions[..] {
+        [LoadImm { dst: d1, imm: 4 }, sub, LoadImm { dst: d2, imm: ret }, Store { addr: 2, src }, LoadImm { dst: 0, imm }, ..]
+            if d1 == s
+                && sub == sub_sp
+                && d2 == s
+                && src == s
+                && ret as u32 as usize == run[4].0 + 4 =>
+        {
+            Some((format!("call {}", target(run[4].0, imm)), 5))
+        }
+        [LoadImm { dst: d1, imm: -4 }, sub, LoadImm { dst: d2, imm: 4 }, Sub { dst, lhs: 2, rhs }, Load { dst: reg, addr }, ..]
+            if d1 == s && sub == sub_sp && d2 == s && dst == s && rhs == s && addr == s =>
+        {
+            Some((
+                if reg == 0 {
+                    "ret".to_owned()
+                } else {
+                    format!("pop r{reg}")
+                },
+                5,
+            ))
+        }
+        [LoadImm { dst, imm: 4 }, sub, Store { addr: 2, src }, ..]
+            if dst == s && sub == sub_sp && src != s =>
+        {
+            Some((format!("push r{src}"), 3))
+        }
+        [LoadImm { dst, imm }, MoveIf { dst: 0, src, cond }, ..]
+            if dst == s && src == s && cond != s =>
+        {
+            Some((format!("jnz r{cond}, {}", target(run[0].0, imm)), 2))
+        }
+        [LoadImm { dst: 0, imm }, ..] => Some((format!("jmp {}", target(run[0].0, imm)), 1)),
+        _ => None,
+    }
+}
+
+/// Data starting at `addr` and ending at the next data label.
+fn data_chunk<'a>(bytes: &'a [u8], starts: &BTreeSet<usize>, addr: usize) -> &'a [u8] {
+    let end = starts
+        .range(addr + 1..)
+        .next()
+        .copied()
+        .unwrap_or(bytes.len());
+    &bytes[addr..end]
+}
+
+fn is_text(data: &[u8]) -> bool {
+    data.iter()
+        .all(|&b| (b' '..=b'~').contains(&b) || b"\n\r\t".contains(&b))
+}
+
+/// Append a data line, as a byte string if the data looks like text or as a
+/// list of bytes otherwise.
+fn data_line(listing: &mut String, data: &[u8]) {
+    listing.push_str("  ???? ");
+    if is_text(data) {
+        let quote = if data.contains(&b'\'') && !data.contains(&b'"') {
+            '"'
+        } else {
+            '\''
+        };
+        listing.push('b');
+        listing.push(quote);
+        for &b in data {
+            match b {
+                b'\n' => listing.push_str("\\n"),
+                b'\r' => listing.push_str("\\r"),
+                b'\t' => listing.push_str("\\t"),
+                b'\\' => listing.push_str("\\\\"),
+                _ if b as char == quote => {
+                    listing.push('\\');
+                    listing.push(quote);
+                }
+                _ => listing.push(b as char),
+            }
+        }
+        listing.push(quote);
+    } else {
+        let list: Vec<String> = data.iter().map(u8::to_string).collect();
+        write!(listing, "[{}]", list.join(", ")).unwrap();
+    }
+    listing.push('\n');
+}
@@ -0,0 +1,167 @@
+use crate::symbols::SymbolTable;
+use crate::Instruction;
+use std::fmt;
+use std::io;
+
+/// Error of a machine operation. Errors raised while executing an
+/// instruction also carry the IP and the bytes of that instruction.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    ip: Option<u32>,
+    bytes: Vec<u8>,
+    /// The IP as `label+offset`, shown instead of the IP if set.
+    location: Option<String>,
+}
+
+/// Cause of an [`Error`].
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// Attempt to create a machine with too large a memory
+    MemoryOverflow,
+    /// Program ending at `end`, past the end of a memory of `size` bytes
+    ProgramTooLarge { end: u64, size: usize },
+    /// Register out of r0 to r15, in an instruction or given to
+    /// [`Machine::set_reg`](crate::Machine::set_reg)
+    InvalidRegister { reg: usize },
+    /// Unknown opcode, or instruction of a disabled extension
+    UnknownOpcode { opcode: u8 },
+    /// Instruction cut by the end of the memory
+    TruncatedInstruction,
+    /// Access to `len` bytes at `address`, past the end of the memory
+    OutOfRange { address: u64, len: usize },
+    /// Access to `len` bytes at `address`, only partially covered by a
+    /// device
+    MisalignedAccess { address: u64, len: usize },
+    /// Error while reading from the input or a device
+    ReadFailure(io::Error),
+    /// Error while writing to the output, a device or the tracer
+    WriteFailure(io::Error),
+    /// No valid number found in the input by `in_number`
+    InvalidNumber,
+    /// Division or remainder by zero
+    DivisionByZero,
+    /// Attempt to map a device on an empty range or over another device
+    InvalidDeviceRange,
+    /// Malformed snapshot or unsupported snapshot version
+    InvalidSnapshot,
+    /// Malformed executable file or unsupported executable version
+    InvalidExecutable,
+}
+
+impl Error {
+    /// Cause of the error.
+    #[must_use]
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// IP of the faulting instruction, if the error happened while
+    /// executing one.
+    #[must_use]
+    pub fn ip(&self) -> Option<u32> {
+        self.ip
+    }
+
+    /// Bytes of the faulting instruction, as much of them as could be read
+    /// for an invalid instruction. Empty if no instruction is involved.
+    #[must_use]
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Name the IP of the error with `symbols` when displaying it, e.g.
+    /// `division by zero at loop+8` rather than `division by zero at 0012`.
+    #[must_use]
+    pub fn symbolize(mut self, symbols: &SymbolTable) -> Self {
+        self.location = self.ip.map(|ip| symbols.symbolize(ip));
+        self
+    }
+
+    /// Attribute the error to the instruction at `ip`, unless it already is.
+    pub(crate) fn at(mut self, ip: u32) -> Self {
+        self.ip.get_or_insert(ip);
+        self
+    }
+
+    /// Record the bytes of the faulting instruction, unless they already
+    /// are.
+    pub(crate) fn with_bytes(mut self, bytes: &[u8]) -> Self {
+        if self.bytes.is_empty() {
+            self.bytes = bytes.to_vec();
+        }
+        self
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error {
+            kind,
+            ip: None,
+            bytes: Vec::new(),
+            location: None,
+        }
+    }
+}
+
+/// Format the error as a diagnostic, e.g.
+/// `division by zero at 0012: divs r1 <- r2 / r3 (22 01 02 03)`.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(location) = &self.location {
+            write!(f, " at {location}")?;
+        } else if let Some(ip) = self.ip {
+            write!(f, " at {ip:04}")?;
+        }
+        if let Ok((instruction, _)) = Instruction::decode(&self.bytes) {
+            write!(f, ": {instruction}")?;
+        }
+        if !self.bytes.is_empty() {
+            let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{b:02x}")).collect();
+            write!(f, " ({})", bytes.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::MemoryOverflow => write!(f, "memory too large"),
+            ErrorKind::ProgramTooLarge { end, size } => write!(
+                f,
+                "program of {end} bytes does not fit in {size} bytes of memory"
+            ),
+            ErrorKind::InvalidRegister { reg } => write!(f, "invalid register r{reg}"),
+            ErrorKind::UnknownOpcode { opcode } => write!(f, "unknown opcode {opcode}"),
+            ErrorKind::TruncatedInstruction => {
+                write!(f, "instruction truncated by the end of memory")
+            }
+            ErrorKind::OutOfRange { address, len } => {
+                write!(f, "{len}-byte access at address {address} out of memory")
+            }
+            ErrorKind::MisalignedAccess { address, len } => write!(
+                f,
+                "{len}-byte access at address {address} across a device boundary"
+            ),
+            ErrorKind::ReadFailure(e) => write!(f, "read failure: {e}"),
+            ErrorKind::WriteFailure(e) => write!(f, "write failure: {e}"),
+            ErrorKind::InvalidNumber => write!(f, "no valid number in the input"),
+            ErrorKind::DivisionByZero => write!(f, "division by zero"),
+            ErrorKind::InvalidDeviceRange => write!(f, "invalid device range"),
+            ErrorKind::InvalidSnapshot => write!(f, "invalid snapshot"),
+            ErrorKind::InvalidExecutable => write!(f, "invalid executable"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::ReadFailure(e) | ErrorKind::WriteFailure(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Executables: program images with the metadata needed to start them.
+//!
+//! A raw image is loaded at address 0 and started there with every register
+//! zero. An executable file also gives the entry point, the initial
+//! registers and the extensions needed, splits the image into code and data
+//! sections, and may name addresses with symbols. It holds, with every
+//! number in little-endian order:
+//!
+//! | size | content                                                    |
+//! |------|------------------------------------------------------------|
+//! | 4    | magic `VMEX`                                               |
+//! | 4    | format version, currently 1                                |
+//! | 4    | flags, bit 0 set if the arithmetic extension is needed     |
+//! | 4    | entry point, the initial value of r0                       |
+//! | 60   | initial values of r1 to r15                                |
+//! | 4    | number of sections                                         |
+//! | ...  | sections: kind (1), address (4), length (4), bytes         |
+//! | 4    | number of symbols                                          |
+//! | ...  | symbols: address (4), name                                 |
+//!
+//! A section kind is 0 for code and 1 for data, and sections are sorted by
+//! address without overlapping. Names are distinct, and stored as their
+//! length (2) followed by their UTF-8 bytes.
+//!
+//! The magic is not a valid instruction, so a raw image cannot be mistaken
+//! for an executable file.
+
+use crate::asm::Program;
+use crate::machine::{Result, NREGS};
+use crate::reader::Reader;
+use crate::{ErrorKind, Instruction};
+use std::collections::BTreeMap;
+
+const MAGIC: &[u8; 4] = b"VMEX";
+/// Version of the executable format written by [`Executable::encode`].
+pub const VERSION: u32 = 1;
+const ARITH_EXTENSION: u32 = 1;
+
+/// A program and how to start it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Executable {
+    /// Address of the first instruction to execute.
+    pub entry: u32,
+    /// Initial values of the registers, r0 being replaced by `entry`.
+    pub regs: [u32; NREGS],
+    pub arith_extension: bool,
+    /// Contents of the memory, the rest of it being zero.
+    pub sections: Vec<Section>,
+    /// Addresses of the labels of the program, by name.
+    pub symbols: BTreeMap<String, u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub address: u32,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    Code,
+    Data,
+}
+
+impl Section {
+    /// Address following the section.
+    #[must_use]
+    pub fn end(&self) -> u64 {
+        u64::from(self.address) + self.bytes.len() as u64
+    }
+}
+
+impl Executable {
+    /// Executable of a raw image: a single code section at address 0,
+    /// started there with every register zero.
+    #[must_use]
+    pub fn raw(bytes: &[u8]) -> Executable {
+        let sections = if bytes.is_empty() {
+            Vec::new()
+        } else {
+            vec![Section {
+                kind: SectionKind::Code,
+                address: 0,
+                bytes: bytes.to_vec(),
+            }]
+        };
+        Executable {
+            sections,
+            ..Executable::default()
+        }
+    }
+
+    /// Executable of an assembled program, started at address 0. The bytes
+    /// emitted by its instruction lines make up the code sections, the others
+    /// the data sections, its labels are the symbols, and the arithmetic
+    /// extension is needed if one of its instructions belongs to it.
+    #[must_use]
+    pub fn from_program(program: &Program) -> Executable {
+        let mut code = vec![false; program.bytes.len()];
+        for range in program.ranges.values() {
+            code[range.start as usize..range.end as usize].fill(true);
+        }
+        let arith_extension = program
+            .instructions()
+            .iter()
+            .any(|(_, instruction, _)| matches!(instruction, Instruction::Arith { .. }));
+        let mut sections: Vec<Section> = Vec::new();
+        for (address, (&byte, &is_code)) in program.bytes.iter().zip(&code).enumerate() {
+            let kind = if is_code {
+                SectionKind::Code
+            } else {
+                SectionKind::Data
+            };
+            match sections.last_mut() {
+                Some(section) if section.kind == kind => section.bytes.push(byte),
+                _ => sections.push(Section {
+                    kind,
+                    address: address as u32,
+                    bytes: vec![byte],
+                }),
+            }
+        }
+        Executable {
+            arith_extension,
+            sections,
+            symbols: program.labels.clone(),
+            ..Executable::default()
+        }
+    }
+
+    /// Decode an executable file, or take any other bytes as a raw image.
+    ///
+    /// # Errors
+    /// `InvalidExecutable` is returned if `bytes` starts with the magic of
+    /// executable files but is not one of a supported version.
+    pub fn parse(bytes: &[u8]) -> Result<Executable> {
+        if bytes.starts_with(MAGIC) {
+            Executable::decode(bytes)
+        } else {
+            Ok(Executable::raw(bytes))
+        }
+    }
+
+    /// Check that every section fits in a memory of `size` bytes.
+    ///
+    /// # Errors
+    /// `ProgramTooLarge` is returned for the first section ending past
+    /// `size`.
+    pub fn fits_in(&self, size: usize) -> Result<()> {
+        match self.sections.iter().find(|s| s.end() > size as u64) {
+            Some(section) => Err(ErrorKind::ProgramTooLarge {
+                end: section.end(),
+                size,
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Memory image from address 0 to the end of the last section, in a
+    /// memory of `size` bytes.
+    ///
+    /// # Errors
+    /// `ProgramTooLarge` is returned, before allocating anything, if a
+    /// section does not fit in memory.
+    pub fn memory(&self, size: usize) -> Result<Vec<u8>> {
+        self.fits_in(size)?;
+        let len = self.sections.last().map_or(0, Section::end);
+        let mut memory = vec![0; len as usize];
+        for section in &self.sections {
+            memory[section.address as usize..section.end() as usize]
+                .copy_from_slice(&section.bytes);
+        }
+        Ok(memory)
+    }
+
+    /// Encode the executable in the executable file format.
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(MAGIC);
+        bytes.extend(VERSION.to_le_bytes());
+        let flags = if self.arith_extension {
+            ARITH_EXTENSION
+        } else {
+            0
+        };
+        bytes.extend(flags.to_le_bytes());
+        bytes.extend(self.entry.to_le_bytes());
+        for reg in &self.regs[1..] {
+            bytes.extend(reg.to_le_bytes());
+        }
+        bytes.extend((self.sections.len() as u32).to_le_bytes());
+        for section in &self.sections {
+            bytes.push(match section.kind {
+                SectionKind::Code => 0,
+                SectionKind::Data => 1,
+            });
+            bytes.extend(section.address.to_le_bytes());
+            bytes.extend((section.bytes.len() as u32).to_le_bytes());
+            bytes.extend(&section.bytes);
+        }
+        bytes.extend((self.symbols.len() as u32).to_le_bytes());
+        for (name, address) in &self.symbols {
+            bytes.extend(address.to_le_bytes());
+            bytes.extend((name.len() as u16).to_le_bytes());
+            bytes.extend(name.as_bytes());
+        }
+        bytes
+    }
+
+    /// Decode an executable file.
+    ///
+    /// # Errors
+    /// `InvalidExecutable` is returned if `bytes` is not an executable file
+    /// of a supported version.
+    pub fn decode(bytes: &[u8]) -> Result<Executable> {
+        let mut reader = Reader::new(bytes, || ErrorKind::InvalidExecutable);
+        if reader.take(4)? != MAGIC || reader.u32()? != VERSION {
+            return Err(ErrorKind::InvalidExecutable.into());
+        }
+        let flags = reader.u32()?;
+        if flags & !ARITH_EXTENSION != 0 {
+            return Err(ErrorKind::InvalidExecutable.into());
+        }
+        let entry = reader.u32()?;
+        let mut regs = [0; NREGS];
+        regs[0] = entry;
+        for reg in &mut regs[1..] {
+            *reg = reader.u32()?;
+        }
+        let mut sections: Vec<Section> = Vec::new();
+        for _ in 0..reader.u32()? {
+            let kind = match reader.take(1)? {
+                [0] => SectionKind::Code,
+                [1] => SectionKind::Data,
+                _ => return Err(ErrorKind::InvalidExecutable.into()),
+            };
+            let address = reader.u32()?;
+            let len = reader.u32()? as usize;
+            let section = Section {
+                kind,
+                address,
+                bytes: reader.take(len)?.to_vec(),
+            };
+            if sections
+                .last()
+                .is_some_and(|last| last.end() > u64::from(address))
+            {
+                return Err(ErrorKind::InvalidExecutable.into());
+            }
+            sections.push(section);
+        }
+        let mut symbols = BTreeMap::new();
+        for _ in 0..reader.u32()? {
+            let address = reader.u32()?;
+            if symbols.insert(reader.name()?, address).is_some() {
+                return Err(ErrorKind::InvalidExecutable.into());
+            }
+        }
+        if !reader.rest().is_empty() {
+            return Err(ErrorKind::InvalidExecutable.into());
+        }
+        Ok(Executable {
+            entry,
+            regs,
+            arith_extension: flags & ARITH_EXTENSION != 0,
+            sections,
+            symbols,
+        })
+    }
+}
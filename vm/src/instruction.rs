@@ -0,0 +1,407 @@
+use crate::machine::{Result, NREGS};
+use crate::{Error, ErrorKind};
+use std::fmt;
+
+/// A decoded instruction. Register operands are register indices, always
+/// lower than 16 when the instruction comes from [`Instruction::decode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// `move rᵢ <- rⱼ if rₖ != 0` (opcode 1)
+    MoveIf { dst: u8, src: u8, cond: u8 },
+    /// `store [rᵢ] <- rⱼ` (opcode 2)
+    Store { addr: u8, src: u8 },
+    /// `load rᵢ <- [rⱼ]` (opcode 3)
+    Load { dst: u8, addr: u8 },
+    /// `loadimm rᵢ <- #imm` (opcode 4), the immediate being sign-extended
+    LoadImm { dst: u8, imm: i16 },
+    /// `sub rᵢ <- rⱼ - rₖ` (opcode 5)
+    Sub { dst: u8, lhs: u8, rhs: u8 },
+    /// `out rᵢ` (opcode 6)
+    Out { src: u8 },
+    /// `exit` (opcode 7)
+    Exit,
+    /// `out_number rᵢ` (opcode 8)
+    OutNumber { src: u8 },
+    /// `in rᵢ` (opcode 9), reading a byte or -1 at end of input
+    In { dst: u8 },
+    /// `in_number rᵢ` (opcode 10), reading a signed decimal number
+    InNumber { dst: u8 },
+    /// `load8 rᵢ <- [rⱼ]` (opcode 11), `load8s` (12), `load16` (13) and
+    /// `load16s` (14), zero- or sign-extending the loaded value
+    LoadNarrow {
+        width: Width,
+        signed: bool,
+        dst: u8,
+        addr: u8,
+    },
+    /// `store8 [rᵢ] <- rⱼ` (opcode 15) and `store16` (16), storing the low
+    /// bits of the register
+    StoreNarrow { width: Width, addr: u8, src: u8 },
+    /// `exit rᵢ` (opcode 17), exiting with the value of the register as
+    /// exit status
+    ExitWith { src: u8 },
+    /// `add rᵢ <- rⱼ + rₖ` and the other instructions of the arithmetic
+    /// extension (opcodes 32 to 42)
+    Arith {
+        op: ArithOp,
+        dst: u8,
+        lhs: u8,
+        rhs: u8,
+    },
+}
+
+/// Operation of an instruction of the arithmetic extension. All operations
+/// wrap around on overflow, and shift amounts are taken modulo 32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    /// `add rᵢ <- rⱼ + rₖ` (opcode 32)
+    Add,
+    /// `mul rᵢ <- rⱼ * rₖ` (opcode 33)
+    Mul,
+    /// `divs rᵢ <- rⱼ / rₖ` (opcode 34), signed, rounding toward zero
+    DivS,
+    /// `divu rᵢ <- rⱼ / rₖ` (opcode 35), unsigned
+    DivU,
+    /// `rem rᵢ <- rⱼ % rₖ` (opcode 36), remainder of `divs`
+    Rem,
+    /// `and rᵢ <- rⱼ & rₖ` (opcode 37)
+    And,
+    /// `or rᵢ <- rⱼ | rₖ` (opcode 38)
+    Or,
+    /// `xor rᵢ <- rⱼ ^ rₖ` (opcode 39)
+    Xor,
+    /// `shl rᵢ <- rⱼ << rₖ` (opcode 40)
+    Shl,
+    /// `shr rᵢ <- rⱼ >> rₖ` (opcode 41), logical
+    Shr,
+    /// `sar rᵢ <- rⱼ >> rₖ` (opcode 42), arithmetic
+    Sar,
+}
+
+/// Width of the memory accesses of `load8`, `load16` and their variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    /// 8 bits
+    Byte,
+    /// 16 bits, little-endian
+    Half,
+}
+
+impl Width {
+    /// Number of bytes accessed.
+    #[must_use]
+    pub fn bytes(self) -> usize {
+        match self {
+            Width::Byte => 1,
+            Width::Half => 2,
+        }
+    }
+
+    /// Number of bits accessed, used as the suffix of mnemonics.
+    #[must_use]
+    pub fn bits(self) -> u32 {
+        8 * self.bytes() as u32
+    }
+
+    /// Zero- or sign-extend a value of this width to a word.
+    #[must_use]
+    pub fn extend(self, value: u32, signed: bool) -> u32 {
+        match (self, signed) {
+            (Width::Byte, false) => value as u8 as u32,
+            (Width::Byte, true) => value as u8 as i8 as u32,
+            (Width::Half, false) => value as u16 as u32,
+            (Width::Half, true) => value as u16 as i16 as u32,
+        }
+    }
+}
+
+impl ArithOp {
+    /// Every operation, in opcode order.
+    pub const ALL: [ArithOp; 11] = [
+        ArithOp::Add,
+        ArithOp::Mul,
+        ArithOp::DivS,
+        ArithOp::DivU,
+        ArithOp::Rem,
+        ArithOp::And,
+        ArithOp::Or,
+        ArithOp::Xor,
+        ArithOp::Shl,
+        ArithOp::Shr,
+        ArithOp::Sar,
+    ];
+
+    /// Opcode of the instruction.
+    #[must_use]
+    pub fn opcode(self) -> u8 {
+        32 + self as u8
+    }
+
+    /// Mnemonic used in listings.
+    #[must_use]
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            ArithOp::Add => "add",
+            ArithOp::Mul => "mul",
+            ArithOp::DivS => "divs",
+            ArithOp::DivU => "divu",
+            ArithOp::Rem => "rem",
+            ArithOp::And => "and",
+            ArithOp::Or => "or",
+            ArithOp::Xor => "xor",
+            ArithOp::Shl => "shl",
+            ArithOp::Shr => "shr",
+            ArithOp::Sar => "sar",
+        }
+    }
+
+    /// Operator written between the operands in listings.
+    #[must_use]
+    pub fn operator(self) -> &'static str {
+        match self {
+            ArithOp::Add => "+",
+            ArithOp::Mul => "*",
+            ArithOp::DivS | ArithOp::DivU => "/",
+            ArithOp::Rem => "%",
+            ArithOp::And => "&",
+            ArithOp::Or => "|",
+            ArithOp::Xor => "^",
+            ArithOp::Shl => "<<",
+            ArithOp::Shr | ArithOp::Sar => ">>",
+        }
+    }
+
+    /// Apply the operation, or return `None` on a division by zero.
+    #[must_use]
+    pub fn apply(self, lhs: u32, rhs: u32) -> Option<u32> {
+        Some(match self {
+            ArithOp::Add => lhs.wrapping_add(rhs),
+            ArithOp::Mul => lhs.wrapping_mul(rhs),
+            _ if rhs == 0 && matches!(self, ArithOp::DivS | ArithOp::DivU | ArithOp::Rem) => {
+                return None;
+            }
+            ArithOp::DivS => (lhs as i32).wrapping_div(rhs as i32) as u32,
+            ArithOp::DivU => lhs / rhs,
+            ArithOp::Rem => (lhs as i32).wrapping_rem(rhs as i32) as u32,
+            ArithOp::And => lhs & rhs,
+            ArithOp::Or => lhs | rhs,
+            ArithOp::Xor => lhs ^ rhs,
+            ArithOp::Shl => lhs.wrapping_shl(rhs),
+            ArithOp::Shr => lhs.wrapping_shr(rhs),
+            ArithOp::Sar => (lhs as i32).wrapping_shr(rhs) as u32,
+        })
+    }
+}
+
+impl Instruction {
+    /// Decode the instruction at the beginning of `bytes`, and return it
+    /// along with its size in bytes.
+    ///
+    /// # Errors
+    /// `UnknownOpcode` is returned if `bytes` starts with an unknown opcode,
+    /// `TruncatedInstruction` if `bytes` is empty or the instruction is
+    /// truncated, and `InvalidRegister` if it refers to a register outside
+    /// r0 to r15. The error holds the bytes of the instruction.
+    pub fn decode(bytes: &[u8]) -> Result<(Instruction, usize)> {
+        let size = match bytes.first() {
+            Some(7) => 1,
+            Some(6 | 8 | 9 | 10 | 17) => 2,
+            Some(2 | 3 | 11..=16) => 3,
+            Some(1 | 4 | 5 | 32..=42) => 4,
+            Some(&opcode) => {
+                return Err(Error::from(ErrorKind::UnknownOpcode { opcode }).with_bytes(&[opcode]));
+            }
+            None => return Err(ErrorKind::TruncatedInstruction.into()),
+        };
+        let Some(b) = bytes.get(..size) else {
+            return Err(Error::from(ErrorKind::TruncatedInstruction).with_bytes(bytes));
+        };
+        let instruction = match b[0] {
+            1 => Instruction::MoveIf {
+                dst: b[1],
+                src: b[2],
+                cond: b[3],
+            },
+            2 => Instruction::Store {
+                addr: b[1],
+                src: b[2],
+            },
+            3 => Instruction::Load {
+                dst: b[1],
+                addr: b[2],
+            },
+            4 => Instruction::LoadImm {
+                dst: b[1],
+                imm: i16::from_le_bytes([b[2], b[3]]),
+            },
+            5 => Instruction::Sub {
+                dst: b[1],
+                lhs: b[2],
+                rhs: b[3],
+            },
+            6 => Instruction::Out { src: b[1] },
+            7 => Instruction::Exit,
+            8 => Instruction::OutNumber { src: b[1] },
+            9 => Instruction::In { dst: b[1] },
+            10 => Instruction::InNumber { dst: b[1] },
+            op @ 11..=14 => Instruction::LoadNarrow {
+                width: if op < 13 { Width::Byte } else { Width::Half },
+                signed: op % 2 == 0,
+                dst: b[1],
+                addr: b[2],
+            },
+            op @ (15 | 16) => Instruction::StoreNarrow {
+                width: if op == 15 { Width::Byte } else { Width::Half },
+                addr: b[1],
+                src: b[2],
+            },
+            17 => Instruction::ExitWith { src: b[1] },
+            op => Instruction::Arith {
+                op: ArithOp::ALL[(op - 32) as usize],
+                dst: b[1],
+                lhs: b[2],
+                rhs: b[3],
+            },
+        };
+        if let Some(reg) = instruction.registers().find(|&r| r as usize >= NREGS) {
+            let reg = reg as usize;
+            return Err(Error::from(ErrorKind::InvalidRegister { reg }).with_bytes(b));
+        }
+        Ok((instruction, size))
+    }
+
+    /// Decode like [`decode`](Instruction::decode), except that the opcodes
+    /// of the arithmetic extension are unknown unless `arith_extension` is
+    /// set, whatever their operands.
+    pub(crate) fn decode_for(bytes: &[u8], arith_extension: bool) -> Result<(Instruction, usize)> {
+        match bytes.first() {
+            Some(&opcode @ 32..=42) if !arith_extension => {
+                let len = bytes.len().min(4);
+                Err(Error::from(ErrorKind::UnknownOpcode { opcode }).with_bytes(&bytes[..len]))
+            }
+            _ => Instruction::decode(bytes),
+        }
+    }
+
+    /// Encode the instruction as it is laid out in memory.
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        match *self {
+            Instruction::MoveIf { dst, src, cond } => vec![1, dst, src, cond],
+            Instruction::Store { addr, src } => vec![2, addr, src],
+            Instruction::Load { dst, addr } => vec![3, dst, addr],
+            Instruction::LoadImm { dst, imm } => {
+                let [low, high] = imm.to_le_bytes();
+                vec![4, dst, low, high]
+            }
+            Instruction::Sub { dst, lhs, rhs } => vec![5, dst, lhs, rhs],
+            Instruction::Out { src } => vec![6, src],
+            Instruction::Exit => vec![7],
+            Instruction::OutNumber { src } => vec![8, src],
+            Instruction::In { dst } => vec![9, dst],
+            Instruction::InNumber { dst } => vec![10, dst],
+            Instruction::LoadNarrow {
+                width,
+                signed,
+                dst,
+                addr,
+            } => {
+                let opcode = match width {
+                    Width::Byte => 11,
+                    Width::Half => 13,
+                };
+                vec![opcode + u8::from(signed), dst, addr]
+            }
+            Instruction::StoreNarrow { width, addr, src } => match width {
+                Width::Byte => vec![15, addr, src],
+                Width::Half => vec![16, addr, src],
+            },
+            Instruction::ExitWith { src } => vec![17, src],
+            Instruction::Arith { op, dst, lhs, rhs } => vec![op.opcode(), dst, lhs, rhs],
+        }
+    }
+
+    /// Size of the encoded instruction, in bytes.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::Exit => 1,
+            Instruction::Out { .. }
+            | Instruction::OutNumber { .. }
+            | Instruction::In { .. }
+            | Instruction::InNumber { .. }
+            | Instruction::ExitWith { .. } => 2,
+            Instruction::Store { .. }
+            | Instruction::Load { .. }
+            | Instruction::LoadNarrow { .. }
+            | Instruction::StoreNarrow { .. } => 3,
+            Instruction::MoveIf { .. }
+            | Instruction::LoadImm { .. }
+            | Instruction::Sub { .. }
+            | Instruction::Arith { .. } => 4,
+        }
+    }
+
+    /// Registers referenced by the instruction.
+    pub fn registers(&self) -> impl Iterator<Item = u8> {
+        let (regs, count) = match *self {
+            Instruction::MoveIf { dst, src, cond } => ([dst, src, cond], 3),
+            Instruction::Store { addr, src } | Instruction::StoreNarrow { addr, src, .. } => {
+                ([addr, src, 0], 2)
+            }
+            Instruction::Load { dst, addr } | Instruction::LoadNarrow { dst, addr, .. } => {
+                ([dst, addr, 0], 2)
+            }
+            Instruction::LoadImm { dst, .. } => ([dst, 0, 0], 1),
+            Instruction::Sub { dst, lhs, rhs } | Instruction::Arith { dst, lhs, rhs, .. } => {
+                ([dst, lhs, rhs], 3)
+            }
+            Instruction::Out { src }
+            | Instruction::OutNumber { src }
+            | Instruction::ExitWith { src } => ([src, 0, 0], 1),
+            Instruction::In { dst } | Instruction::InNumber { dst } => ([dst, 0, 0], 1),
+            Instruction::Exit => ([0; 3], 0),
+        };
+        regs.into_iter().take(count)
+    }
+}
+
+/// Format the instruction using the listing syntax, e.g.
+/// `move r0 <- r5 if r4 != 0`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::MoveIf { dst, src, cond } => {
+                write!(f, "move r{dst} <- r{src} if r{cond} != 0")
+            }
+            Instruction::Store { addr, src } => write!(f, "store [r{addr}] <- r{src}"),
+            Instruction::Load { dst, addr } => write!(f, "load r{dst} <- [r{addr}]"),
+            Instruction::LoadImm { dst, imm } => write!(f, "loadimm r{dst} <- #{imm}"),
+            Instruction::Sub { dst, lhs, rhs } => write!(f, "sub r{dst} <- r{lhs} - r{rhs}"),
+            Instruction::Out { src } => write!(f, "out r{src}"),
+            Instruction::Exit => write!(f, "exit"),
+            Instruction::ExitWith { src } => write!(f, "exit r{src}"),
+            Instruction::OutNumber { src } => write!(f, "out_number r{src}"),
+            Instruction::In { dst } => write!(f, "in r{dst}"),
+            Instruction::InNumber { dst } => write!(f, "in_number r{dst}"),
+            Instruction::LoadNarrow {
+                width,
+                signed,
+                dst,
+                addr,
+            } => {
+                let suffix = if *signed { "s" } else { "" };
+                write!(f, "load{}{suffix} r{dst} <- [r{addr}]", width.bits())
+            }
+            Instruction::StoreNarrow { width, addr, src } => {
+                write!(f, "store{} [r{addr}] <- r{src}", width.bits())
+            }
+            Instruction::Arith { op, dst, lhs, rhs } => write!(
+                f,
+                "{} r{dst} <- r{lhs} {} r{rhs}",
+                op.mnemonic(),
+                op.operator()
+            ),
+        }
+    }
+}
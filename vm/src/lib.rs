@@ -1,3 +1,21 @@
+pub mod asm;
+pub mod check;
+pub mod compiler;
+pub mod coverage;
+pub mod debugger;
+pub mod device;
+pub mod disasm;
+mod error;
+pub mod executable;
+mod instruction;
 mod machine;
+pub mod object;
+pub mod profile;
+mod reader;
+pub mod snapshot;
+pub mod symbols;
+pub mod trace;
 
+pub use error::*;
+pub use instruction::*;
 pub use machine::*;
@@ -1,27 +1,75 @@
-use std::io::{self, Write};
+use crate::coverage::Coverage;
+use crate::device::Device;
+use crate::executable::Executable;
+use crate::snapshot::Snapshot;
+use crate::trace::{MemWrite, RegWrite, Step, Tracer};
+use crate::{Error, ErrorKind, Instruction};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::ops::Range;
 
+/// Memory size of a machine created with [`Machine::new`].
 pub const MEMORY_SIZE: usize = 4096;
-const NREGS: usize = 16;
+/// Largest memory size, covering the whole 32-bit address space.
+pub const MAX_MEMORY_SIZE: u64 = 1 << 32;
+pub(crate) const NREGS: usize = 16;
+/// Size of the largest instruction.
+const MAX_INSTRUCTION_SIZE: usize = 4;
+/// Number of addresses covered by a page of the decode cache.
+const CACHE_PAGE_SIZE: usize = 4096;
 
-type Result<T, E = Error> = std::result::Result<T, E>;
+/// Instruction decoded at each address of a page of the decode cache, and
+/// its size.
+type CachePage = Box<[Option<(Instruction, u8)>; CACHE_PAGE_SIZE]>;
+
+pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
 
 pub struct Machine {
     regs: [u32; NREGS],
-    machine_memory: [u8; MEMORY_SIZE],
+    machine_memory: Box<[u8]>,
+    tracer: Option<Box<dyn Tracer>>,
+    /// Effects of the instruction being executed, recorded only when needed.
+    reg_writes: Vec<RegWrite>,
+    mem_writes: Vec<MemWrite>,
+    /// Number of instructions executed since the machine was created.
+    executed: u64,
+    /// Whether the arithmetic extension instructions are accepted.
+    arith_extension: bool,
+    devices: Vec<Mapping>,
+    /// Effects of the last executed instructions, most recent last.
+    journal: VecDeque<JournalEntry>,
+    journal_capacity: usize,
+    coverage: Option<Coverage>,
+    /// Exit status given by the last executed exit instruction.
+    exit_status: Option<u32>,
+    /// Pages of the decode cache by page number, if it is enabled. A page
+    /// is only allocated once an instruction in it is executed, and pages
+    /// past the end are not allocated yet.
+    decoded: Option<Vec<Option<CachePage>>>,
+}
+
+/// What is needed to undo an executed instruction.
+struct JournalEntry {
+    ip: u32,
+    regs: Vec<RegWrite>,
+    memory: Vec<MemWrite>,
+}
+
+/// A device and the addresses it answers to.
+struct Mapping {
+    range: Range<u64>,
+    device: Box<dyn Device>,
 }
 
+/// How a bounded run ended, see [`Machine::run_for`].
 #[derive(Debug)]
-pub enum Error {
-    /// Attempt to create a machine with too large a memory
-    MemoryOverflow,
-    /// Attempt to set a register out of r0 to r15
-    RegIndexOutOfRange,
-    /// Error while writing to the output
-    WriteError,
-    /// Unknown instruction
-    UnknownInstruction,
-    /// Memory adress out of range
-    MemAddressOutOfRange,
+pub enum RunOutcome {
+    /// The program executed an exit instruction.
+    Exited,
+    /// The instruction budget was spent before the program exited.
+    BudgetExhausted,
+    /// Execution stopped on an error.
+    Faulted(Error),
 }
 
 impl Machine {
@@ -30,37 +78,133 @@ impl Machine {
     ///
     /// # Errors
     /// This function returns an error when the memory exceeds `MEMORY_SIZE`.
+    #[allow(clippy::double_must_use)]
     #[must_use]
     pub fn new(memory: &[u8]) -> Result<Self> {
+        Self::with_memory_size(memory, MEMORY_SIZE)
+    }
+
+    /// Create a new machine in its reset state, with `size` bytes of
+    /// memory. The `memory` parameter will be copied at the beginning of the
+    /// machine memory.
+    ///
+    /// # Errors
+    /// `MemoryOverflow` is returned if `size` exceeds `MAX_MEMORY_SIZE`, and
+    /// `ProgramTooLarge` if the memory exceeds `size`.
+    pub fn with_memory_size(memory: &[u8], size: usize) -> Result<Self> {
         let regs: [u32; 16] = [0; 16];
 
-        if memory.len() > MEMORY_SIZE {
-            // MemoryOverflow
-            return std::result::Result::Err(Error::MemoryOverflow);
+        if size as u64 > MAX_MEMORY_SIZE {
+            return Err(ErrorKind::MemoryOverflow.into());
         }
-
-        let end = memory.len() as usize;
-        let mut machine_memory: [u8; MEMORY_SIZE] = [0; MEMORY_SIZE];
-        for i in 0..end {
-            machine_memory[i] = memory[i];
+        if memory.len() > size {
+            let end = memory.len() as u64;
+            return Err(ErrorKind::ProgramTooLarge { end, size }.into());
         }
+
+        let mut machine_memory = vec![0; size].into_boxed_slice();
+        machine_memory[..memory.len()].copy_from_slice(memory);
         std::result::Result::Ok(Machine {
             regs,
             machine_memory,
+            tracer: None,
+            reg_writes: Vec::new(),
+            mem_writes: Vec::new(),
+            executed: 0,
+            arith_extension: false,
+            devices: Vec::new(),
+            journal: VecDeque::new(),
+            journal_capacity: 0,
+            coverage: None,
+            exit_status: None,
+            decoded: None,
         })
     }
 
+    /// Create a new machine with `size` bytes of memory, holding an
+    /// executable file or a raw image, and ready to start it: see
+    /// [`Executable::parse`].
+    ///
+    /// # Errors
+    /// `InvalidExecutable` is returned if `image` is a malformed executable
+    /// file, `MemoryOverflow` if `size` exceeds `MAX_MEMORY_SIZE`, and
+    /// `ProgramTooLarge` if the program does not fit in memory.
+    pub fn load_image(image: &[u8], size: usize) -> Result<Self> {
+        Self::with_executable(&Executable::parse(image)?, size)
+    }
+
+    /// Create a new machine with `size` bytes of memory, holding the
+    /// sections of `executable` and in its initial state.
+    ///
+    /// # Errors
+    /// `MemoryOverflow` is returned if `size` exceeds `MAX_MEMORY_SIZE`, and
+    /// `ProgramTooLarge` if a section does not fit in memory.
+    pub fn with_executable(executable: &Executable, size: usize) -> Result<Self> {
+        let mut machine = Self::with_memory_size(&[], size)?;
+        executable.fits_in(size)?;
+        for section in &executable.sections {
+            machine.set_memory(section.address as usize, &section.bytes)?;
+        }
+        machine.regs = executable.regs;
+        machine.regs[0] = executable.entry;
+        machine.arith_extension = executable.arith_extension;
+        Ok(machine)
+    }
+
     /// Run until the program terminates or until an error happens.
-    /// If output instructions are run, they print on `fd`.
-    pub fn run_on<T: Write>(&mut self, fd: &mut T) -> Result<()> {
-        while !self.step_on(fd)? {}
+    /// Input instructions read from `input`, and output instructions print
+    /// on `output`.
+    pub fn run_io<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<()> {
+        while !self.step_io(input, output)? {}
         Ok(())
     }
 
     /// Run until the program terminates or until an error happens.
-    /// If output instructions are run, they print on standard output.
+    /// If output instructions are run, they print on `fd`. Input
+    /// instructions find the input empty.
+    pub fn run_on<T: Write>(&mut self, fd: &mut T) -> Result<()> {
+        self.run_io(&mut io::empty(), fd)
+    }
+
+    /// Run until the program terminates or until an error happens.
+    /// Input instructions read from standard input, and output
+    /// instructions print on standard output.
     pub fn run(&mut self) -> Result<()> {
-        self.run_on(&mut io::stdout().lock())
+        self.run_io(&mut io::stdin().lock(), &mut io::stdout().lock())
+    }
+
+    /// Run until the program terminates, an error happens, or `max_steps`
+    /// instructions have been executed, whichever comes first.
+    /// Input instructions read from `input`, and output instructions print
+    /// on `output`.
+    pub fn run_for_io<R: Read, W: Write>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+        max_steps: u64,
+    ) -> RunOutcome {
+        for _ in 0..max_steps {
+            match self.step_io(input, output) {
+                Ok(true) => return RunOutcome::Exited,
+                Ok(false) => (),
+                Err(e) => return RunOutcome::Faulted(e),
+            }
+        }
+        RunOutcome::BudgetExhausted
+    }
+
+    /// Similar to [`run_for_io`](Machine::run_for_io).
+    /// If output instructions are run, they print on `fd`. Input
+    /// instructions find the input empty.
+    pub fn run_for_on<T: Write>(&mut self, fd: &mut T, max_steps: u64) -> RunOutcome {
+        self.run_for_io(&mut io::empty(), fd, max_steps)
+    }
+
+    /// Similar to [`run_for_io`](Machine::run_for_io).
+    /// Input instructions read from standard input, and output
+    /// instructions print on standard output.
+    pub fn run_for(&mut self, max_steps: u64) -> RunOutcome {
+        self.run_for_io(&mut io::stdin().lock(), &mut io::stdout().lock(), max_steps)
     }
 
     /// Execute the next instruction by doing the following steps:
@@ -68,189 +212,464 @@ impl Machine {
     ///   - increment the IP by the size of the instruction
     ///   - execute the decoded instruction
     ///
-    /// If output instructions are run, they print on `fd`.
+    /// Input instructions read from `input`, and output instructions print
+    /// on `output`.
     /// If an error happens at either of those steps, an error is
     /// returned.
     ///
     /// In case of success, `true` is returned if the program is
     /// terminated (upon encountering an exit instruction), or
     /// `false` if the execution must continue.
-    pub fn step_on<T: Write>(&mut self, fd: &mut T) -> Result<bool> {
-        // exec_after_end_of_address_space
-        if (self.regs[0] as usize) >= MEMORY_SIZE {
-            return std::result::Result::Err(Error::UnknownInstruction);
+    pub fn step_io<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<bool> {
+        let ip = self.regs[0] as usize;
+        let (instruction, size) = self.fetch(ip)?;
+        if matches!(instruction, Instruction::Arith { .. }) && !self.arith_extension {
+            let code = &self.machine_memory[ip..ip + size];
+            return Err(Error::from(ErrorKind::UnknownOpcode { opcode: code[0] })
+                .at(ip as u32)
+                .with_bytes(code));
         }
-        // get the instruction in IP, note that the data storage uses little-endian
-        // theres no problem in reading 4 bytes every time
-        let instruction: [u8; 4];
-        if (self.regs[0] as usize) + 1 >= MEMORY_SIZE {
-            instruction = [self.machine_memory[(self.regs[0] as usize) + 0], 0, 0, 0];
-        } else if self.regs[0] as usize + 2 >= MEMORY_SIZE {
-            // MemoryOverflow
-            instruction = [
-                self.machine_memory[(self.regs[0] as usize) + 0],
-                self.machine_memory[(self.regs[0] as usize) + 1],
-                0,
-                0,
-            ];
-        } else if self.regs[0] as usize + 3 >= MEMORY_SIZE {
-            // MemoryOverflow
-            instruction = [
-                self.machine_memory[(self.regs[0] as usize) + 0],
-                self.machine_memory[(self.regs[0] as usize) + 1],
-                self.machine_memory[(self.regs[0] as usize) + 2],
-                0,
-            ];
-        } else {
-            instruction = [
-                self.machine_memory[(self.regs[0] as usize) + 0],
-                self.machine_memory[(self.regs[0] as usize) + 1],
-                self.machine_memory[(self.regs[0] as usize) + 2],
-                self.machine_memory[(self.regs[0] as usize) + 3],
-            ];
+        let fault = |e: Error| e.at(ip as u32).with_bytes(&instruction.encode());
+        self.regs[0] = self.regs[0].wrapping_add(size as u32);
+        self.reg_writes.clear();
+        self.mem_writes.clear();
+        let exited = self.execute(instruction, input, output).map_err(fault)?;
+        self.executed += 1;
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(ip as u32);
         }
-
-        // decode the instruction
-        if instruction[0] == 1 {
-            if (self.regs[0] as usize + 4) > MEMORY_SIZE {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
+        for mapping in &mut self.devices {
+            mapping.device.tick();
+        }
+        if let Some(tracer) = &mut self.tracer {
+            let step = Step {
+                ip: ip as u32,
+                instruction,
+                regs: &self.reg_writes,
+                memory: &self.mem_writes,
+            };
+            tracer
+                .trace(&step)
+                .map_err(|e| fault(ErrorKind::WriteFailure(e).into()))?;
+        }
+        if self.journal_capacity > 0 {
+            if self.journal.len() == self.journal_capacity {
+                self.journal.pop_front();
             }
-            // move if
-            if instruction[1] >= 16 || instruction[2] >= 16 || instruction[3] >= 16 {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
+            self.journal.push_back(JournalEntry {
+                ip: ip as u32,
+                regs: std::mem::take(&mut self.reg_writes),
+                memory: std::mem::take(&mut self.mem_writes),
+            });
+        }
+        Ok(exited)
+    }
+
+    /// Decode the instruction at `ip`, or take it from the decode cache.
+    fn fetch(&mut self, ip: usize) -> Result<(Instruction, usize)> {
+        let (page, offset) = (ip / CACHE_PAGE_SIZE, ip % CACHE_PAGE_SIZE);
+        let cached = self.decoded.as_ref().and_then(|d| d.get(page)?.as_ref());
+        if let Some(&Some((instruction, size))) = cached.map(|page| &page[offset]) {
+            return Ok((instruction, size.into()));
+        }
+        let code = self.machine_memory.get(ip..).unwrap_or_default();
+        let (instruction, size) =
+            Instruction::decode_for(code, self.arith_extension).map_err(|e| e.at(ip as u32))?;
+        if let Some(decoded) = &mut self.decoded {
+            if decoded.len() <= page {
+                decoded.resize_with(page + 1, || None);
             }
-            self.regs[0] += 4;
-            if self.regs[instruction[3] as usize] != 0 {
-                self.regs[instruction[1] as usize] = self.regs[instruction[2] as usize];
+            let page = decoded[page].get_or_insert_with(|| Box::new([None; CACHE_PAGE_SIZE]));
+            page[offset] = Some((instruction, size as u8));
+        }
+        Ok((instruction, size))
+    }
+
+    /// Drop the decoded instructions overlapping the `len` bytes of memory
+    /// starting at `address`, which are about to change.
+    fn invalidate(&mut self, address: usize, len: usize) {
+        if let Some(decoded) = &mut self.decoded {
+            let start = address.saturating_sub(MAX_INSTRUCTION_SIZE - 1);
+            for ip in start..address + len {
+                if let Some(Some(page)) = decoded.get_mut(ip / CACHE_PAGE_SIZE) {
+                    page[ip % CACHE_PAGE_SIZE] = None;
+                }
             }
-            std::result::Result::Ok(false)
-        } else if instruction[0] == 2 {
-            // store
-            self.regs[0] += 3;
-            // store the content of register rⱼ into the memory starting at address pointed by register rᵢ using little-endian representation.
-            if instruction[1] >= 16 || instruction[2] >= 16 {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
+        }
+    }
+
+    /// Similar to [`step_io`](Machine::step_io).
+    /// If output instructions are run, they print on `fd`. Input
+    /// instructions find the input empty.
+    pub fn step_on<T: Write>(&mut self, fd: &mut T) -> Result<bool> {
+        self.step_io(&mut io::empty(), fd)
+    }
+
+    /// Execute an already decoded instruction, the IP having already been
+    /// moved past it. Return `true` if the program is terminated.
+    fn execute<R: Read, W: Write>(
+        &mut self,
+        instruction: Instruction,
+        input: &mut R,
+        fd: &mut W,
+    ) -> Result<bool> {
+        match instruction {
+            Instruction::MoveIf { dst, src, cond } => {
+                if self.regs[cond as usize] != 0 {
+                    self.write_reg(dst, self.regs[src as usize]);
+                }
             }
-            let mut address: u32 = self.regs[instruction[1] as usize] as u32;
-            if (address as usize) + 3 >= MEMORY_SIZE {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
+            Instruction::Store { addr, src } => {
+                let address = self.regs[addr as usize] as usize;
+                self.store(address, 4, self.regs[src as usize])?;
             }
-            // register to copy
-            let ri = self.regs[instruction[2] as usize];
-            for i in 0..4 {
-                self.machine_memory[address as usize] = (ri >> (i * 8)) as u8;
-                address += 1;
+            Instruction::Load { dst, addr } => {
+                let address = self.regs[addr as usize] as usize;
+                let word = self.load(address, 4)?;
+                self.write_reg(dst, word);
             }
-            std::result::Result::Ok(false)
-        } else if instruction[0] == 3 {
-            // load
-            self.regs[0] += 3;
-            if (self.regs[0] as usize + 3) > MEMORY_SIZE {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
+            Instruction::StoreNarrow { width, addr, src } => {
+                let address = self.regs[addr as usize] as usize;
+                self.store(address, width.bytes(), self.regs[src as usize])?;
             }
-            if (instruction[2]) >= 16 {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
+            Instruction::LoadNarrow {
+                width,
+                signed,
+                dst,
+                addr,
+            } => {
+                let address = self.regs[addr as usize] as usize;
+                let value = self.load(address, width.bytes())?;
+                self.write_reg(dst, width.extend(value, signed));
             }
-            let mut address: usize = self.regs[instruction[2] as usize] as usize;
-            // address out of range
-            if address + 3 >= MEMORY_SIZE {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
+            Instruction::LoadImm { dst, imm } => self.write_reg(dst, imm as u32),
+            Instruction::Sub { dst, lhs, rhs } => {
+                self.write_reg(
+                    dst,
+                    self.regs[lhs as usize].wrapping_sub(self.regs[rhs as usize]),
+                );
             }
-            let mut word: u32 = 0;
-            for i in 0..4 {
-                word += (self.machine_memory[address] as u32) << (i * 8);
-                address += 1;
+            Instruction::Out { src } => {
+                let c = self.regs[src as usize] as u8 as char;
+                let mut buf = [0; 4];
+                fd.write_all(c.encode_utf8(&mut buf).as_bytes())
+                    .map_err(ErrorKind::WriteFailure)?;
             }
-            if (instruction[1]) >= 16 {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
+            Instruction::Exit => {
+                self.exit_status = Some(0);
+                return Ok(true);
             }
-            self.regs[instruction[1] as usize] = word;
-            std::result::Result::Ok(false)
-        } else if instruction[0] == 4 {
-            // loadimm
-            self.regs[0] += 4;
-            if (self.regs[0] as usize + 4) > MEMORY_SIZE {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
+            Instruction::ExitWith { src } => {
+                self.exit_status = Some(self.regs[src as usize]);
+                return Ok(true);
             }
-            let word: i16 = (instruction[2] as u16 + ((instruction[3] as u16) << 8)) as i16;
-            if (instruction[1]) >= 16 {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
+            Instruction::OutNumber { src } => {
+                write!(fd, "{}", self.regs[src as usize] as i32)
+                    .map_err(ErrorKind::WriteFailure)?;
             }
-            self.regs[instruction[1] as usize] = word as u32;
-            std::result::Result::Ok(false)
-        } else if instruction[0] == 5 {
-            // sub
-            self.regs[0] += 4;
-            if (self.regs[0] as usize + 4) > MEMORY_SIZE {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
+            Instruction::In { dst } => {
+                let byte = read_byte(input)?;
+                self.write_reg(dst, byte.map_or(u32::MAX, u32::from));
             }
-            // sub_with_wraparound_neg
-            if instruction[1] >= 16 || instruction[2] >= 16 || instruction[3] >= 16 {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
+            Instruction::InNumber { dst } => {
+                let number = read_number(input)?;
+                self.write_reg(dst, number as u32);
             }
-            self.regs[instruction[1] as usize] =
-                self.regs[instruction[2] as usize].wrapping_sub(self.regs[instruction[3] as usize]);
-            return std::result::Result::Ok(false);
-        } else if instruction[0] == 6 {
-            // out
-            self.regs[0] += 2;
-            if (self.regs[0] as usize + 4) > MEMORY_SIZE {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
+            Instruction::Arith { op, dst, lhs, rhs } => {
+                let value = op
+                    .apply(self.regs[lhs as usize], self.regs[rhs as usize])
+                    .ok_or(ErrorKind::DivisionByZero)?;
+                self.write_reg(dst, value);
             }
-            let c: char = self.regs[instruction[1] as usize] as u8 as char;
-            let mut buf = [0; 4]; // Buffer to hold UTF-8 encoding
-            let utf8_bytes = c.encode_utf8(&mut buf).as_bytes(); // Get UTF-8 bytes of the character
+        }
+        Ok(false)
+    }
 
-            if fd.write_all(utf8_bytes).is_err() {
-                // Handle WriteError
-                return std::result::Result::Err(Error::WriteError);
-            }
+    /// Whether the effects of instructions must be recorded.
+    fn recording(&self) -> bool {
+        self.tracer.is_some() || self.journal_capacity > 0
+    }
 
-            std::result::Result::Ok(false)
-        } else if instruction[0] == 7 {
-            if ((self.regs[0] + 1) as usize) > MEMORY_SIZE {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
-            }
-            self.regs[0] += 1;
-            // exit the current program
-            std::result::Result::Ok(true)
-        } else if instruction[0] == 8 {
-            self.regs[0] += 2;
-            if (self.regs[0] as usize + 4) > MEMORY_SIZE {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
-            }
-            if (instruction[1]) >= 16 {
-                return std::result::Result::Err(Error::MemAddressOutOfRange);
-            }
-            // out number 8 rᵢ: output the signed number stored in register rᵢ in decimal.
-            let mut number: i32 = self.regs[instruction[1] as usize] as i32;
-            if number < 0 {
-                if fd.write_all(&['-' as u8]).is_err() {
-                    // WriteError
-                    return std::result::Result::Err(Error::WriteError);
+    /// Write a register on behalf of the instruction being executed.
+    fn write_reg(&mut self, reg: u8, value: u32) {
+        if self.recording() {
+            self.reg_writes.push(RegWrite {
+                reg,
+                old: self.regs[reg as usize],
+                new: value,
+            });
+        }
+        self.regs[reg as usize] = value;
+    }
+
+    /// Find the device handling the `len` bytes starting at `address`, and
+    /// return it with the offset of `address` in its range.
+    ///
+    /// # Errors
+    /// `MisalignedAccess` is returned if the bytes are only partially
+    /// handled by a device.
+    fn device(&mut self, address: usize, len: usize) -> Result<Option<(&mut dyn Device, u32)>> {
+        let (address, end) = (address as u64, (address + len) as u64);
+        for mapping in &mut self.devices {
+            let range = &mapping.range;
+            if address < range.end && range.start < end {
+                if address < range.start || end > range.end {
+                    return Err(ErrorKind::MisalignedAccess { address, len }.into());
                 }
+                let offset = (address - range.start) as u32;
+                return Ok(Some((mapping.device.as_mut(), offset)));
             }
-            number = number.abs();
-            let mut digits: Vec<u8> = Vec::new();
-            while number > 0 {
-                digits.push((number % 10) as u8 + '0' as u8);
-                number /= 10;
-            }
-            digits.reverse();
-            if fd.write_all(&digits).is_err() {
-                // WriteError
-                return std::result::Result::Err(Error::WriteError);
+        }
+        Ok(None)
+    }
+
+    /// Read the `len` bytes (1, 2 or 4) starting at `address` as a
+    /// little-endian value.
+    fn load(&mut self, address: usize, len: usize) -> Result<u32> {
+        if let Some((device, offset)) = self.device(address, len)? {
+            let value = if len == 4 {
+                device.read_word(offset)
+            } else {
+                (0..len).try_fold(0, |value, i| {
+                    let byte = device.read_byte(offset + i as u32)?;
+                    Ok(value | u32::from(byte) << (8 * i))
+                })
+            };
+            return Ok(value.map_err(ErrorKind::ReadFailure)?);
+        }
+        let bytes =
+            self.machine_memory
+                .get(address..address + len)
+                .ok_or(ErrorKind::OutOfRange {
+                    address: address as u64,
+                    len,
+                })?;
+        let mut word = [0; 4];
+        word[..len].copy_from_slice(bytes);
+        Ok(u32::from_le_bytes(word))
+    }
+
+    /// Write the low `len` bytes (1, 2 or 4) of `value` in little-endian
+    /// order starting at `address`.
+    fn store(&mut self, address: usize, len: usize, value: u32) -> Result<()> {
+        if let Some((device, offset)) = self.device(address, len)? {
+            let written = if len == 4 {
+                device.write_word(offset, value)
+            } else {
+                (0..len).try_for_each(|i| {
+                    device.write_byte(offset + i as u32, (value >> (8 * i)) as u8)
+                })
+            };
+            return Ok(written.map_err(ErrorKind::WriteFailure)?);
+        }
+        self.invalidate(address, len);
+        let recording = self.recording();
+        let new_bytes = &value.to_le_bytes()[..len];
+        let bytes =
+            self.machine_memory
+                .get_mut(address..address + len)
+                .ok_or(ErrorKind::OutOfRange {
+                    address: address as u64,
+                    len,
+                })?;
+        if recording {
+            for (i, (old, &new)) in bytes.iter().zip(new_bytes).enumerate() {
+                if *old != new {
+                    self.mem_writes.push(MemWrite {
+                        addr: (address + i) as u32,
+                        old: *old,
+                        new,
+                    });
+                }
             }
-            std::result::Result::Ok(false)
-        } else {
-            std::result::Result::Err(Error::UnknownInstruction)
         }
+        bytes.copy_from_slice(new_bytes);
+        Ok(())
+    }
+
+    /// Map a device on `range`: loads and stores touching those addresses
+    /// are sent to the device instead of the memory, even if the range is
+    /// beyond the end of the memory. Instructions are still fetched from
+    /// the memory, and device accesses are not reported to the tracer.
+    ///
+    /// # Errors
+    /// `InvalidDeviceRange` is returned if `range` is empty, goes past the
+    /// end of the address space or overlaps the range of an already mapped
+    /// device.
+    pub fn map_device(&mut self, range: Range<u64>, device: Box<dyn Device>) -> Result<()> {
+        if range.is_empty()
+            || range.end > MAX_MEMORY_SIZE
+            || self
+                .devices
+                .iter()
+                .any(|m| range.start < m.range.end && m.range.start < range.end)
+        {
+            return Err(ErrorKind::InvalidDeviceRange.into());
+        }
+        self.devices.push(Mapping { range, device });
+        Ok(())
     }
 
-    /// Similar to [`step_on`](Machine::step_on).
-    /// If output instructions are run, they print on standard output.
+    /// Install a tracer, called after every executed instruction, in place
+    /// of the previous one if any.
+    pub fn set_tracer(&mut self, tracer: impl Tracer + 'static) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    /// Remove the tracer and return it.
+    pub fn remove_tracer(&mut self) -> Option<Box<dyn Tracer>> {
+        self.tracer.take()
+    }
+
+    /// Similar to [`step_io`](Machine::step_io).
+    /// Input instructions read from standard input, and output
+    /// instructions print on standard output.
     pub fn step(&mut self) -> Result<bool> {
-        self.step_on(&mut io::stdout().lock())
+        self.step_io(&mut io::stdin().lock(), &mut io::stdout().lock())
+    }
+
+    /// Enable or disable the arithmetic extension (opcodes 32 to 42). It is
+    /// disabled by default, those opcodes being unknown instructions of the
+    /// original instruction set.
+    pub fn set_arith_extension(&mut self, enabled: bool) {
+        self.arith_extension = enabled;
+    }
+
+    /// Whether the arithmetic extension is enabled.
+    #[must_use]
+    pub fn arith_extension(&self) -> bool {
+        self.arith_extension
+    }
+
+    /// Enable or disable the decode cache, disabled by default. When it is
+    /// enabled, an instruction is decoded the first time it is executed and
+    /// taken from the cache afterwards, which makes loops faster. Writes
+    /// to the memory drop the instructions they overlap from the cache, so
+    /// that self-modifying programs behave the same.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decoded = enabled.then(Vec::new);
+    }
+
+    /// Whether the decode cache is enabled.
+    #[must_use]
+    pub fn decode_cache(&self) -> bool {
+        self.decoded.is_some()
+    }
+
+    /// Exit status of the program: 0 after `exit`, the value of the
+    /// register after `exit rᵢ`, or `None` if the program has not exited.
+    #[must_use]
+    pub fn exit_status(&self) -> Option<u32> {
+        self.exit_status
+    }
+
+    /// Number of instructions successfully executed so far, across all runs.
+    #[must_use]
+    pub fn executed_instructions(&self) -> u64 {
+        self.executed
+    }
+
+    /// Record the address of every executed instruction into `coverage`,
+    /// in place of the previous collector if any.
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    /// The coverage collector, if any.
+    #[must_use]
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Stop collecting coverage and return the collector.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// Keep the effects of the last `capacity` executed instructions in an
+    /// undo journal, so that they can be undone with
+    /// [`step_back`](Machine::step_back). A capacity of 0, the default,
+    /// disables the journal. Shrinking the capacity drops the oldest
+    /// entries.
+    pub fn set_journal_capacity(&mut self, capacity: usize) {
+        self.journal_capacity = capacity;
+        while self.journal.len() > capacity {
+            self.journal.pop_front();
+        }
+    }
+
+    /// Number of executed instructions that can currently be undone.
+    #[must_use]
+    pub fn journal_len(&self) -> usize {
+        self.journal.len()
+    }
+
+    /// Undo the last executed instruction, restoring the registers and
+    /// memory bytes it changed. Changes made with `set_reg` or `set_memory`,
+    /// input and output, and the state of devices are not undone. Return
+    /// `false` if the journal is empty.
+    pub fn step_back(&mut self) -> bool {
+        let Some(entry) = self.journal.pop_back() else {
+            return false;
+        };
+        for write in entry.memory.iter().rev() {
+            self.invalidate(write.addr as usize, 1);
+            self.machine_memory[write.addr as usize] = write.old;
+        }
+        for write in entry.regs.iter().rev() {
+            self.regs[write.reg as usize] = write.old;
+        }
+        self.regs[0] = entry.ip;
+        self.executed -= 1;
+        self.exit_status = None;
+        true
+    }
+
+    /// Step back until the instruction at `ip` is about to be executed
+    /// again. Return `false` if the journal runs out before, the machine
+    /// being then in the oldest state it can go back to.
+    pub fn run_back_to(&mut self, ip: u32) -> bool {
+        while self.step_back() {
+            if self.regs[0] == ip {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Capture the state of the machine: registers, memory, number of
+    /// executed instructions and enabled extensions.
+    #[must_use]
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            regs: self.regs,
+            memory: self.machine_memory.to_vec(),
+            executed: self.executed,
+            arith_extension: self.arith_extension,
+        }
+    }
+
+    /// Put the machine back in the state captured by `snapshot`, memory
+    /// size included. Mapped devices and the tracer are kept, and the undo
+    /// journal and the exit status are cleared.
+    ///
+    /// # Errors
+    /// `MemoryOverflow` is returned if the snapshot memory is larger than
+    /// `MAX_MEMORY_SIZE`, in which case the machine is left unchanged.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        if snapshot.memory.len() as u64 > MAX_MEMORY_SIZE {
+            return Err(ErrorKind::MemoryOverflow.into());
+        }
+        self.regs = snapshot.regs;
+        self.machine_memory = snapshot.memory.clone().into_boxed_slice();
+        if let Some(decoded) = &mut self.decoded {
+            decoded.clear();
+        }
+        self.executed = snapshot.executed;
+        self.arith_extension = snapshot.arith_extension;
+        self.journal.clear();
+        self.exit_status = None;
+        Ok(())
     }
 
     /// Reference onto the machine current set of registers.
@@ -260,13 +679,32 @@ impl Machine {
     }
 
     /// Sets a register to the given value.
+    ///
+    /// # Errors
+    /// `InvalidRegister` is returned if `reg` is not in r0 to r15.
     pub fn set_reg(&mut self, reg: usize, value: u32) -> Result<()> {
-        if reg > NREGS {
-            // RegIndexOutOfRange
-            return std::result::Result::Err(Error::RegIndexOutOfRange);
+        if reg >= NREGS {
+            return Err(ErrorKind::InvalidRegister { reg }.into());
         }
-        self.regs[reg] = value as u32;
-        std::result::Result::Ok(())
+        self.regs[reg] = value;
+        Ok(())
+    }
+
+    /// Copy `bytes` into the machine memory, starting at `address`.
+    ///
+    /// # Errors
+    /// `OutOfRange` is returned if the bytes do not fit in memory.
+    pub fn set_memory(&mut self, address: usize, bytes: &[u8]) -> Result<()> {
+        self.machine_memory
+            .get_mut(address..)
+            .and_then(|memory| memory.get_mut(..bytes.len()))
+            .ok_or(ErrorKind::OutOfRange {
+                address: address as u64,
+                len: bytes.len(),
+            })?
+            .copy_from_slice(bytes);
+        self.invalidate(address, bytes.len());
+        Ok(())
     }
 
     /// Reference onto the machine current memory.
@@ -275,3 +713,41 @@ impl Machine {
         &self.machine_memory
     }
 }
+
+/// Read a single byte, or `None` at the end of the input.
+fn read_byte<R: Read>(input: &mut R) -> Result<Option<u8>> {
+    let mut byte = 0;
+    loop {
+        match input.read(std::slice::from_mut(&mut byte)) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte)),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(ErrorKind::ReadFailure(e).into()),
+        }
+    }
+}
+
+/// Read a signed decimal number preceded by optional whitespace. The
+/// character following the number, which must be whitespace, is consumed.
+fn read_number<R: Read>(input: &mut R) -> Result<i32> {
+    let mut byte = read_byte(input)?;
+    while byte.is_some_and(|b| b.is_ascii_whitespace()) {
+        byte = read_byte(input)?;
+    }
+    let negative = byte == Some(b'-');
+    if matches!(byte, Some(b'-' | b'+')) {
+        byte = read_byte(input)?;
+    }
+    let mut number: i64 = 0;
+    let mut digits = 0;
+    while let Some(digit @ b'0'..=b'9') = byte {
+        number = (number * 10 + i64::from(digit - b'0')).min(1 << 32);
+        digits += 1;
+        byte = read_byte(input)?;
+    }
+    if digits == 0 || byte.is_some_and(|b| !b.is_ascii_whitespace()) {
+        return Err(ErrorKind::InvalidNumber.into());
+    }
+    Ok(i32::try_from(if negative { -number } else { number })
+        .map_err(|_| ErrorKind::InvalidNumber)?)
+}
@@ -1,11 +1,820 @@
-fn main() -> Result<(), interpreter::Error> {
-    // Take a filename as argument on the command line
-    let filename = std::env::args().nth(1).unwrap();
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use interpreter::asm::{self, Program};
+use interpreter::check::{self, Block};
+use interpreter::coverage::{Coverage, Report};
+use interpreter::debugger::{self, Debugger};
+use interpreter::executable::Executable;
+use interpreter::object::{self, Object};
+use interpreter::profile::Profiler;
+use interpreter::snapshot::Snapshot;
+use interpreter::symbols::SymbolTable;
+use interpreter::trace::{JsonTracer, TextTracer};
+use interpreter::{disasm, ErrorKind, Instruction, Machine, RunOutcome};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
-    // Read content to buffer
-    let buffer = std::fs::read(filename).unwrap();
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  the program executed `exit`, or a command other than running succeeded
+  1  the machine faulted
+  2  invalid command line
+  3  failure reading or writing a file or stream
+  4  the program was still running after the step limit
+  5  invalid program, listing or snapshot
+  6  with `--no-exit-status`, the program executed `exit rN` with a nonzero rN
+Otherwise a program exiting with `exit rN` gives the value of rN as exit
+code, truncated to its low 8 bits by Unix systems, which may be mistaken for
+one of the codes above.";
 
-    // Create a machine with this memory content and run it
-    let mut machine = interpreter::Machine::new(&buffer).unwrap();
-    machine.run()
+/// Number of instructions that can be undone in the debugger.
+const DEBUG_JOURNAL_CAPACITY: usize = 100_000;
+
+/// Run, inspect and debug programs of the virtual machine. Programs are
+/// raw `.bin` images, executable files, or `.dis` listings which are
+/// assembled on the fly. `vm PROGRAM` is short for `vm run PROGRAM`.
+#[derive(Parser)]
+#[command(
+    version,
+    about,
+    after_help = EXIT_CODES,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    arg_required_else_help = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Program to run when no command is given
+    #[arg(required = true)]
+    program: Option<PathBuf>,
+    #[command(flatten)]
+    machine: MachineArgs,
+    #[command(flatten)]
+    exec: ExecArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a program
+    Run(RunArgs),
+    /// Run a program, tracing the executed instructions on the standard
+    /// error unless `--trace` is given
+    Trace(RunArgs),
+    /// Resume the execution saved in a snapshot
+    Resume {
+        snapshot: PathBuf,
+        /// Set a register after restoring the snapshot, e.g. `r10=12`
+        #[arg(short, long = "reg", value_name = "rN=VALUE", value_parser = parse_reg)]
+        regs: Vec<(usize, u32)>,
+        #[command(flatten)]
+        exec: ExecArgs,
+    },
+    /// Assemble a listing into a program image
+    Asm {
+        listing: PathBuf,
+        /// Output image, the listing with a `.bin` extension by default, or
+        /// `.o` with `--object`
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Produce a relocatable object file, to be given to `link`
+        #[arg(short = 'c', long, conflicts_with = "executable")]
+        object: bool,
+        #[command(flatten)]
+        executable: ExecutableArgs,
+    },
+    /// Link object files into a program image, the first one starting at
+    /// address 0. Listings are assembled on the fly.
+    Link {
+        #[arg(required = true)]
+        objects: Vec<PathBuf>,
+        /// Output image
+        #[arg(short, long)]
+        output: PathBuf,
+        #[command(flatten)]
+        executable: ExecutableArgs,
+    },
+    /// Print the listing of a program image
+    Disasm {
+        program: PathBuf,
+        /// Memory size in bytes
+        #[arg(long, value_name = "BYTES", default_value_t = interpreter::MEMORY_SIZE)]
+        memory_size: usize,
+        /// List the instruction sequences of pseudo-instructions such as
+        /// `call` or `push` as these pseudo-instructions
+        #[arg(long)]
+        fold: bool,
+        /// Scratch register of the pseudo-instructions
+        #[arg(long, value_name = "rN", default_value = "r3", value_parser = parse_register, requires = "fold")]
+        scratch: u8,
+    },
+    /// Debug a program, interactively or by running a command file
+    Debug {
+        program: PathBuf,
+        #[command(flatten)]
+        machine: MachineArgs,
+        /// Execute the debugger commands of a file
+        #[arg(short = 'x', long, value_name = "FILE")]
+        commands: Option<PathBuf>,
+    },
+    /// Describe a program: size, code, data, labels and extensions used
+    Info {
+        program: PathBuf,
+        /// Memory size in bytes
+        #[arg(long, value_name = "BYTES", default_value_t = interpreter::MEMORY_SIZE)]
+        memory_size: usize,
+    },
+    /// Check a program before running it: rebuild its control-flow graph,
+    /// and report invalid instructions in reachable code, jumps outside
+    /// the memory or into an instruction, and unreachable code
+    Check {
+        program: PathBuf,
+        /// Memory size in bytes
+        #[arg(long, value_name = "BYTES", default_value_t = interpreter::MEMORY_SIZE)]
+        memory_size: usize,
+        /// Enable the arithmetic extension
+        #[arg(long)]
+        arith: bool,
+        /// Print the basic blocks of the control-flow graph
+        #[arg(long)]
+        cfg: bool,
+    },
+    /// Run a program under the profiler, printing the report on the
+    /// standard error
+    Profile {
+        program: PathBuf,
+        #[command(flatten)]
+        machine: MachineArgs,
+        #[arg(long, value_name = "N")]
+        max_steps: Option<u64>,
+        /// Write the call stacks in the collapsed format of flame graphs
+        #[arg(long, value_name = "FILE")]
+        collapsed: Option<PathBuf>,
+    },
+    /// Run a program once per input file, or once with an empty input, and
+    /// print its listing annotated with the coverage of those runs
+    Coverage {
+        program: PathBuf,
+        #[command(flatten)]
+        machine: MachineArgs,
+        /// Input of one run
+        #[arg(long, value_name = "FILE")]
+        input: Vec<PathBuf>,
+        #[arg(long, value_name = "N")]
+        max_steps: Option<u64>,
+        /// Also write the coverage in the LCOV format
+        #[arg(long, value_name = "FILE")]
+        lcov: Option<PathBuf>,
+    },
+}
+
+/// Setup of a new machine.
+#[derive(Args)]
+struct MachineArgs {
+    /// Set a register before running, e.g. `r10=12`
+    #[arg(short, long = "reg", value_name = "rN=VALUE", value_parser = parse_reg)]
+    regs: Vec<(usize, u32)>,
+    /// Memory size in bytes
+    #[arg(long, value_name = "BYTES", default_value_t = interpreter::MEMORY_SIZE)]
+    memory_size: usize,
+    /// Enable the arithmetic extension
+    #[arg(long)]
+    arith: bool,
+    /// Decode each instruction once rather than at every execution, which
+    /// runs loops faster
+    #[arg(long)]
+    decode_cache: bool,
+}
+
+/// Format of a produced program.
+#[derive(Args)]
+struct ExecutableArgs {
+    /// Produce an executable file holding the entry point, the initial
+    /// registers and the labels, rather than a raw image
+    #[arg(long)]
+    executable: bool,
+    /// Label of the entry point of the executable
+    #[arg(long, value_name = "LABEL", requires = "executable")]
+    entry: Option<String>,
+    /// Set an initial register of the executable, e.g. `r2=4096`
+    #[arg(short, long = "reg", value_name = "rN=VALUE", value_parser = parse_reg, requires = "executable")]
+    regs: Vec<(usize, u32)>,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    program: PathBuf,
+    #[command(flatten)]
+    machine: MachineArgs,
+    #[command(flatten)]
+    exec: ExecArgs,
+}
+
+/// Control of an execution.
+#[derive(Args)]
+struct ExecArgs {
+    /// Stop after executing N instructions
+    #[arg(long, value_name = "N")]
+    max_steps: Option<u64>,
+    /// Print the registers on the standard error when the execution stops
+    #[arg(long)]
+    print_regs: bool,
+    /// Trace the executed instructions in a file, `-` being the standard
+    /// error
+    #[arg(long, value_name = "FILE")]
+    trace: Option<String>,
+    #[arg(long, value_enum, default_value_t = TraceFormat::Text)]
+    trace_format: TraceFormat,
+    /// Save a snapshot of the machine when the execution stops
+    #[arg(long, value_name = "FILE")]
+    save_state_on_exit: Option<PathBuf>,
+    /// Exit with code 6 rather than with the status given by the program
+    /// to `exit rN` when it is nonzero, so that it cannot be mistaken for
+    /// another exit code
+    #[arg(long)]
+    no_exit_status: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TraceFormat {
+    Text,
+    Json,
+}
+
+/// Why a command failed, which determines the exit code.
+enum Failure {
+    Fault(interpreter::Error),
+    Io(String),
+    StillRunning(u64),
+    Invalid(String),
+    ExitStatus(u32),
+}
+
+impl Failure {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Fault(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::ReadFailure(_) | ErrorKind::WriteFailure(_)
+                ) =>
+            {
+                3
+            }
+            Failure::Fault(_) => 1,
+            Failure::Io(_) => 3,
+            Failure::StillRunning(_) => 4,
+            Failure::Invalid(_) => 5,
+            Failure::ExitStatus(_) => 6,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Fault(e) => write!(f, "{e}"),
+            Failure::Io(message) | Failure::Invalid(message) => write!(f, "{message}"),
+            Failure::StillRunning(steps) => {
+                write!(f, "program still running after {steps} instructions")
+            }
+            Failure::ExitStatus(status) => write!(f, "program exited with status {status}"),
+        }
+    }
+}
+
+type Result<T, E = Failure> = std::result::Result<T, E>;
+
+fn main() {
+    let cli = Cli::parse();
+    let command = match (cli.command, cli.program) {
+        (Some(command), _) => command,
+        (None, Some(program)) => Command::Run(RunArgs {
+            program,
+            machine: cli.machine,
+            exec: cli.exec,
+        }),
+        (None, None) => unreachable!("clap requires a program or a command"),
+    };
+    let result = match command {
+        Command::Run(args) => run(args, None),
+        Command::Trace(args) => {
+            let trace = args.exec.trace.clone().unwrap_or_else(|| "-".to_owned());
+            run(args, Some(trace))
+        }
+        Command::Resume {
+            snapshot,
+            regs,
+            exec,
+        } => resume(&snapshot, &regs, exec),
+        Command::Asm {
+            listing,
+            output,
+            object,
+            executable,
+        } => asm(&listing, output, object, &executable).map(|()| 0),
+        Command::Link {
+            objects,
+            output,
+            executable,
+        } => link(&objects, &output, &executable).map(|()| 0),
+        Command::Disasm {
+            program,
+            memory_size,
+            fold,
+            scratch,
+        } => load_image(&program, memory_size).map(|(bytes, _)| {
+            if fold {
+                print!("{}", disasm::disassemble_folded(&bytes, scratch));
+            } else {
+                print!("{}", disasm::disassemble(&bytes));
+            }
+            0
+        }),
+        Command::Debug {
+            program,
+            machine,
+            commands,
+        } => debug(&program, &machine, commands.as_deref()).map(|()| 0),
+        Command::Info {
+            program,
+            memory_size,
+        } => info(&program, memory_size).map(|()| 0),
+        Command::Check {
+            program,
+            memory_size,
+            arith,
+            cfg,
+        } => check(&program, memory_size, arith, cfg).map(|()| 0),
+        Command::Profile {
+            program,
+            machine,
+            max_steps,
+            collapsed,
+        } => profile(&program, &machine, max_steps, collapsed.as_deref()).map(|()| 0),
+        Command::Coverage {
+            program,
+            machine,
+            input,
+            max_steps,
+            lcov,
+        } => coverage(&program, &machine, &input, max_steps, lcov.as_deref()).map(|()| 0),
+    };
+    match result {
+        Ok(status) => process::exit(status as i32),
+        Err(failure) => {
+            eprintln!("Error: {failure}");
+            process::exit(failure.exit_code());
+        }
+    }
+}
+
+/// Parse a register assignment such as `r10=12`, `r3=-1` or `r4=0x10`.
+fn parse_reg(text: &str) -> Result<(usize, u32), String> {
+    let (reg, value) = text
+        .split_once('=')
+        .ok_or_else(|| format!("expected rN=VALUE, found `{text}`"))?;
+    let reg = parse_register(reg)?.into();
+    let value = debugger::parse_number(value).ok_or_else(|| format!("invalid value `{value}`"))?;
+    Ok((reg, value))
+}
+
+/// Parse a register name such as `r3`.
+fn parse_register(text: &str) -> Result<u8, String> {
+    debugger::parse_register(text)
+        .map(|reg| reg as u8)
+        .ok_or_else(|| format!("invalid register `{text}`"))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| Failure::Io(format!("{}: {e}", path.display())))
+}
+
+fn write(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    std::fs::write(path, contents).map_err(|e| Failure::Io(format!("{}: {e}", path.display())))
+}
+
+/// Create a machine loaded with `executable` as described by `args`.
+fn new_machine(executable: &Executable, args: &MachineArgs) -> Result<Machine> {
+    let mut machine = Machine::with_executable(executable, args.memory_size)
+        .map_err(|e| Failure::Invalid(format!("{e}")))?;
+    machine.set_arith_extension(args.arith || executable.arith_extension);
+    machine.set_decode_cache(args.decode_cache);
+    for &(reg, value) in &args.regs {
+        machine.set_reg(reg, value).map_err(Failure::Fault)?;
+    }
+    Ok(machine)
+}
+
+/// Run a program, tracing its execution on `trace` if given, or on the
+/// file of `--trace`. Return the exit status of the program.
+fn run(args: RunArgs, trace: Option<String>) -> Result<u32> {
+    let (program, executable) = load_program(&args.program, args.machine.memory_size)?;
+    let machine = new_machine(&executable, &args.machine)?;
+    let symbols = SymbolTable::new(&program.labels);
+    execute(
+        machine,
+        &args.exec,
+        trace.or(args.exec.trace.clone()),
+        &symbols,
+    )
+}
+
+/// Resume the execution saved in a snapshot. Return the exit status of the
+/// program.
+fn resume(path: &Path, regs: &[(usize, u32)], exec: ExecArgs) -> Result<u32> {
+    let snapshot = Snapshot::decode(&read(path)?)
+        .map_err(|e| Failure::Invalid(format!("{}: {e}", path.display())))?;
+    let mut machine = Machine::new(&[]).map_err(Failure::Fault)?;
+    machine
+        .restore(&snapshot)
+        .map_err(|e| Failure::Invalid(format!("{}: {e}", path.display())))?;
+    for &(reg, value) in regs {
+        machine.set_reg(reg, value).map_err(Failure::Fault)?;
+    }
+    let trace = exec.trace.clone();
+    execute(machine, &exec, trace, &SymbolTable::default())
+}
+
+/// Execute a machine on the standard input and output until it stops, as
+/// controlled by `exec`, and return the exit status of the program, which
+/// is a failure if nonzero with `--no-exit-status`. A `-` trace
+/// file stands for the standard error. Addresses in the trace and errors
+/// are named with `symbols`.
+fn execute(
+    mut machine: Machine,
+    exec: &ExecArgs,
+    trace: Option<String>,
+    symbols: &SymbolTable,
+) -> Result<u32> {
+    if let Some(trace) = trace {
+        let out: Box<dyn Write> = if trace == "-" {
+            Box::new(io::stderr())
+        } else {
+            let file = File::create(&trace).map_err(|e| Failure::Io(format!("{trace}: {e}")))?;
+            Box::new(BufWriter::new(file))
+        };
+        match (exec.trace_format, symbols.is_empty()) {
+            (TraceFormat::Text, true) => machine.set_tracer(TextTracer::new(out)),
+            (TraceFormat::Text, false) => {
+                machine.set_tracer(TextTracer::with_symbols(out, symbols.clone()));
+            }
+            (TraceFormat::Json, true) => machine.set_tracer(JsonTracer::new(out)),
+            (TraceFormat::Json, false) => {
+                machine.set_tracer(JsonTracer::with_symbols(out, symbols.clone()));
+            }
+        }
+    }
+    let max_steps = exec.max_steps.unwrap_or(u64::MAX);
+    let outcome = machine.run_for(max_steps);
+    // Flush a buffered trace
+    drop(machine.remove_tracer());
+    if let Some(path) = &exec.save_state_on_exit {
+        write(path, machine.snapshot().encode())?;
+    }
+    if exec.print_regs {
+        eprint!("{}", debugger::format_regs(machine.regs()));
+    }
+    match outcome {
+        RunOutcome::Exited => match machine.exit_status().unwrap_or_default() {
+            status if status == 0 || !exec.no_exit_status => Ok(status),
+            status => Err(Failure::ExitStatus(status)),
+        },
+        RunOutcome::BudgetExhausted => Err(Failure::StillRunning(max_steps)),
+        RunOutcome::Faulted(e) => Err(Failure::Fault(e.symbolize(symbols))),
+    }
+}
+
+/// Assemble a `.dis` listing into a `.bin` image.
+fn asm(
+    input: &Path,
+    output: Option<PathBuf>,
+    object: bool,
+    executable: &ExecutableArgs,
+) -> Result<()> {
+    if object {
+        let output = output.unwrap_or_else(|| input.with_extension("o"));
+        return write(&output, load_object(input)?.encode());
+    }
+    let output = output.unwrap_or_else(|| input.with_extension("bin"));
+    let source = read_to_string(input)?;
+    let program =
+        asm::assemble(&source).map_err(|e| Failure::Invalid(format!("{}:{e}", input.display())))?;
+    write_program(&output, program, executable)
+}
+
+/// Write `program` as a raw image, or as an executable file if requested.
+fn write_program(output: &Path, program: Program, args: &ExecutableArgs) -> Result<()> {
+    if !args.executable {
+        return write(output, program.bytes);
+    }
+    let mut executable = Executable::from_program(&program);
+    for &(reg, value) in &args.regs {
+        executable.regs[reg] = value;
+    }
+    executable.entry = executable.regs[0];
+    if let Some(entry) = &args.entry {
+        executable.entry = *program
+            .labels
+            .get(entry)
+            .ok_or_else(|| Failure::Invalid(format!("undefined label `{entry}`")))?;
+    }
+    write(output, executable.encode())
+}
+
+/// Load an object file, or assemble a listing into an object.
+fn load_object(input: &Path) -> Result<Object> {
+    if input.extension().is_some_and(|ext| ext == "dis") {
+        let source = read_to_string(input)?;
+        return asm::assemble_object(&source)
+            .map_err(|e| Failure::Invalid(format!("{}:{e}", input.display())));
+    }
+    Object::decode(&read(input)?).map_err(|e| Failure::Invalid(format!("{}: {e}", input.display())))
+}
+
+fn link(inputs: &[PathBuf], output: &Path, executable: &ExecutableArgs) -> Result<()> {
+    let objects = inputs
+        .iter()
+        .map(|input| load_object(input))
+        .collect::<Result<Vec<_>>>()?;
+    let program = object::link(&objects).map_err(|e| {
+        // Name modules by their file rather than their index
+        let module = |index: usize| inputs[index].display().to_string();
+        Failure::Invalid(match e {
+            object::Error::DuplicateSymbol {
+                name,
+                modules: (first, second),
+            } => format!(
+                "symbol `{name}` is exported by both {} and {}",
+                module(first),
+                module(second)
+            ),
+            object::Error::UnresolvedSymbol {
+                name,
+                module: index,
+            } => {
+                format!("{}: unresolved symbol `{name}`", module(index))
+            }
+            object::Error::AddressOverflow {
+                address,
+                module: index,
+            } => format!(
+                "{}: address {address} does not fit in an immediate",
+                module(index)
+            ),
+            object::Error::InvalidObject => e.to_string(),
+        })
+    })?;
+    write_program(output, program, executable)
+}
+
+fn read_to_string(path: &Path) -> Result<String> {
+    String::from_utf8(read(path)?)
+        .map_err(|_| Failure::Invalid(format!("{}: not UTF-8", path.display())))
+}
+
+/// Load an executable file or a raw image.
+fn load_executable(input: &Path) -> Result<Executable> {
+    Executable::parse(&read(input)?)
+        .map_err(|e| Failure::Invalid(format!("{}: {e}", input.display())))
+}
+
+/// Load an executable file or a raw image, along with its memory image in
+/// a memory of `memory_size` bytes.
+fn load_image(input: &Path, memory_size: usize) -> Result<(Vec<u8>, Executable)> {
+    let executable = load_executable(input)?;
+    let bytes = executable
+        .memory(memory_size)
+        .map_err(|e| Failure::Invalid(format!("{e}")))?;
+    Ok((bytes, executable))
+}
+
+/// Load a program for a tool working on labels, along with the executable
+/// to run: a listing is assembled, an executable file gets its symbols as
+/// labels, and a raw `.bin` image gets the labels of the `.dis` listing
+/// next to it if there is a matching one, or synthesized labels otherwise.
+fn load_program(input: &Path, memory_size: usize) -> Result<(Program, Executable)> {
+    let (_, program, executable) = load_listing(input, memory_size)?;
+    Ok((program, executable))
+}
+
+/// Like [`load_program`], also returning the listing the labels come from,
+/// a disassembly of the image if there is no matching listing.
+fn load_listing(input: &Path, memory_size: usize) -> Result<(String, Program, Executable)> {
+    let read_listing = |path: &Path| {
+        std::fs::read_to_string(path).map(|s| asm::assemble(&s).map(|program| (s, program)))
+    };
+    if input.extension().is_some_and(|ext| ext == "dis") {
+        let source = read_to_string(input)?;
+        let program = asm::assemble(&source)
+            .map_err(|e| Failure::Invalid(format!("{}:{e}", input.display())))?;
+        let executable = listing_executable(&program);
+        return Ok((source, program, executable));
+    }
+    let (bytes, executable) = load_image(input, memory_size)?;
+    if let Ok(Ok((source, program))) = read_listing(&input.with_extension("dis")) {
+        if program.bytes == bytes {
+            // A raw image is split into code and data by its listing
+            let executable = if executable == Executable::raw(&bytes) {
+                listing_executable(&program)
+            } else {
+                executable
+            };
+            return Ok((source, program, executable));
+        }
+    }
+    let source = disasm::disassemble(&bytes);
+    let program = asm::assemble(&source).unwrap_or_default();
+    let labels = if executable.symbols.is_empty() {
+        program.labels
+    } else {
+        executable.symbols.clone()
+    };
+    Ok((
+        source,
+        Program {
+            bytes,
+            labels,
+            lines: program.lines,
+            ranges: program.ranges,
+        },
+        executable,
+    ))
+}
+
+/// Executable of an assembled listing, which tells code from data, the
+/// arithmetic extension being left to the command line as for raw images.
+fn listing_executable(program: &Program) -> Executable {
+    Executable {
+        arith_extension: false,
+        ..Executable::from_program(program)
+    }
+}
+
+/// Debug a program, interactively or by running a command file.
+fn debug(input: &Path, args: &MachineArgs, script: Option<&Path>) -> Result<()> {
+    let (program, executable) = load_program(input, args.memory_size)?;
+    let mut machine = new_machine(&executable, args)?;
+    machine.set_journal_capacity(DEBUG_JOURNAL_CAPACITY);
+    let mut debugger = Debugger::new(machine, program.bytes.len(), program.labels);
+    let mut out = io::stdout().lock();
+    let result = match script {
+        Some(script) => {
+            let file = File::open(script)
+                .map_err(|e| Failure::Io(format!("{}: {e}", script.display())))?;
+            debugger.run(BufReader::new(file), &mut out, true)
+        }
+        None => debugger.run(io::stdin().lock(), &mut out, false),
+    };
+    result.map_err(|e| Failure::Io(e.to_string()))
+}
+
+/// Describe a program: its size, how much of it is code and data, its
+/// labels and the extensions its instructions need.
+fn info(input: &Path, memory_size: usize) -> Result<()> {
+    let (program, _) = load_program(input, memory_size)?;
+    let instructions = program.instructions();
+    let code: usize = program
+        .ranges
+        .values()
+        .map(|range| (range.end - range.start) as usize)
+        .sum();
+    let arith = instructions
+        .iter()
+        .any(|(_, instruction, _)| matches!(instruction, Instruction::Arith { .. }));
+    println!("size: {} bytes", program.bytes.len());
+    println!("code: {code} bytes, {} instructions", instructions.len());
+    println!("data: {} bytes", program.bytes.len() - code);
+    println!("labels: {}", program.labels.len());
+    println!("extensions: {}", if arith { "arith" } else { "none" });
+    Ok(())
+}
+
+/// Run a program under the profiler. The program output goes to the
+/// standard output, and the report to the standard error.
+fn profile(
+    input: &Path,
+    args: &MachineArgs,
+    max_steps: Option<u64>,
+    collapsed: Option<&Path>,
+) -> Result<()> {
+    let (program, executable) = load_program(input, args.memory_size)?;
+    let mut machine = new_machine(&executable, args)?;
+    let mut profiler = Profiler::new();
+    let max_steps = max_steps.unwrap_or(u64::MAX);
+    let outcome = profiler.run_for(
+        &mut machine,
+        &mut io::stdin().lock(),
+        &mut io::stdout().lock(),
+        max_steps,
+    );
+    let profile = profiler.profile();
+    eprint!("{}", profile.report(machine.memory(), &program.labels));
+    if let Some(path) = collapsed {
+        write(path, profile.collapsed(&program.labels))?;
+    }
+    match outcome {
+        RunOutcome::Exited => Ok(()),
+        RunOutcome::BudgetExhausted => Err(Failure::StillRunning(max_steps)),
+        RunOutcome::Faulted(e) => {
+            let symbols = SymbolTable::new(&program.labels);
+            Err(Failure::Fault(e.symbolize(&symbols)))
+        }
+    }
+}
+
+/// Run a program once per input file, or once with an empty input, and
+/// print its listing annotated with the coverage of those runs. The output
+/// of the program is discarded, and runs that do not exit are reported
+/// without stopping the others.
+fn coverage(
+    path: &Path,
+    args: &MachineArgs,
+    inputs: &[PathBuf],
+    max_steps: Option<u64>,
+    lcov: Option<&Path>,
+) -> Result<()> {
+    let (source, program, executable) = load_listing(path, args.memory_size)?;
+    let symbols = SymbolTable::new(&program.labels);
+    let inputs: Vec<Vec<u8>> = if inputs.is_empty() {
+        vec![Vec::new()]
+    } else {
+        inputs
+            .iter()
+            .map(|path| read(path))
+            .collect::<Result<_>>()?
+    };
+    let max_steps = max_steps.unwrap_or(u64::MAX);
+    let mut coverage = Coverage::new();
+    for input in inputs {
+        let mut machine = new_machine(&executable, args)?;
+        machine.set_coverage(coverage);
+        match machine.run_for_io(&mut &input[..], &mut io::sink(), max_steps) {
+            RunOutcome::Exited => (),
+            RunOutcome::BudgetExhausted => eprintln!("Error: {}", Failure::StillRunning(max_steps)),
+            RunOutcome::Faulted(e) => eprintln!("Error: {}", e.symbolize(&symbols)),
+        }
+        coverage = machine.take_coverage().unwrap();
+    }
+    let report = Report::new(&source, &coverage)
+        .map_err(|e| Failure::Invalid(format!("{}:{e}", path.display())))?;
+    print!("{}\n{}", report.annotated(), report.summary());
+    if let Some(lcov) = lcov {
+        write(lcov, report.lcov(&path.display().to_string()))?;
+    }
+    Ok(())
+}
+
+/// Check a program, printing its basic blocks if `cfg` is set, then the
+/// problems found. Errors make the check fail.
+fn check(input: &Path, memory_size: usize, arith: bool, cfg: bool) -> Result<()> {
+    let (program, mut executable) = load_program(input, memory_size)?;
+    executable.arith_extension |= arith;
+    let analysis = check::check(&executable, memory_size);
+    let symbols = SymbolTable::new(&program.labels);
+    if cfg {
+        for block in analysis.cfg.blocks.values() {
+            println!("{}", format_block(block, &symbols));
+        }
+    }
+    let mut errors = 0;
+    for problem in &analysis.problems {
+        let severity = if problem.kind.is_error() {
+            errors += 1;
+            "error"
+        } else {
+            "warning"
+        };
+        let location = symbols.symbolize(problem.address);
+        println!("{location}: {severity}: {}", problem.kind);
+    }
+    match errors {
+        0 => Ok(()),
+        1 => Err(Failure::Invalid("1 error found".to_owned())),
+        n => Err(Failure::Invalid(format!("{n} errors found"))),
+    }
+}
+
+/// Format a basic block as its location, its number of instructions and
+/// its successors, `?` standing for an unknown jump target.
+fn format_block(block: &Block, symbols: &SymbolTable) -> String {
+    let mut successors: Vec<String> = block
+        .successors
+        .iter()
+        .map(|&addr| symbols.symbolize(addr))
+        .collect();
+    if block.indirect {
+        successors.push("?".to_owned());
+    }
+    if successors.is_empty() {
+        successors.push("exit".to_owned());
+    }
+    format!(
+        "{}: {} instructions -> {}",
+        symbols.symbolize(block.start),
+        block.instructions.len(),
+        successors.join(", ")
+    )
 }
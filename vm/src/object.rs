@@ -0,0 +1,232 @@
+//! Relocatable object files, and the linker combining them into a program
+//! image.
+//!
+//! An object holds the code and data of a module assembled at address 0,
+//! with [`asm::assemble_object`](crate::asm::assemble_object). Its labels
+//! are private unless exported with `.export name`, and labels of other
+//! modules are used after an `.import name` directive. Every `loadimm`
+//! immediate holding an address gets a relocation entry, so that the module
+//! can be moved by the linker.
+//!
+//! An object file holds, with every number in little-endian order:
+//!
+//! | size | content                                                   |
+//! |------|-----------------------------------------------------------|
+//! | 4    | magic `VMOB`                                              |
+//! | 4    | format version, currently 1                               |
+//! | 4    | length of the code and data                               |
+//! | ...  | code and data                                             |
+//! | 4    | number of symbols                                         |
+//! | ...  | symbols: address (4), exported flag (1), name             |
+//! | 4    | number of relocations                                     |
+//! | ...  | relocations: offset of the immediate (4), imported name   |
+//!
+//! Names are stored as their length (2) followed by their UTF-8 bytes. The
+//! imported name of a relocation is empty for an address in the module
+//! itself.
+
+use crate::asm::Program;
+use crate::reader::Reader;
+use std::collections::BTreeMap;
+use std::fmt;
+
+const MAGIC: &[u8; 4] = b"VMOB";
+/// Version of the object format written by [`Object::encode`].
+pub const VERSION: u32 = 1;
+
+/// A relocatable module.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    /// Code and data, as if loaded at address 0.
+    pub bytes: Vec<u8>,
+    /// Every label of the module, by name.
+    pub symbols: BTreeMap<String, Symbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    /// Address in the module.
+    pub address: u32,
+    /// Whether other modules may import the symbol.
+    pub exported: bool,
+}
+
+/// A `loadimm` immediate to patch when linking.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    /// Offset of the 16-bit immediate in the module.
+    pub offset: u32,
+    /// Symbol imported from another module whose address is the value of
+    /// the immediate, or `None` if the immediate is an address in the
+    /// module, to which the address of the module is added.
+    pub import: Option<String>,
+}
+
+/// Error raised while decoding or linking objects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Malformed object file or unsupported object version
+    InvalidObject,
+    /// Symbol exported by two modules, given by index
+    DuplicateSymbol {
+        name: String,
+        modules: (usize, usize),
+    },
+    /// Symbol imported by a module but exported by none
+    UnresolvedSymbol { name: String, module: usize },
+    /// Address not fitting in a `loadimm` immediate once linked
+    AddressOverflow { address: u32, module: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidObject => write!(f, "invalid object file"),
+            Error::DuplicateSymbol {
+                name,
+                modules: (first, second),
+            } => write!(
+                f,
+                "symbol `{name}` is exported by modules {first} and {second}"
+            ),
+            Error::UnresolvedSymbol { name, module } => {
+                write!(
+                    f,
+                    "symbol `{name}` imported by module {module} is not exported"
+                )
+            }
+            Error::AddressOverflow { address, module } => write!(
+                f,
+                "address {address} used by module {module} does not fit in an immediate"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Object {
+    /// Encode the object in the object file format.
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(MAGIC);
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend((self.bytes.len() as u32).to_le_bytes());
+        bytes.extend(&self.bytes);
+        bytes.extend((self.symbols.len() as u32).to_le_bytes());
+        for (name, symbol) in &self.symbols {
+            bytes.extend(symbol.address.to_le_bytes());
+            bytes.push(symbol.exported.into());
+            put_name(&mut bytes, name);
+        }
+        bytes.extend((self.relocations.len() as u32).to_le_bytes());
+        for relocation in &self.relocations {
+            bytes.extend(relocation.offset.to_le_bytes());
+            put_name(&mut bytes, relocation.import.as_deref().unwrap_or_default());
+        }
+        bytes
+    }
+
+    /// Decode an object file.
+    ///
+    /// # Errors
+    /// `InvalidObject` is returned if `bytes` is not an object file of a
+    /// supported version, or if a relocation is out of the module.
+    pub fn decode(bytes: &[u8]) -> Result<Object> {
+        let mut reader = Reader::new(bytes, || Error::InvalidObject);
+        if reader.take(4)? != MAGIC || reader.u32()? != VERSION {
+            return Err(Error::InvalidObject);
+        }
+        let len = reader.u32()? as usize;
+        let mut object = Object {
+            bytes: reader.take(len)?.to_vec(),
+            ..Object::default()
+        };
+        for _ in 0..reader.u32()? {
+            let address = reader.u32()?;
+            let exported = match reader.take(1)? {
+                [0] => false,
+                [1] => true,
+                _ => return Err(Error::InvalidObject),
+            };
+            let name = reader.name()?;
+            object.symbols.insert(name, Symbol { address, exported });
+        }
+        for _ in 0..reader.u32()? {
+            let offset = reader.u32()?;
+            if offset as usize + 2 > object.bytes.len() {
+                return Err(Error::InvalidObject);
+            }
+            let name = reader.name()?;
+            object.relocations.push(Relocation {
+                offset,
+                import: (!name.is_empty()).then_some(name),
+            });
+        }
+        if !reader.rest().is_empty() {
+            return Err(Error::InvalidObject);
+        }
+        Ok(object)
+    }
+}
+
+fn put_name(bytes: &mut Vec<u8>, name: &str) {
+    bytes.extend((name.len() as u16).to_le_bytes());
+    bytes.extend(name.as_bytes());
+}
+
+/// Link objects into a program image, laid out in order: the first object
+/// starts at address 0, where execution starts.
+///
+/// The labels of the program are the exported symbols.
+///
+/// # Errors
+/// An error is returned if a symbol is exported twice or imported but
+/// never exported, or if an address does not fit in a `loadimm` immediate.
+pub fn link(objects: &[Object]) -> Result<Program> {
+    let mut program = Program::default();
+    let mut exporters = BTreeMap::new();
+    let mut bases = Vec::new();
+    for (module, object) in objects.iter().enumerate() {
+        let base = program.bytes.len() as u32;
+        for (name, symbol) in object.symbols.iter().filter(|(_, s)| s.exported) {
+            if let Some(&first) = exporters.get(name) {
+                return Err(Error::DuplicateSymbol {
+                    name: name.clone(),
+                    modules: (first, module),
+                });
+            }
+            exporters.insert(name.clone(), module);
+            program.labels.insert(name.clone(), base + symbol.address);
+        }
+        bases.push(base);
+        program.bytes.extend(&object.bytes);
+    }
+    for (module, (object, base)) in objects.iter().zip(bases).enumerate() {
+        for relocation in &object.relocations {
+            let offset = (base + relocation.offset) as usize;
+            let immediate = &mut program.bytes[offset..offset + 2];
+            let address = match &relocation.import {
+                None => u32::from(u16::from_le_bytes([immediate[0], immediate[1]])) + base,
+                Some(name) => match program.labels.get(name) {
+                    Some(&address) => address,
+                    None => {
+                        return Err(Error::UnresolvedSymbol {
+                            name: name.clone(),
+                            module,
+                        })
+                    }
+                },
+            };
+            let Ok(imm) = i16::try_from(address) else {
+                return Err(Error::AddressOverflow { address, module });
+            };
+            immediate.copy_from_slice(&imm.to_le_bytes());
+        }
+    }
+    Ok(program)
+}
@@ -0,0 +1,268 @@
+//! Execution profiler.
+//!
+//! A [`Profiler`] drives a [`Machine`] and counts how many times every
+//! instruction address runs. Costs are also attributed to functions by
+//! following the calling convention of the listings: a call pushes the
+//! return address on the stack pointed to by r2, then jumps with
+//! `loadimm r0 <- #fn`. The function returns when the IP reaches the return
+//! address with the stack popped.
+
+use crate::machine::Result;
+use crate::symbols::SymbolTable;
+use crate::{Instruction, Machine, RunOutcome};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+
+/// Number of instructions listed as the hottest ones in the report.
+const HOTTEST: usize = 10;
+
+/// Costs attributed to a function, in executed instructions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FunctionCost {
+    pub calls: u64,
+    /// Instructions executed by the function itself.
+    pub self_cost: u64,
+    /// Instructions executed by the function and the functions it called.
+    pub total_cost: u64,
+}
+
+/// Result of a profiled run. Functions are identified by their address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Number of executed instructions.
+    pub total: u64,
+    /// Number of executions of every executed address.
+    pub counts: BTreeMap<u32, u64>,
+    pub functions: BTreeMap<u32, FunctionCost>,
+    /// Number of instructions executed with each call stack, outermost
+    /// function first.
+    pub stacks: BTreeMap<Vec<u32>, u64>,
+}
+
+/// An active function call.
+struct Frame {
+    function: u32,
+    /// Address the function returns to.
+    ret: u32,
+    /// Stack pointer before the call, the return address being on top.
+    sp: u32,
+}
+
+/// Profiler of the execution of a machine.
+#[derive(Default)]
+pub struct Profiler {
+    profile: Profile,
+    /// Active calls, the outermost frame being the entry point of the
+    /// program, which never returns.
+    frames: Vec<Frame>,
+}
+
+impl Profiler {
+    #[must_use]
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    /// Execute the next instruction of `machine` with
+    /// [`Machine::step_io`] and account for it.
+    ///
+    /// # Errors
+    /// Errors of the machine are returned, the failing instruction not
+    /// being counted.
+    pub fn step<R: Read, W: Write>(
+        &mut self,
+        machine: &mut Machine,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<bool> {
+        let ip = machine.regs()[0];
+        let sp = machine.regs()[2];
+        if self.frames.is_empty() {
+            self.frames.push(Frame {
+                function: ip,
+                ret: u32::MAX,
+                sp: u32::MAX,
+            });
+            self.profile.functions.entry(ip).or_default().calls += 1;
+        }
+        let ret = call_return_address(machine, ip, sp);
+        let exited = machine.step_io(input, output)?;
+        self.account(ip);
+
+        let ip = machine.regs()[0];
+        match ret {
+            Some(ret) if ip != ret => {
+                self.frames.push(Frame {
+                    function: ip,
+                    ret,
+                    sp,
+                });
+                self.profile.functions.entry(ip).or_default().calls += 1;
+            }
+            _ => {
+                while self.frames.len() > 1 {
+                    let top = self.frames.last().unwrap();
+                    if top.ret != ip || machine.regs()[2] <= top.sp {
+                        break;
+                    }
+                    self.frames.pop();
+                }
+            }
+        }
+        Ok(exited)
+    }
+
+    /// Profile `machine` until the program terminates, an error happens or
+    /// `max_steps` instructions have been executed, like
+    /// [`Machine::run_for_io`].
+    pub fn run_for<R: Read, W: Write>(
+        &mut self,
+        machine: &mut Machine,
+        input: &mut R,
+        output: &mut W,
+        max_steps: u64,
+    ) -> RunOutcome {
+        for _ in 0..max_steps {
+            match self.step(machine, input, output) {
+                Ok(true) => return RunOutcome::Exited,
+                Ok(false) => (),
+                Err(e) => return RunOutcome::Faulted(e),
+            }
+        }
+        RunOutcome::BudgetExhausted
+    }
+
+    /// The profile gathered so far.
+    #[must_use]
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Account for an instruction executed at `ip` in the current frame.
+    fn account(&mut self, ip: u32) {
+        let profile = &mut self.profile;
+        profile.total += 1;
+        *profile.counts.entry(ip).or_default() += 1;
+        let stack: Vec<u32> = self.frames.iter().map(|frame| frame.function).collect();
+        let current = *stack.last().unwrap();
+        profile.functions.entry(current).or_default().self_cost += 1;
+        // Recursive functions are only charged once
+        for (i, &function) in stack.iter().enumerate() {
+            if !stack[..i].contains(&function) {
+                profile.functions.entry(function).or_default().total_cost += 1;
+            }
+        }
+        *profile.stacks.entry(stack).or_default() += 1;
+    }
+}
+
+/// If the instruction at `ip` is a call, that is a jump executed while the
+/// word on top of the stack is the address following the jump, return
+/// that address.
+fn call_return_address(machine: &Machine, ip: u32, sp: u32) -> Option<u32> {
+    let memory = machine.memory();
+    let (instruction, size) = Instruction::decode(memory.get(ip as usize..)?).ok()?;
+    if !matches!(
+        instruction,
+        Instruction::LoadImm { dst: 0, .. } | Instruction::MoveIf { dst: 0, .. }
+    ) {
+        return None;
+    }
+    let ret = ip.wrapping_add(size as u32);
+    let top = memory.get(sp as usize..(sp as usize).checked_add(4)?)?;
+    (u32::from_le_bytes(top.try_into().unwrap()) == ret).then_some(ret)
+}
+
+impl Profile {
+    /// Format the profile as text: a table of the functions, sorted by
+    /// decreasing total cost, the hottest instructions, and the execution
+    /// count of every executed address. `memory` holds the program, and
+    /// `labels` name addresses, as `label+offset` where no label is at the
+    /// address itself.
+    #[must_use]
+    pub fn report(&self, memory: &[u8], labels: &BTreeMap<String, u32>) -> String {
+        let symbols = SymbolTable::new(labels);
+        let percent = |count: u64| 100.0 * count as f64 / self.total.max(1) as f64;
+        let mut report = format!("{} instructions executed\n\n", self.total);
+
+        report.push_str("     calls       self  self%      total total%  function\n");
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by_key(|&(&addr, cost)| (std::cmp::Reverse(cost.total_cost), addr));
+        for (&addr, cost) in functions {
+            writeln!(
+                report,
+                "{:>10} {:>10} {:>5.1}% {:>10} {:>5.1}%  {}",
+                cost.calls,
+                cost.self_cost,
+                percent(cost.self_cost),
+                cost.total_cost,
+                percent(cost.total_cost),
+                function(&symbols, addr)
+            )
+            .unwrap();
+        }
+
+        let line = |report: &mut String, location: String, addr: u32, count: u64| {
+            let instruction = match memory
+                .get(addr as usize..)
+                .and_then(|code| Instruction::decode(code).ok())
+            {
+                Some((Instruction::LoadImm { dst, imm }, _)) if imm >= 0 => {
+                    match symbols.label(imm as u32) {
+                        Some(name) => format!("loadimm r{dst} <- #{name}"),
+                        None => format!("loadimm r{dst} <- #{imm}"),
+                    }
+                }
+                Some((instruction, _)) => instruction.to_string(),
+                None => "???".to_owned(),
+            };
+            writeln!(
+                report,
+                "{count:>10} {:>5.1}%  {location}   {instruction}",
+                percent(count)
+            )
+            .unwrap();
+        };
+
+        report.push_str("\nhottest instructions:\n");
+        let mut hottest: Vec<(u32, u64)> = self.counts.iter().map(|(&a, &c)| (a, c)).collect();
+        hottest.sort_by_key(|&(addr, count)| (std::cmp::Reverse(count), addr));
+        for &(addr, count) in hottest.iter().take(HOTTEST) {
+            line(&mut report, symbols.symbolize(addr), addr, count);
+        }
+
+        report.push_str("\nexecution counts:\n");
+        for (&addr, &count) in &self.counts {
+            if let Some(name) = symbols.label(addr) {
+                writeln!(report, "{name}:").unwrap();
+            }
+            line(&mut report, format!("{addr:04}"), addr, count);
+        }
+        report
+    }
+
+    /// Format the call stacks in the collapsed format read by flame graph
+    /// tools: one line per stack, with the function names separated by `;`
+    /// and followed by the number of instructions executed in that stack.
+    #[must_use]
+    pub fn collapsed(&self, labels: &BTreeMap<String, u32>) -> String {
+        let symbols = SymbolTable::new(labels);
+        let mut collapsed = String::new();
+        for (stack, count) in &self.stacks {
+            let stack: Vec<String> = stack.iter().map(|&addr| function(&symbols, addr)).collect();
+            writeln!(collapsed, "{} {count}", stack.join(";")).unwrap();
+        }
+        collapsed
+    }
+}
+
+/// Name of a function: its label, `main` for an unlabelled entry point at
+/// address 0, or its address named by the closest label.
+fn function(symbols: &SymbolTable, addr: u32) -> String {
+    match symbols.label(addr) {
+        Some(name) => name.to_owned(),
+        None if addr == 0 => "main".to_owned(),
+        None => symbols.symbolize(addr),
+    }
+}
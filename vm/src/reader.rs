@@ -0,0 +1,43 @@
+//! Reader of the little-endian numbers and names of the binary formats:
+//! executables, objects and snapshots.
+
+/// Cursor over the bytes of a file, failing with the error of its format
+/// when they are cut short or malformed.
+pub(crate) struct Reader<'a, E> {
+    bytes: &'a [u8],
+    invalid: fn() -> E,
+}
+
+impl<'a, E> Reader<'a, E> {
+    pub(crate) fn new(bytes: &'a [u8], invalid: fn() -> E) -> Self {
+        Reader { bytes, invalid }
+    }
+
+    /// Bytes not read yet.
+    pub(crate) fn rest(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], E> {
+        if self.bytes.len() < len {
+            return Err((self.invalid)());
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, E> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, E> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// A name stored as its length (2) followed by its UTF-8 bytes.
+    pub(crate) fn name(&mut self) -> Result<String, E> {
+        let len = u16::from_le_bytes(self.take(2)?.try_into().unwrap());
+        String::from_utf8(self.take(len.into())?.to_vec()).map_err(|_| (self.invalid)())
+    }
+}
@@ -0,0 +1,98 @@
+//! Machine snapshots and their binary file format.
+//!
+//! A snapshot file holds, with every number in little-endian order:
+//!
+//! | size | content                                           |
+//! |------|---------------------------------------------------|
+//! | 4    | magic `VMSS`                                      |
+//! | 4    | format version, currently 1                       |
+//! | 4    | flags, bit 0 set if the arithmetic extension is on |
+//! | 64   | registers r0 to r15                               |
+//! | 8    | number of executed instructions                   |
+//! | 8    | memory size                                       |
+//! | 8    | length of the stored memory prefix                |
+//! | ...  | memory prefix, the rest of the memory being zero  |
+
+use crate::machine::{Result, MAX_MEMORY_SIZE, NREGS};
+use crate::reader::Reader;
+use crate::ErrorKind;
+
+const MAGIC: &[u8; 4] = b"VMSS";
+/// Version of the snapshot format written by [`Snapshot::encode`].
+pub const VERSION: u32 = 1;
+const ARITH_EXTENSION: u32 = 1;
+
+/// The state of a machine, as returned by
+/// [`Machine::snapshot`](crate::Machine::snapshot). Mapped devices and the
+/// tracer are not part of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub regs: [u32; NREGS],
+    pub memory: Vec<u8>,
+    /// Number of instructions executed by the machine.
+    pub executed: u64,
+    pub arith_extension: bool,
+}
+
+impl Snapshot {
+    /// Encode the snapshot in the snapshot file format.
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let stored = self.memory.len() - self.memory.iter().rev().take_while(|&&b| b == 0).count();
+        let mut bytes = Vec::with_capacity(100 + stored);
+        bytes.extend(MAGIC);
+        bytes.extend(VERSION.to_le_bytes());
+        let flags = if self.arith_extension {
+            ARITH_EXTENSION
+        } else {
+            0
+        };
+        bytes.extend(flags.to_le_bytes());
+        for reg in self.regs {
+            bytes.extend(reg.to_le_bytes());
+        }
+        bytes.extend(self.executed.to_le_bytes());
+        bytes.extend((self.memory.len() as u64).to_le_bytes());
+        bytes.extend((stored as u64).to_le_bytes());
+        bytes.extend(&self.memory[..stored]);
+        bytes
+    }
+
+    /// Decode a snapshot file.
+    ///
+    /// # Errors
+    /// `InvalidSnapshot` is returned if `bytes` is not a snapshot file of a
+    /// supported version, and `MemoryOverflow` if its memory is larger than
+    /// `MAX_MEMORY_SIZE`.
+    pub fn decode(bytes: &[u8]) -> Result<Snapshot> {
+        let mut reader = Reader::new(bytes, || ErrorKind::InvalidSnapshot);
+        if reader.take(4)? != MAGIC || reader.u32()? != VERSION {
+            return Err(ErrorKind::InvalidSnapshot.into());
+        }
+        let flags = reader.u32()?;
+        if flags & !ARITH_EXTENSION != 0 {
+            return Err(ErrorKind::InvalidSnapshot.into());
+        }
+        let mut regs = [0; NREGS];
+        for reg in &mut regs {
+            *reg = reader.u32()?;
+        }
+        let executed = reader.u64()?;
+        let size = reader.u64()?;
+        let stored = reader.u64()?;
+        if size > MAX_MEMORY_SIZE {
+            return Err(ErrorKind::MemoryOverflow.into());
+        }
+        if stored > size || stored != reader.rest().len() as u64 {
+            return Err(ErrorKind::InvalidSnapshot.into());
+        }
+        let mut memory = vec![0; size as usize];
+        memory[..stored as usize].copy_from_slice(reader.rest());
+        Ok(Snapshot {
+            regs,
+            memory,
+            executed,
+            arith_extension: flags & ARITH_EXTENSION != 0,
+        })
+    }
+}
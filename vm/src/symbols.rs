@@ -0,0 +1,63 @@
+//! Names of addresses, to show `label+offset` rather than raw addresses.
+
+use crate::asm::{self, assemble};
+use std::collections::BTreeMap;
+
+/// Labels of a program, by address. An address with several labels is
+/// named by the first of them in alphabetical order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    names: BTreeMap<u32, String>,
+}
+
+impl SymbolTable {
+    /// Symbol table of labels given by name, such as the labels of an
+    /// assembled program or the symbols of an executable.
+    #[must_use]
+    pub fn new(labels: &BTreeMap<String, u32>) -> Self {
+        let mut names = BTreeMap::new();
+        for (name, &addr) in labels {
+            names.entry(addr).or_insert_with(|| name.clone());
+        }
+        SymbolTable { names }
+    }
+
+    /// Symbol table of the labels defined in a listing.
+    ///
+    /// # Errors
+    /// The listing must assemble, see [`assemble`].
+    pub fn parse(listing: &str) -> Result<Self, asm::Error> {
+        Ok(Self::new(&assemble(listing)?.labels))
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Label at exactly `addr`.
+    #[must_use]
+    pub fn label(&self, addr: u32) -> Option<&str> {
+        self.names.get(&addr).map(String::as_str)
+    }
+
+    /// Closest label at or before `addr`, and the offset of `addr` from it.
+    #[must_use]
+    pub fn resolve(&self, addr: u32) -> Option<(&str, u32)> {
+        self.names
+            .range(..=addr)
+            .next_back()
+            .map(|(&label_addr, name)| (name.as_str(), addr - label_addr))
+    }
+
+    /// Format an address as `label+offset`, `label` alone at the label
+    /// itself, or the address as in listings if no label precedes it.
+    #[must_use]
+    pub fn symbolize(&self, addr: u32) -> String {
+        match self.resolve(addr) {
+            Some((name, 0)) => name.to_owned(),
+            Some((name, offset)) => format!("{name}+{offset}"),
+            None => format!("{addr:04}"),
+        }
+    }
+}
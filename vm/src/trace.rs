@@ -0,0 +1,192 @@
+//! Instruction-level execution traces.
+//!
+//! A [`Tracer`] installed with [`Machine::set_tracer`](crate::Machine::set_tracer)
+//! is called after every successfully executed instruction with a [`Step`]
+//! describing it. [`TextTracer`] and [`JsonTracer`] write steps one per
+//! line, so that traces of two runs can be compared with `diff`. Given a
+//! [`SymbolTable`], they also name addresses as `label+offset`.
+
+use crate::symbols::SymbolTable;
+use crate::Instruction;
+use std::fmt;
+use std::io::{self, Write};
+
+/// A register written by an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegWrite {
+    pub reg: u8,
+    pub old: u32,
+    pub new: u32,
+}
+
+/// A memory byte changed by an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemWrite {
+    pub addr: u32,
+    pub old: u8,
+    pub new: u8,
+}
+
+/// An executed instruction and its effects. The implicit move of the IP
+/// past the instruction is not listed in `regs`.
+#[derive(Debug, Clone, Copy)]
+pub struct Step<'a> {
+    /// Address of the instruction.
+    pub ip: u32,
+    pub instruction: Instruction,
+    /// Registers written, in order.
+    pub regs: &'a [RegWrite],
+    /// Memory bytes whose value changed, in order.
+    pub memory: &'a [MemWrite],
+}
+
+impl Step<'_> {
+    /// Format the step as a single-line JSON object.
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        self.json(None)
+    }
+
+    /// Format the step as its [`Display`](fmt::Display) does, with the
+    /// address of the instruction named by `symbols`.
+    #[must_use]
+    pub fn to_text(&self, symbols: &SymbolTable) -> String {
+        format!("{:<13}   {}", symbols.symbolize(self.ip), self.body())
+    }
+
+    /// The instruction and its effects.
+    fn body(&self) -> String {
+        let mut body = format!("{:<28}", self.instruction.to_string());
+        for w in self.regs {
+            body += &format!(" r{}=0x{:08x}", w.reg, w.new);
+        }
+        for w in self.memory {
+            body += &format!(" [{}]=0x{:02x}", w.addr, w.new);
+        }
+        body
+    }
+
+    /// Format the step as a JSON object, with a `symbol` member naming the
+    /// address of the instruction if `symbols` are given.
+    fn json(&self, symbols: Option<&SymbolTable>) -> String {
+        let regs: Vec<String> = self
+            .regs
+            .iter()
+            .map(|w| format!("{{\"reg\":{},\"value\":{}}}", w.reg, w.new))
+            .collect();
+        let memory: Vec<String> = self
+            .memory
+            .iter()
+            .map(|w| format!("{{\"addr\":{},\"value\":{}}}", w.addr, w.new))
+            .collect();
+        let symbol = symbols
+            .map(|symbols| format!(",\"symbol\":{}", json_string(&symbols.symbolize(self.ip))))
+            .unwrap_or_default();
+        format!(
+            "{{\"ip\":{}{symbol},\"instruction\":{},\"regs\":[{}],\"memory\":[{}]}}",
+            self.ip,
+            json_string(&self.instruction.to_string()),
+            regs.join(","),
+            memory.join(",")
+        )
+    }
+}
+
+/// Format the step as a listing line followed by its effects, e.g.
+/// `0016   store [r2] <- r3   [4092]=0x17`.
+impl fmt::Display for Step<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}   {}", self.ip, self.body())
+    }
+}
+
+/// Receiver of execution steps.
+pub trait Tracer {
+    /// Called after each successfully executed instruction.
+    ///
+    /// # Errors
+    /// An error stops the machine with a `WriteFailure`.
+    fn trace(&mut self, step: &Step) -> io::Result<()>;
+}
+
+impl<F: FnMut(&Step) -> io::Result<()>> Tracer for F {
+    fn trace(&mut self, step: &Step) -> io::Result<()> {
+        self(step)
+    }
+}
+
+/// Tracer writing steps as plain text, one per line.
+pub struct TextTracer<W: Write> {
+    out: W,
+    symbols: Option<SymbolTable>,
+}
+
+impl<W: Write> TextTracer<W> {
+    pub fn new(out: W) -> Self {
+        TextTracer { out, symbols: None }
+    }
+
+    /// Tracer naming addresses with `symbols`, see [`Step::to_text`].
+    pub fn with_symbols(out: W, symbols: SymbolTable) -> Self {
+        TextTracer {
+            out,
+            symbols: Some(symbols),
+        }
+    }
+}
+
+impl<W: Write> Tracer for TextTracer<W> {
+    fn trace(&mut self, step: &Step) -> io::Result<()> {
+        let text = match &self.symbols {
+            Some(symbols) => step.to_text(symbols),
+            None => step.to_string(),
+        };
+        writeln!(self.out, "{}", text.trim_end())
+    }
+}
+
+/// Tracer writing steps as JSON Lines.
+pub struct JsonTracer<W: Write> {
+    out: W,
+    symbols: Option<SymbolTable>,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(out: W) -> Self {
+        JsonTracer { out, symbols: None }
+    }
+
+    /// Tracer adding to every step a `symbol` member naming its address
+    /// with `symbols`.
+    pub fn with_symbols(out: W, symbols: SymbolTable) -> Self {
+        JsonTracer {
+            out,
+            symbols: Some(symbols),
+        }
+    }
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+    fn trace(&mut self, step: &Step) -> io::Result<()> {
+        writeln!(self.out, "{}", step.json(self.symbols.as_ref()))
+    }
+}
+
+/// Quote `text` as a JSON string, escaping the quotes, backslashes and
+/// control characters it holds.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\r' => quoted += "\\r",
+            '\t' => quoted += "\\t",
+            c if c.is_control() => quoted += &format!("\\u{:04x}", c as u32),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
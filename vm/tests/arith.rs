@@ -0,0 +1,121 @@
+use interpreter::asm::assemble;
+use interpreter::{ArithOp, Error, ErrorKind, Instruction, Machine};
+use std::io;
+
+// Execute `op r1 <- r2 <op> r3` with the extension enabled
+fn apply(op: ArithOp, lhs: u32, rhs: u32) -> Result<u32, Error> {
+    let mut machine = Machine::new(&[op.opcode(), 1, 2, 3]).unwrap();
+    machine.set_arith_extension(true);
+    machine.set_reg(2, lhs).unwrap();
+    machine.set_reg(3, rhs).unwrap();
+    machine.step_on(&mut io::sink())?;
+    Ok(machine.regs()[1])
+}
+
+#[test]
+fn disabled_by_default() {
+    for op in ArithOp::ALL {
+        let mut machine = Machine::new(&[op.opcode(), 1, 2, 3, 7]).unwrap();
+        assert!(!machine.arith_extension());
+        assert!(matches!(
+            machine.step_on(&mut io::sink()),
+            Err(e) if matches!(e.kind(), ErrorKind::UnknownOpcode { opcode: _ })
+        ));
+        assert_eq!(0, machine.regs()[0]);
+    }
+
+    // Whatever their operands
+    let unknown = |mut machine: Machine| {
+        let error = machine.step_on(&mut io::sink()).unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            ErrorKind::UnknownOpcode { opcode: 32 }
+        ));
+    };
+    unknown(Machine::new(&[32, 16, 0, 0]).unwrap());
+    unknown(Machine::with_memory_size(&[32, 1], 2).unwrap());
+}
+
+#[test]
+fn operations() {
+    let m = |v: i32| v as u32;
+    assert_eq!(7, apply(ArithOp::Add, 3, 4).unwrap());
+    assert_eq!(1, apply(ArithOp::Add, u32::MAX, 2).unwrap());
+    assert_eq!(m(-12), apply(ArithOp::Mul, m(-3), 4).unwrap());
+    assert_eq!(0, apply(ArithOp::Mul, 1 << 16, 1 << 16).unwrap());
+    assert_eq!(m(-3), apply(ArithOp::DivS, m(-7), 2).unwrap());
+    assert_eq!(
+        m(i32::MIN),
+        apply(ArithOp::DivS, m(i32::MIN), m(-1)).unwrap()
+    );
+    assert_eq!(0x7fff_fffc, apply(ArithOp::DivU, m(-7), 2).unwrap());
+    assert_eq!(m(-1), apply(ArithOp::Rem, m(-7), 2).unwrap());
+    assert_eq!(1, apply(ArithOp::Rem, 7, m(-2)).unwrap());
+    assert_eq!(0, apply(ArithOp::Rem, m(i32::MIN), m(-1)).unwrap());
+    assert_eq!(0b1000, apply(ArithOp::And, 0b1100, 0b1010).unwrap());
+    assert_eq!(0b1110, apply(ArithOp::Or, 0b1100, 0b1010).unwrap());
+    assert_eq!(0b0110, apply(ArithOp::Xor, 0b1100, 0b1010).unwrap());
+    assert_eq!(0x8000_0000, apply(ArithOp::Shl, 1, 31).unwrap());
+    assert_eq!(2, apply(ArithOp::Shl, 1, 33).unwrap());
+    assert_eq!(0x4000_0000, apply(ArithOp::Shr, 0x8000_0000, 1).unwrap());
+    assert_eq!(0xc000_0000, apply(ArithOp::Sar, 0x8000_0000, 1).unwrap());
+    assert_eq!(0x8000_0000, apply(ArithOp::Sar, 0x8000_0000, 32).unwrap());
+}
+
+#[test]
+fn division_by_zero() {
+    for op in [ArithOp::DivS, ArithOp::DivU, ArithOp::Rem] {
+        assert!(matches!(apply(op, 7, 0), Err(e) if matches!(e.kind(), ErrorKind::DivisionByZero)));
+    }
+}
+
+#[test]
+fn listing() {
+    let listing = "  0000   add r1 <- r2 + r3
+  0004   mul r4 <- r4 * r5
+  0008   divs r1 <- r1 / r2
+  0012   divu r1 <- r1 / r2
+  0016   rem r1 <- r1 % r2
+  0020   and r1 <- r1 & r2
+  0024   or r1 <- r1 | r2
+  0028   xor r1 <- r1 ^ r2
+  0032   shl r1 <- r1 << r2
+  0036   shr r1 <- r1 >> r2
+  0040   sar r15 <- r1 >> r2
+";
+    let program = assemble(listing).unwrap();
+    assert_eq!(&program.bytes[..4], &[32, 1, 2, 3]);
+    assert_eq!(&program.bytes[40..], &[42, 15, 1, 2]);
+    let mut decoded = String::new();
+    let mut addr = 0;
+    while addr < program.bytes.len() {
+        let (instruction, size) = Instruction::decode(&program.bytes[addr..]).unwrap();
+        decoded += &format!("  {addr:04}   {instruction}\n");
+        addr += size;
+    }
+    assert_eq!(listing, decoded);
+    assert!(assemble("add r1 <- r2 - r3").is_err());
+}
+
+// Factorial using mul instead of a subtraction loop
+#[test]
+fn factorial() {
+    let program = assemble(
+        "  loadimm r11 <- #1
+           loadimm r3 <- #1
+         loop:
+           loadimm r4 <- #body
+           move r0 <- r4 if r10 != 0
+           exit
+         body:
+           mul r11 <- r11 * r10
+           sub r10 <- r10 - r3
+           loadimm r0 <- #loop",
+    )
+    .unwrap();
+    let mut machine = Machine::new(&program.bytes).unwrap();
+    machine.set_arith_extension(true);
+    machine.set_reg(10, 12).unwrap();
+    machine.run_on(&mut io::sink()).unwrap();
+    assert_eq!(479_001_600, machine.regs()[11]);
+}
@@ -0,0 +1,151 @@
+use interpreter::asm::assemble;
+
+fn check_round_trip(listing: &str, binary: &[u8]) {
+    assert_eq!(binary, &assemble(listing).unwrap().bytes[..]);
+}
+
+#[test]
+fn round_trip_tests() {
+    check_round_trip(include_str!("afact.dis"), include_bytes!("afact.bin"));
+    check_round_trip(include_str!("fact.dis"), include_bytes!("fact.bin"));
+    check_round_trip(include_str!("fibo.dis"), include_bytes!("fibo.bin"));
+    check_round_trip(include_str!("function.dis"), include_bytes!("function.bin"));
+    check_round_trip(include_str!("multiply.dis"), include_bytes!("multiply.bin"));
+    check_round_trip(include_str!("push_pop.dis"), include_bytes!("push_pop.bin"));
+    check_round_trip(include_str!("rfact.dis"), include_bytes!("rfact.bin"));
+    check_round_trip(include_str!("rfact_tr.dis"), include_bytes!("rfact_tr.bin"));
+}
+
+#[test]
+fn round_trip_examples() {
+    check_round_trip(
+        include_str!("../examples/ask_factorial.dis"),
+        include_bytes!("../examples/ask_factorial.bin"),
+    );
+    check_round_trip(
+        include_str!("../examples/99bottles.dis"),
+        include_bytes!("../examples/99bottles.bin"),
+    );
+    check_round_trip(
+        include_str!("../examples/count.dis"),
+        include_bytes!("../examples/count.bin"),
+    );
+    check_round_trip(
+        include_str!("../examples/factorial.dis"),
+        include_bytes!("../examples/factorial.bin"),
+    );
+    check_round_trip(
+        include_str!("../examples/fibonacci.dis"),
+        include_bytes!("../examples/fibonacci.bin"),
+    );
+    check_round_trip(
+        include_str!("../examples/hello_world.dis"),
+        include_bytes!("../examples/hello_world.bin"),
+    );
+    check_round_trip(
+        include_str!("../examples/reverse.dis"),
+        include_bytes!("../examples/reverse.bin"),
+    );
+}
+
+#[test]
+fn labels() {
+    let program = assemble(include_str!("rfact.dis")).unwrap();
+    assert_eq!(Some(&24), program.labels.get("mult"));
+    assert_eq!(Some(&87), program.labels.get("rfact"));
+    assert_eq!(Some(&187), program.labels.get("ite_end_2"));
+    assert_eq!(Some(&187), program.labels.get("return_from_mult_1"));
+}
+
+#[test]
+fn data() {
+    let program = assemble("b'a\\'b\\n\\x00'\nb\"I'm\"\n[1, 2, 255]").unwrap();
+    assert_eq!(b"a'b\n\0I'm\x01\x02\xff", &program.bytes[..]);
+}
+
+#[test]
+fn without_address_column() {
+    let program = assemble("start: loadimm r1 <- #-2 ; comment\n  out_number r1\nexit").unwrap();
+    assert_eq!(&[4, 1, 0xfe, 0xff, 8, 1, 7], &program.bytes[..]);
+}
+
+#[test]
+fn exit_forms() {
+    let program = assemble("exit ; done\nexit r3 ; with status\n").unwrap();
+    assert_eq!(&[7, 17, 3], &program.bytes[..]);
+}
+
+#[test]
+fn pseudo_instructions() {
+    let program = assemble(
+        "start:\n\
+         push r4\n\
+         pop r5\n\
+         call f\n\
+         ret\n\
+         jmp start\n\
+         jnz r6, #2\n\
+         f:",
+    )
+    .unwrap();
+    let expanded = assemble(
+        "start:\n\
+         loadimm r3 <- #4\nsub r2 <- r2 - r3\nstore [r2] <- r4\n\
+         loadimm r3 <- #-4\nsub r2 <- r2 - r3\nloadimm r3 <- #4\nsub r3 <- r2 - r3\n\
+         load r5 <- [r3]\n\
+         loadimm r3 <- #4\nsub r2 <- r2 - r3\nloadimm r3 <- #return\nstore [r2] <- r3\n\
+         loadimm r0 <- #f\n\
+         return:\n\
+         loadimm r3 <- #-4\nsub r2 <- r2 - r3\nloadimm r3 <- #4\nsub r3 <- r2 - r3\n\
+         load r0 <- [r3]\n\
+         loadimm r0 <- #start\n\
+         loadimm r3 <- #2\nmove r0 <- r3 if r6 != 0\n\
+         f:",
+    )
+    .unwrap();
+    assert_eq!(expanded.bytes, program.bytes);
+    assert_eq!(Some(&30), program.lines.get(&4));
+}
+
+#[test]
+fn scratch_register() {
+    let program = assemble("push r4\n.scratch r9\npush r3\njnz r1, #0").unwrap();
+    let expanded = assemble(
+        "loadimm r3 <- #4\nsub r2 <- r2 - r3\nstore [r2] <- r4\n\
+         loadimm r9 <- #4\nsub r2 <- r2 - r9\nstore [r2] <- r3\n\
+         loadimm r9 <- #0\nmove r0 <- r9 if r1 != 0",
+    )
+    .unwrap();
+    assert_eq!(expanded.bytes, program.bytes);
+}
+
+fn error_at(source: &str) -> (usize, usize) {
+    let error = assemble(source).unwrap_err();
+    (error.line, error.column)
+}
+
+#[test]
+fn errors() {
+    // Unknown instruction
+    assert_eq!((2, 10), error_at("  0000   exit\n  0001   jump r1"));
+    // Register out of range
+    assert_eq!((1, 9), error_at("loadimm r16 <- #0"));
+    // Immediate out of range
+    assert_eq!((1, 16), error_at("loadimm r1 <- #40000"));
+    // Missing operand separator
+    assert_eq!((1, 14), error_at("sub r1 <- r2 r3"));
+    // Undefined label
+    assert_eq!((2, 16), error_at("exit\nloadimm r0 <- #nowhere"));
+    // Duplicate label
+    assert_eq!((3, 1), error_at("here:\nexit\nhere:"));
+    // Bad data
+    assert_eq!((1, 5), error_at("[1, 256]"));
+    assert_eq!((1, 1), error_at("b'unterminated"));
+    // The scratch register cannot be pushed
+    assert_eq!((2, 6), error_at(".scratch r5\npush r5"));
+    // Nor tested, as loading the target would overwrite the condition
+    assert_eq!((1, 5), error_at("jnz r3, #0"));
+    assert_eq!((2, 5), error_at(".scratch r5\njnz r5, #0"));
+    assert_eq!((1, 1), error_at(".origin 12"));
+    assert_eq!((1, 10), error_at("jnz r1, #nowhere"));
+}
@@ -1,5 +1,5 @@
-use interpreter::{Machine, MEMORY_SIZE};
-use std::io::{self, Write};
+use interpreter::{Machine, MAX_MEMORY_SIZE, MEMORY_SIZE};
+use std::io::{self, Cursor, Write};
 
 #[test]
 fn create_with_memory() {
@@ -15,6 +15,22 @@ fn create_with_too_large_a_memory() {
     assert!(Machine::new(&[0; MEMORY_SIZE + 1]).is_err());
 }
 
+#[test]
+fn create_with_memory_size() {
+    let machine = Machine::new(&[1, 2, 3]).unwrap();
+    assert_eq!(MEMORY_SIZE, machine.memory().len());
+    let machine = Machine::with_memory_size(&[1, 2, 3], 65536).unwrap();
+    assert_eq!(65536, machine.memory().len());
+    assert_eq!(&[1, 2, 3, 0], &machine.memory()[..4]);
+    assert!(Machine::with_memory_size(&[], 0).is_ok());
+    let error = Machine::with_memory_size(&[1, 2, 3], 2).err().unwrap();
+    assert_eq!(
+        "program of 3 bytes does not fit in 2 bytes of memory",
+        error.to_string()
+    );
+    assert!(Machine::with_memory_size(&[], MAX_MEMORY_SIZE as usize + 1).is_err());
+}
+
 #[test]
 fn refuse_illegal_instruction() {
     let mut machine = Machine::new(&[]).unwrap();
@@ -140,6 +156,7 @@ fn test_load_out_of_bounds() {
 }
 
 #[test]
+#[allow(clippy::zero_prefixed_literal)]
 fn test_store() {
     // 0: store [r0] <- r1
     // 3:
@@ -247,6 +264,22 @@ fn test_out_number() {
     assert_eq!("-1234".as_bytes(), &out[..]);
 }
 
+#[test]
+fn out_number_zero_and_min() {
+    // 0: out_number r1
+    // 2:
+    let mut machine = Machine::new(&[8, 1]).unwrap();
+    let mut out = Vec::new();
+    expect_on(&mut machine, &mut out, false, 2);
+    assert_eq!("0".as_bytes(), &out[..]);
+
+    let mut machine = Machine::new(&[8, 1]).unwrap();
+    let mut out = Vec::new();
+    machine.set_reg(1, i32::MIN as u32).unwrap();
+    expect_on(&mut machine, &mut out, false, 2);
+    assert_eq!("-2147483648".as_bytes(), &out[..]);
+}
+
 #[test]
 fn test_run_on() {
     // 0: out_number r0
@@ -283,6 +316,7 @@ fn end_of_memory() {
 }
 
 #[test]
+#[allow(clippy::needless_range_loop)]
 fn no_wraparound_past_end_of_memory() {
     // memory_size-4: move r1 <- r1 if r1
     // 0:             exit
@@ -316,6 +350,29 @@ fn exec_over_end_of_memory() {
     assert!(machine.step().is_err());
 }
 
+#[test]
+fn bounds_follow_memory_size() {
+    // 0:    loadimm r1 <- #8192
+    // 4:    store [r1] <- r1
+    // 7:    load r2 <- [r1]
+    // 10:   exit
+    let program = [4, 1, 0x00, 0x20, 2, 1, 1, 3, 2, 1, 7];
+    let mut machine = Machine::new(&program).unwrap();
+    assert!(machine.run().is_err());
+    let mut machine = Machine::with_memory_size(&program, 8196).unwrap();
+    machine.run().unwrap();
+    assert_eq!(8192, machine.regs()[2]);
+    let mut machine = Machine::with_memory_size(&program, 8195).unwrap();
+    assert!(machine.run().is_err());
+
+    // Execution past the end of a larger memory
+    let mut machine = Machine::with_memory_size(&[], 8192).unwrap();
+    machine.set_reg(0, 8191).unwrap();
+    assert!(machine.step().is_err());
+    machine.set_memory(8191, &[7]).unwrap();
+    expect(&mut machine, true, 8192);
+}
+
 #[test]
 fn load_near_end_of_memory() {
     // 0: load r1 <- [r1]
@@ -358,6 +415,71 @@ fn store_near_end_of_address_space() {
     assert!(machine.step().is_err());
 }
 
+#[test]
+fn load_narrow() {
+    // 0: load8 r3 <- [r1]
+    // 3: load8s r4 <- [r1]
+    // 6: load16 r5 <- [r1]
+    // 9: load16s r6 <- [r1]
+    // 12: load16 r7 <- [r2]
+    // 15: exit
+    // 16: 0x80 0xff 0x7f
+    let mut machine = Machine::new(&[
+        11, 3, 1, 12, 4, 1, 13, 5, 1, 14, 6, 1, 13, 7, 2, 7, 0x80, 0xff, 0x7f,
+    ])
+    .unwrap();
+    machine.set_reg(1, 16).unwrap();
+    machine.set_reg(2, 17).unwrap();
+    machine.run_on(&mut io::sink()).unwrap();
+    assert_eq!(0x80, machine.regs()[3]);
+    assert_eq!(-128, machine.regs()[4] as i32);
+    assert_eq!(0xff80, machine.regs()[5]);
+    assert_eq!(-128, machine.regs()[6] as i32);
+    assert_eq!(0x7fff, machine.regs()[7]);
+}
+
+#[test]
+fn store_narrow() {
+    // 0: store8 [r1] <- r3
+    // 3: store16 [r2] <- r3
+    // 6: exit
+    let mut machine = Machine::new(&[15, 1, 3, 16, 2, 3, 7]).unwrap();
+    machine.set_reg(1, 100).unwrap();
+    machine.set_reg(2, 102).unwrap();
+    machine.set_reg(3, 0x1234_5678).unwrap();
+    machine.set_memory(100, &[0xaa; 6]).unwrap();
+    machine.run_on(&mut io::sink()).unwrap();
+    assert_eq!(
+        &[0x78, 0xaa, 0x78, 0x56, 0xaa, 0xaa],
+        &machine.memory()[100..106]
+    );
+}
+
+#[test]
+fn narrow_accesses_near_end_of_memory() {
+    let last = (MEMORY_SIZE - 1) as u32;
+    // The last byte can be accessed by load8 and store8, but not by the
+    // 16-bit variants
+    for (program, ok) in [
+        ([11, 1, 1], true),
+        ([12, 1, 1], true),
+        ([15, 1, 1], true),
+        ([13, 1, 1], false),
+        ([14, 1, 1], false),
+        ([16, 1, 1], false),
+    ] {
+        let mut machine = Machine::new(&program).unwrap();
+        machine.set_reg(1, last).unwrap();
+        assert_eq!(ok, machine.step().is_ok());
+        assert_eq!(3, machine.regs()[0]);
+    }
+    for opcode in 11..=16 {
+        let mut machine = Machine::new(&[opcode, 1, 1]).unwrap();
+        machine.set_reg(1, u32::MAX).unwrap();
+        assert!(machine.step().is_err());
+    }
+}
+
 #[test]
 fn sub_with_wraparound() {
     // 0: sub r1 <- r2 - r1
@@ -388,9 +510,71 @@ fn test_invalid_opcode() {
     // 4: exit
     // 5:
     let mut memory = [0, 7, 7, 7, 7];
-    for invalid in std::iter::once(0).chain(9..u8::MAX) {
+    for invalid in std::iter::once(0).chain(18..u8::MAX) {
         memory[0] = invalid;
         let mut machine = Machine::new(&memory).unwrap();
         assert!(machine.step().is_err());
     }
 }
+
+#[test]
+fn in_byte() {
+    // 0: in r1
+    // 2: in r2
+    // 4: in r3
+    // 6: exit
+    let mut machine = Machine::new(&[9, 1, 9, 2, 9, 3, 7]).unwrap();
+    let mut input = Cursor::new(b"A\xff");
+    machine.run_io(&mut input, &mut io::sink()).unwrap();
+    assert_eq!(65, machine.regs()[1]);
+    assert_eq!(255, machine.regs()[2]);
+    // End of input
+    assert_eq!(-1, machine.regs()[3] as i32);
+}
+
+#[test]
+fn in_number() {
+    // 0: in_number r1
+    // 2: in_number r2
+    // 4: in r3
+    // 6: exit
+    let mut machine = Machine::new(&[10, 1, 10, 2, 9, 3, 7]).unwrap();
+    let mut input = Cursor::new(b"  42\n-2147483648 x");
+    machine.run_io(&mut input, &mut io::sink()).unwrap();
+    assert_eq!(42, machine.regs()[1]);
+    assert_eq!(i32::MIN, machine.regs()[2] as i32);
+    // The space following the number is consumed
+    assert_eq!(u32::from(b'x'), machine.regs()[3]);
+}
+
+#[test]
+fn in_number_invalid() {
+    // 0: in_number r1
+    // 2: exit
+    for input in [&b""[..], b"  \n", b"x", b"12x", b"-", b"2147483648"] {
+        let mut machine = Machine::new(&[10, 1, 7]).unwrap();
+        assert!(machine
+            .step_io(&mut Cursor::new(input), &mut io::sink())
+            .is_err());
+    }
+    for (input, value) in [(&b"+7"[..], 7), (b"0", 0), (b"-0012\t", -12)] {
+        let mut machine = Machine::new(&[10, 1, 7]).unwrap();
+        expect_io(&mut machine, input, value);
+    }
+}
+
+fn expect_io(machine: &mut Machine, input: &[u8], value: i32) {
+    machine
+        .step_io(&mut Cursor::new(input), &mut io::sink())
+        .unwrap();
+    assert_eq!(value, machine.regs()[1] as i32);
+}
+
+#[test]
+fn in_without_input() {
+    // 0: in r1
+    // 2: exit
+    let mut machine = Machine::new(&[9, 1, 7]).unwrap();
+    machine.run_on(&mut io::sink()).unwrap();
+    assert_eq!(u32::MAX, machine.regs()[1]);
+}
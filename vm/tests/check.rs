@@ -0,0 +1,179 @@
+use interpreter::asm::assemble;
+use interpreter::check::{check, Analysis, Problem, ProblemKind};
+use interpreter::executable::{Executable, Section, SectionKind};
+use interpreter::MEMORY_SIZE;
+
+fn check_listing(listing: &str) -> Analysis {
+    let program = assemble(listing).unwrap();
+    check(&Executable::from_program(&program), MEMORY_SIZE)
+}
+
+#[test]
+fn control_flow_graph() {
+    let program = assemble(include_str!("fact.dis")).unwrap();
+    let analysis = check(&Executable::from_program(&program), MEMORY_SIZE);
+    assert!(analysis.problems.is_empty());
+    let label = |name: &str| program.labels[name];
+    let blocks = &analysis.cfg.blocks;
+
+    // Calls go to the function and return after the call
+    let entry = &blocks[&0];
+    assert_eq!(
+        vec![label("return_from_fact_1"), label("fact")],
+        entry.successors
+    );
+    assert!(blocks[&label("return_from_fact_1")].successors.is_empty());
+
+    // `loadimm r9 <- #ite_then_2` then `move r0 <- r9 if r8 != 0`
+    let fact_loop = &blocks[&label("fact_loop")];
+    assert_eq!(4, fact_loop.instructions.len());
+    assert_eq!(
+        vec![fact_loop.end, label("ite_then_2")],
+        fact_loop.successors
+    );
+    assert!(!fact_loop.indirect);
+    assert_eq!(
+        vec![label("fact_loop")],
+        blocks[&label("return_from_mult_1")].successors
+    );
+
+    // Returns jump to an unknown target
+    let ite_end_2 = &blocks[&label("ite_end_2")];
+    assert!(ite_end_2.indirect && ite_end_2.successors.is_empty());
+}
+
+#[test]
+fn invalid_instructions() {
+    let analysis = check(&Executable::raw(&[4, 16, 0, 0]), MEMORY_SIZE);
+    let kind = ProblemKind::InvalidRegister { reg: 16 };
+    assert_eq!(vec![Problem { address: 0, kind }], analysis.problems);
+
+    let analysis = check(&Executable::raw(&[4, 1]), 2);
+    let kind = ProblemKind::TruncatedInstruction;
+    assert_eq!(vec![Problem { address: 0, kind }], analysis.problems);
+
+    // Both paths of the conditional jump are checked
+    let analysis = check_listing(
+        "in r1\nloadimm r8 <- #bad\nmove r0 <- r8 if r1 != 0\nloadimm r0 <- #5000\nbad:\n[255]\n",
+    );
+    let problems = vec![
+        Problem {
+            address: 10,
+            kind: ProblemKind::JumpOutOfMemory { target: 5000 },
+        },
+        Problem {
+            address: 14,
+            kind: ProblemKind::UnknownOpcode { opcode: 255 },
+        },
+    ];
+    assert_eq!(problems, analysis.problems);
+    assert!(analysis.problems.iter().all(|p| p.kind.is_error()));
+
+    // Disabled extensions make opcodes unknown, whatever their operands
+    let analysis = check(&Executable::raw(&[32, 16, 0, 0]), MEMORY_SIZE);
+    let kind = ProblemKind::UnknownOpcode { opcode: 32 };
+    assert_eq!(vec![Problem { address: 0, kind }], analysis.problems);
+
+    // Unknown opcodes are only errors in reachable code
+    assert!(check_listing("exit\n[255]\n").problems.is_empty());
+}
+
+#[test]
+fn jump_into_instruction() {
+    // The immediate of the last `loadimm` is an `exit` instruction
+    let analysis = check_listing(
+        "in r1\nloadimm r8 <- #12\nmove r0 <- r8 if r1 != 0\nloadimm r1 <- #7\nexit\n",
+    );
+    let kind = ProblemKind::JumpIntoInstruction {
+        target: 12,
+        instruction: 10,
+    };
+    assert_eq!(vec![Problem { address: 6, kind }], analysis.problems);
+}
+
+#[test]
+fn unreachable_code() {
+    let analysis = check_listing("loadimm r0 <- #end\nout r1\nout r2\nend:\nexit\n");
+    let kind = ProblemKind::Unreachable { end: 8 };
+    assert!(!kind.is_error());
+    assert_eq!(vec![Problem { address: 4, kind }], analysis.problems);
+    assert_eq!(
+        vec![0, 8],
+        analysis.cfg.blocks.into_keys().collect::<Vec<_>>()
+    );
+
+    // Data is never executed
+    let analysis = check_listing(include_str!("../examples/99bottles.dis"));
+    assert!(analysis.problems.iter().all(|p| !p.kind.is_error()));
+}
+
+#[test]
+fn extensions() {
+    let program = assemble("add r1 <- r2 + r3\nexit\n").unwrap();
+    let mut executable = Executable::from_program(&program);
+    assert!(check(&executable, MEMORY_SIZE).problems.is_empty());
+    executable.arith_extension = false;
+    // The program faults before its `exit`
+    let problems = vec![
+        Problem {
+            address: 0,
+            kind: ProblemKind::UnknownOpcode { opcode: 32 },
+        },
+        Problem {
+            address: 4,
+            kind: ProblemKind::Unreachable { end: 5 },
+        },
+    ];
+    assert_eq!(problems, check(&executable, MEMORY_SIZE).problems);
+}
+
+#[test]
+fn section_out_of_memory() {
+    let mut executable = Executable::raw(&[0]);
+    executable.sections.push(Section {
+        kind: SectionKind::Data,
+        address: u32::MAX,
+        bytes: vec![1, 2],
+    });
+    let kind = ProblemKind::SectionOutOfMemory { end: 0x1_0000_0001 };
+    assert!(kind.is_error());
+    let analysis = check(&executable, MEMORY_SIZE);
+    assert_eq!(
+        vec![Problem {
+            address: u32::MAX,
+            kind
+        }],
+        analysis.problems
+    );
+    assert!(analysis.cfg.blocks.is_empty());
+}
+
+#[test]
+fn entry_out_of_memory() {
+    let executable = Executable {
+        entry: 5000,
+        ..Executable::raw(&[0])
+    };
+    let analysis = check(&executable, MEMORY_SIZE);
+    let kind = ProblemKind::EntryOutOfMemory;
+    assert!(kind.is_error());
+    assert_eq!(
+        vec![Problem {
+            address: 5000,
+            kind
+        }],
+        analysis.problems
+    );
+    assert!(analysis.cfg.blocks.is_empty());
+
+    // The entry is the last byte of the memory, zero, an unknown opcode
+    let analysis = check(&executable, 5001);
+    let kind = ProblemKind::UnknownOpcode { opcode: 0 };
+    assert_eq!(
+        vec![Problem {
+            address: 5000,
+            kind
+        }],
+        analysis.problems
+    );
+}
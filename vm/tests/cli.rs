@@ -0,0 +1,291 @@
+use std::process::{Command, Output};
+
+fn vm(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_vm"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn run_with_registers() {
+    let output = vm(&["run", "tests/fact.bin", "-r", "r10=5", "--print-regs"]);
+    assert_eq!(Some(0), output.status.code());
+    assert!(stderr(&output).contains("r11 = 0x00000078"));
+
+    let output = vm(&[
+        "run",
+        "tests/fact.bin",
+        "-r",
+        "r10=5",
+        "--print-regs",
+        "--decode-cache",
+    ]);
+    assert!(stderr(&output).contains("r11 = 0x00000078"));
+
+    // Listings are assembled
+    let output = vm(&["run", "examples/factorial.dis"]);
+    assert_eq!(Some(0), output.status.code());
+    assert!(output.stdout.ends_with(b"I'm done!\n"));
+}
+
+#[test]
+fn run_without_command() {
+    // `vm PROGRAM` runs the program, with the options of `run`
+    let output = vm(&["examples/factorial.dis"]);
+    assert_eq!(Some(0), output.status.code());
+    assert!(output.stdout.ends_with(b"I'm done!\n"));
+    let output = vm(&["tests/fact.bin", "-r", "r10=5", "--print-regs"]);
+    assert!(stderr(&output).contains("r11 = 0x00000078"));
+
+    // The program cannot be given along with a command
+    let output = vm(&["tests/fact.bin", "info"]);
+    assert_eq!(Some(2), output.status.code());
+    let output = vm(&["--max-steps", "3"]);
+    assert_eq!(Some(2), output.status.code());
+}
+
+#[test]
+fn exit_status() {
+    let path = std::env::temp_dir().join("vm-cli-exit-status.dis");
+    std::fs::write(&path, "loadimm r1 <- #42\nexit r1\n").unwrap();
+    let output = vm(&["run", path.to_str().unwrap()]);
+    assert_eq!(Some(42), output.status.code());
+    assert!(output.stderr.is_empty());
+
+    // A status of 1 is only told apart from a fault on request
+    std::fs::write(&path, "loadimm r1 <- #1\nexit r1\n").unwrap();
+    let output = vm(&["run", path.to_str().unwrap()]);
+    assert_eq!(Some(1), output.status.code());
+    let output = vm(&["run", path.to_str().unwrap(), "--no-exit-status"]);
+    assert_eq!(Some(6), output.status.code());
+    assert_eq!("Error: program exited with status 1\n", stderr(&output));
+}
+
+#[test]
+fn exit_codes() {
+    let output = vm(&["run", "tests/fact.bin", "--max-steps", "3"]);
+    assert_eq!(Some(4), output.status.code());
+    assert_eq!(
+        "Error: program still running after 3 instructions\n",
+        stderr(&output)
+    );
+
+    // The program does not fit in memory
+    let output = vm(&["run", "tests/fact.bin", "--memory-size", "8"]);
+    assert_eq!(Some(5), output.status.code());
+    assert_eq!(
+        "Error: program of 165 bytes does not fit in 8 bytes of memory\n",
+        stderr(&output)
+    );
+
+    let output = vm(&["run", "tests/does_not_exist.bin"]);
+    assert_eq!(Some(3), output.status.code());
+
+    let output = vm(&["run", "tests/fact.bin", "-r", "r16=1"]);
+    assert_eq!(Some(2), output.status.code());
+}
+
+#[test]
+fn fault() {
+    // fact(1) returns through the stack, which is outside a 256-byte memory
+    let output = vm(&["run", "tests/fact.dis", "--memory-size", "256"]);
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!(
+        "Error: 4-byte access at address 4092 out of memory at 0016: store [r2] <- r3 (02 02 03)\n",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn trace() {
+    let output = vm(&["trace", "tests/fact.bin", "-r", "r10=1"]);
+    assert_eq!(Some(0), output.status.code());
+    let trace = stderr(&output);
+    assert!(trace.starts_with("0000            loadimm r2 <- #4096 "));
+    // Addresses are named by the labels of tests/fact.dis
+    assert!(trace.contains("\nreturn_from_fact_1   exit\n"));
+    assert!(trace.contains("\nfact_loop+4     sub r8 <- r10 - r8 "));
+}
+
+#[test]
+fn info() {
+    let output = vm(&["info", "tests/fact.dis"]);
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "size: 165 bytes\n\
+         code: 165 bytes, 43 instructions\n\
+         data: 0 bytes\n\
+         labels: 10\n\
+         extensions: none\n",
+        String::from_utf8(output.stdout).unwrap()
+    );
+
+    // Pseudo-instructions count as all the instructions they expand to
+    let path = std::env::temp_dir().join("vm-cli-info.dis");
+    std::fs::write(&path, "call f\nexit\nf:\npush r4\nret\nmsg:\n[1, 2]\n").unwrap();
+    let output = vm(&["info", path.to_str().unwrap()]);
+    assert_eq!(
+        "size: 52 bytes\n\
+         code: 50 bytes, 14 instructions\n\
+         data: 2 bytes\n\
+         labels: 2\n\
+         extensions: none\n",
+        String::from_utf8(output.stdout).unwrap()
+    );
+}
+
+#[test]
+fn vmc() {
+    let dir = std::env::temp_dir();
+    let listing = dir.join("vm-cli-vmc.dis");
+    let output = Command::new(env!("CARGO_BIN_EXE_vmc"))
+        .args(["examples/factorial.vmc", "-o", listing.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert_eq!(Some(0), output.status.code());
+    let output = vm(&["run", listing.to_str().unwrap()]);
+    assert_eq!(Some(0), output.status.code());
+    assert!(output.stdout.ends_with(b"fact(10) = 3628800\nI'm done!\n"));
+
+    let source = dir.join("vm-cli-vmc.vmc");
+    std::fs::write(&source, "fn main() {\n    print x;\n}\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_vmc"))
+        .arg(&source)
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!(
+        format!("Error: {}:2:11: undefined variable `x`\n", source.display()),
+        stderr(&output)
+    );
+}
+
+#[test]
+fn link() {
+    let dir = std::env::temp_dir();
+    let object = dir.join("vm-cli-link-print.o");
+    let output = vm(&[
+        "asm",
+        "-c",
+        "examples/lib/print.dis",
+        "-o",
+        object.to_str().unwrap(),
+    ]);
+    assert_eq!(Some(0), output.status.code());
+    let image = dir.join("vm-cli-link.bin");
+    let output = vm(&[
+        "link",
+        "examples/hello_linked.dis",
+        object.to_str().unwrap(),
+        "-o",
+        image.to_str().unwrap(),
+    ]);
+    assert_eq!(Some(0), output.status.code());
+    let output = vm(&["run", image.to_str().unwrap()]);
+    assert_eq!(b"Hello, world!\n", &output.stdout[..]);
+
+    let output = vm(&[
+        "link",
+        "examples/hello_linked.dis",
+        "-o",
+        image.to_str().unwrap(),
+    ]);
+    assert_eq!(Some(5), output.status.code());
+    assert_eq!(
+        "Error: examples/hello_linked.dis: unresolved symbol `print`\n",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn executable() {
+    let path = std::env::temp_dir().join("vm-cli-executable.bin");
+    let path = path.to_str().unwrap();
+    // Start in the loop with a single bottle left
+    let output = vm(&[
+        "asm",
+        "examples/99bottles.dis",
+        "--executable",
+        "--entry",
+        "loop",
+        "-r",
+        "r2=4096",
+        "-r",
+        "r7=1",
+        "-o",
+        path,
+    ]);
+    assert_eq!(Some(0), output.status.code());
+    let output = vm(&["run", path]);
+    assert_eq!(Some(0), output.status.code());
+    assert!(output.stdout.starts_with(b"One bottle of beer on the wall"));
+
+    // Symbols name the functions of the profile
+    let output = vm(&["profile", path]);
+    assert!(stderr(&output).contains(" ubottles\n"));
+
+    let output = vm(&["asm", "examples/99bottles.dis", "--entry", "loop"]);
+    assert_eq!(Some(2), output.status.code());
+
+    // A section at the end of the address space does not fit in memory
+    let mut bytes = b"VMEX\x01\0\0\0".to_vec();
+    bytes.extend([0; 68]);
+    bytes.extend([1, 0, 0, 0, 1, 0xff, 0xff, 0xff, 0xff, 1, 0, 0, 0, 42]);
+    bytes.extend([0; 4]);
+    std::fs::write(path, bytes).unwrap();
+    for command in ["disasm", "info"] {
+        let output = vm(&[command, path]);
+        assert_eq!(Some(5), output.status.code());
+        assert!(stderr(&output)
+            .ends_with("program of 4294967296 bytes does not fit in 4096 bytes of memory\n"));
+    }
+}
+
+#[test]
+fn check() {
+    let output = vm(&["check", "tests/fact.dis", "--cfg"]);
+    assert_eq!(Some(0), output.status.code());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("\nfact_loop: 4 instructions -> fact_loop+16, ite_then_2\n"));
+    assert!(stdout.contains("\nite_end_2: 5 instructions -> ?\n"));
+
+    let path = std::env::temp_dir().join("vm-cli-check.dis");
+    std::fs::write(&path, "loadimm r0 <- #5000\n").unwrap();
+    let output = vm(&["check", path.to_str().unwrap()]);
+    assert_eq!(Some(5), output.status.code());
+    assert_eq!(
+        b"0000: error: jump to 5000, out of memory\n",
+        &output.stdout[..]
+    );
+    assert_eq!("Error: 1 error found\n", stderr(&output));
+
+    // An entry point past the end of the memory
+    let executable = std::env::temp_dir().join("vm-cli-check.vx");
+    let executable = executable.to_str().unwrap();
+    let output = vm(&[
+        "asm",
+        path.to_str().unwrap(),
+        "--executable",
+        "-r",
+        "r0=5000",
+        "-o",
+        executable,
+    ]);
+    assert_eq!(Some(0), output.status.code());
+    let output = vm(&["check", executable]);
+    assert_eq!(Some(5), output.status.code());
+    assert_eq!(
+        b"5000: error: entry point out of memory\n",
+        &output.stdout[..]
+    );
+
+    // The memory after the program is zero, an unknown opcode
+    let output = vm(&["check", path.to_str().unwrap(), "--memory-size", "8192"]);
+    assert_eq!(Some(5), output.status.code());
+    assert_eq!(b"5000: error: unknown opcode 0\n", &output.stdout[..]);
+}
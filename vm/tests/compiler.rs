@@ -0,0 +1,259 @@
+use interpreter::asm::assemble;
+use interpreter::compiler::{compile, compile_with_memory_size};
+use interpreter::{Machine, MEMORY_SIZE};
+
+// Compile and run `source` with `input`, returning its output and exit
+// status
+fn run(source: &str, input: &str) -> (String, u32) {
+    run_with_memory_size(source, input, MEMORY_SIZE)
+}
+
+fn run_with_memory_size(source: &str, input: &str, memory_size: usize) -> (String, u32) {
+    let listing = compile_with_memory_size(source, memory_size).unwrap();
+    let program = assemble(&listing).unwrap();
+    let mut machine = Machine::with_memory_size(&program.bytes, memory_size).unwrap();
+    let mut output = Vec::new();
+    machine.run_io(&mut input.as_bytes(), &mut output).unwrap();
+    (
+        String::from_utf8(output).unwrap(),
+        machine.exit_status().unwrap(),
+    )
+}
+
+fn run_original(image: &[u8]) -> String {
+    let mut machine = Machine::new(image).unwrap();
+    let mut output = Vec::new();
+    machine.run_on(&mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+fn error(source: &str) -> String {
+    compile(source).unwrap_err().to_string()
+}
+
+#[test]
+fn examples() {
+    let (output, status) = run(include_str!("../examples/factorial.vmc"), "");
+    assert_eq!(
+        run_original(include_bytes!("../examples/factorial.bin")),
+        output
+    );
+    assert_eq!(0, status);
+
+    let (output, _) = run(include_str!("../examples/99bottles.vmc"), "");
+    assert_eq!(
+        run_original(include_bytes!("../examples/99bottles.bin")),
+        output
+    );
+}
+
+#[test]
+fn stack_convention() {
+    let listing = compile("fn main() { return 3; }").unwrap();
+    assert!(listing.starts_with(
+        "  0000   loadimm r2 <- #4096\n\
+         \x20 0004   loadimm r3 <- #4\n\
+         \x20 0008   sub r2 <- r2 - r3\n\
+         \x20 0012   loadimm r3 <- #return_from_main_1\n\
+         \x20 0016   store [r2] <- r3\n\
+         \x20 0019   loadimm r0 <- #main\n\
+         return_from_main_1:\n\
+         \x20 0023   exit r4\n\
+         main:\n"
+    ));
+    assert_eq!(3, run("fn main() { return 3; }", "").1);
+}
+
+#[test]
+fn operators() {
+    // Ordering comparisons require the difference not to overflow
+    let values: [i32; 10] = [
+        0,
+        1,
+        -1,
+        7,
+        -7,
+        100,
+        -3,
+        40_000,
+        -1_000_000_000,
+        1_000_000_007,
+    ];
+    let mut source = String::from(
+        r#"
+        fn check(a, b) {
+            print a + b, " ", a - b, " ", a * b, " ";
+            print a == b, a != b, a < b, a <= b, a > b, a >= b, a && b, a || b, !a;
+            if b != 0 {
+                print " ", a / b, " ", a % b;
+            }
+            print "\n";
+        }
+        fn main() {
+            var i = 0;
+            while i < 10 {
+                var j = 0;
+                while j < 10 {
+                    check(value(i), value(j));
+                    j = j + 1;
+                }
+                i = i + 1;
+            }
+        }
+        fn value(i) {
+        "#,
+    );
+    let mut expected = String::new();
+    for (i, a) in values.into_iter().enumerate() {
+        source += &format!("if i == {i} {{ return {a}; }}\n");
+        for b in values {
+            let flag = |b: bool| u8::from(b);
+            expected += &format!(
+                "{} {} {} {}{}{}{}{}{}{}{}{}",
+                a.wrapping_add(b),
+                a - b,
+                a.wrapping_mul(b),
+                flag(a == b),
+                flag(a != b),
+                flag(a < b),
+                flag(a <= b),
+                flag(a > b),
+                flag(a >= b),
+                flag(a != 0 && b != 0),
+                flag(a != 0 || b != 0),
+                flag(a == 0),
+            );
+            if b != 0 {
+                expected += &format!(" {} {}", a / b, a % b);
+            }
+            expected.push('\n');
+        }
+    }
+    source += "}\n";
+    // The program does not fit in the default memory
+    let (output, _) = run_with_memory_size(&source, "", 8192);
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn large_constants() {
+    let (output, _) = run(
+        "fn main() { print 2147483647, \" \", 4294967295, \" \", -2147483648, \" \", 65536; }",
+        "",
+    );
+    assert_eq!("2147483647 -1 -2147483648 65536", output);
+}
+
+#[test]
+fn functions_and_variables() {
+    let source = r#"
+        // Mutual recursion, with calls nested in expressions
+        fn even(n) {
+            if n == 0 { return 1; }
+            return odd(n - 1);
+        }
+        fn odd(n) {
+            if n == 0 { return 0; }
+            return even(n - 1);
+        }
+        fn fibo(n) {
+            if n < 2 { return n; }
+            return fibo(n - 1) + fibo(n - 2);
+        }
+        fn sum(a, b, c) {
+            var total = a;
+            total = total + b;
+            return total + c;
+        }
+        fn nothing() {}
+        fn main() {
+            var n = input();
+            print even(n), odd(n), " ", fibo(n), " ", sum(1, sum(2, 3, 4), fibo(5)) * 2;
+            print " ", nothing(), 1 + (2 + (3 + (4 + (5 + 6))));
+            exit n;
+        }
+    "#;
+    assert_eq!(("10 55 30 021".to_owned(), 10), run(source, "10"));
+    assert_eq!(("01 13 30 021".to_owned(), 7), run(source, "7"));
+}
+
+#[test]
+fn division_by_zero() {
+    let (output, status) = run("fn main() { var a = 0; print 3 / a; }", "");
+    assert_eq!("division by zero\n", output);
+    assert_eq!(255, status);
+}
+
+#[test]
+fn errors() {
+    assert_eq!("1:1: no `main` function", error("fn f() {}"));
+    assert_eq!("1:4: `main` takes no parameter", error("fn main(x) {}"));
+    assert_eq!(
+        "1:13: undefined variable `x`",
+        error("fn main() { x = 1; }")
+    );
+    assert_eq!(
+        "2:22: undefined function `f`",
+        error("fn main() {\n    var x = 1; print f(x);\n}")
+    );
+    assert_eq!(
+        "1:30: `f` takes 1 arguments but 2 are given",
+        error("fn f(a) {} fn main() { print f(1, 2); }")
+    );
+    assert_eq!("1:23: expected `;`", error("fn main() { var x = 1 }"));
+    assert_eq!(
+        "1:17: `while` is a keyword",
+        error("fn main() { var while = 1; }")
+    );
+    assert_eq!(
+        "1:4: `__f`: names starting with `__` are reserved",
+        error("fn __f() {}")
+    );
+    assert_eq!(
+        "1:19: unterminated string",
+        error("fn main() { print \"abc; }")
+    );
+    assert_eq!(
+        "1:24: variable `x` is already declared",
+        error("fn main() { var x = 1; var x = 2; }")
+    );
+    assert_eq!(
+        "1:14: function `f` is defined twice",
+        error("fn f() {} fn f() {} fn main() {}")
+    );
+
+    // Temporaries of expressions nested too deeply do not fit in registers
+    let nested = format!(
+        "fn main() {{ print {}1{}; }}",
+        "1 + (".repeat(11),
+        ")".repeat(11)
+    );
+    assert!(error(&nested).ends_with(": expression too complex"));
+}
+
+#[test]
+fn generated_labels() {
+    // Functions may not take the labels of the generated code
+    assert_eq!(
+        "1:4: `str_1`: function names of the form of generated labels are reserved",
+        error("fn str_1() {} fn main() { print \"abc\"; }")
+    );
+    assert_eq!(
+        "1:4: `main_end`: function names of the form of generated labels are reserved",
+        error("fn main_end() {} fn main() {}")
+    );
+    assert!(error("fn return_from_f() {} fn main() {}").starts_with("1:4: "));
+    let (_, status) = run(
+        "fn str() { return 7; } fn ite_then() { return str(); } fn main() { return ite_then(); }",
+        "",
+    );
+    assert_eq!(7, status);
+
+    // Code which does not assemble is reported where it comes from
+    let body = "        x = x + 1;\n".repeat(3000);
+    let source = format!("fn main() {{\n    var x = 0;\n    while x < 10 {{\n{body}    }}\n}}\n");
+    assert_eq!(
+        "3:13: invalid generated code: address of label `__word` (102172) does not fit in an immediate",
+        error(&source)
+    );
+}
@@ -1,9 +1,17 @@
-use interpreter::Machine;
+use interpreter::{ErrorKind, Machine, RunOutcome};
+
+// Run to completion, failing instead of hanging if the program loops
+fn run(machine: &mut Machine) {
+    match machine.run_for(1_000_000) {
+        RunOutcome::Exited => (),
+        outcome => panic!("program did not exit: {outcome:?}"),
+    }
+}
 
 #[test]
 fn test_push_pop() {
     let mut machine = Machine::new(include_bytes!("push_pop.bin")).unwrap();
-    machine.run().unwrap();
+    run(&mut machine);
     assert_eq!(26, machine.regs()[1]);
     assert_eq!(15, machine.regs()[2]);
 }
@@ -11,7 +19,7 @@ fn test_push_pop() {
 #[test]
 fn test_function() {
     let mut machine = Machine::new(include_bytes!("function.bin")).unwrap();
-    machine.run().unwrap();
+    run(&mut machine);
     assert_eq!(42, machine.regs()[10]);
 }
 
@@ -24,7 +32,7 @@ fn test_mult() {
             let mut machine = Machine::new(include_bytes!("multiply.bin")).unwrap();
             machine.set_reg(11, *left as u32).unwrap();
             machine.set_reg(12, *right as u32).unwrap();
-            machine.run().unwrap();
+            run(&mut machine);
             assert_eq!(*left * *right, machine.regs()[11] as i32);
         }
     }
@@ -40,7 +48,7 @@ fn test_fact() {
     for i in 1..13 {
         let mut machine = Machine::new(include_bytes!("fact.bin")).unwrap();
         machine.set_reg(10, i).unwrap();
-        machine.run().unwrap();
+        run(&mut machine);
         assert_eq!(fact(i), machine.regs()[11]);
     }
 }
@@ -51,7 +59,7 @@ fn test_afact() {
     for i in 1..13 {
         let mut machine = Machine::new(include_bytes!("afact.bin")).unwrap();
         machine.set_reg(10, i).unwrap();
-        machine.run().unwrap();
+        run(&mut machine);
         assert_eq!(fact(i), machine.regs()[11]);
     }
 }
@@ -62,7 +70,7 @@ fn test_rfact() {
     for i in 1..13 {
         let mut machine = Machine::new(include_bytes!("rfact.bin")).unwrap();
         machine.set_reg(10, i).unwrap();
-        machine.run().unwrap();
+        run(&mut machine);
         assert_eq!(fact(i), machine.regs()[11]);
     }
 }
@@ -73,7 +81,7 @@ fn test_rfact_tr() {
     for i in 1..13 {
         let mut machine = Machine::new(include_bytes!("rfact_tr.bin")).unwrap();
         machine.set_reg(10, i).unwrap();
-        machine.run().unwrap();
+        run(&mut machine);
         assert_eq!(fact(i), machine.regs()[11]);
     }
 }
@@ -87,7 +95,95 @@ fn test_fibo() {
     for i in 1..20 {
         let mut machine = Machine::new(include_bytes!("fibo.bin")).unwrap();
         machine.set_reg(10, i).unwrap();
-        machine.run().unwrap();
+        run(&mut machine);
         assert_eq!(fibo(i), machine.regs()[11]);
     }
 }
+
+// Factorial of a number read from the input
+#[test]
+fn test_ask_factorial() {
+    for i in 0..13 {
+        let mut machine = Machine::new(include_bytes!("../examples/ask_factorial.bin")).unwrap();
+        let input = format!("{i}\n");
+        let mut output = Vec::new();
+        let outcome = machine.run_for_io(&mut input.as_bytes(), &mut output, 1_000_000);
+        assert!(matches!(outcome, RunOutcome::Exited));
+        let expected = format!("n? {}\n", fact(i).max(1));
+        assert_eq!(expected.as_bytes(), output);
+    }
+}
+
+// Line of the input reversed, kept in memory as bytes
+#[test]
+fn test_reverse() {
+    for (input, expected) in [
+        ("Hello, bytes\n", "setyb ,olleH\n"),
+        ("abc", "cba\n"),
+        ("", "\n"),
+    ] {
+        let mut machine = Machine::new(include_bytes!("../examples/reverse.bin")).unwrap();
+        let mut output = Vec::new();
+        let outcome = machine.run_for_io(&mut input.as_bytes(), &mut output, 1_000_000);
+        assert!(matches!(outcome, RunOutcome::Exited));
+        assert_eq!(expected.as_bytes(), output);
+    }
+}
+
+#[test]
+fn test_budget_exhausted() {
+    // 0: loadimm r1 <- #1, 4: loadimm r0 <- #0
+    let mut machine = Machine::new(&[4, 1, 1, 0, 4, 0, 0, 0]).unwrap();
+    assert!(matches!(machine.run_for(0), RunOutcome::BudgetExhausted));
+    assert_eq!(0, machine.executed_instructions());
+    assert!(matches!(machine.run_for(5), RunOutcome::BudgetExhausted));
+    assert_eq!(5, machine.executed_instructions());
+    assert_eq!(4, machine.regs()[0]);
+    assert!(matches!(machine.run_for(1000), RunOutcome::BudgetExhausted));
+    assert_eq!(1005, machine.executed_instructions());
+}
+
+#[test]
+fn test_executed_instructions() {
+    let mut machine = Machine::new(include_bytes!("push_pop.bin")).unwrap();
+    run(&mut machine);
+    let executed = machine.executed_instructions();
+    assert!(executed > 0);
+
+    // A budget matching the exact instruction count is enough to exit
+    let mut machine = Machine::new(include_bytes!("push_pop.bin")).unwrap();
+    assert!(matches!(
+        machine.run_for(executed - 1),
+        RunOutcome::BudgetExhausted
+    ));
+    assert!(matches!(machine.run_for(1), RunOutcome::Exited));
+    assert_eq!(executed, machine.executed_instructions());
+}
+
+#[test]
+fn test_faulted() {
+    // 0: loadimm r1 <- #1, 4: unknown opcode
+    let mut machine = Machine::new(&[4, 1, 1, 0, 0xff]).unwrap();
+    assert!(matches!(
+        machine.run_for(10),
+        RunOutcome::Faulted(e) if matches!(e.kind(), ErrorKind::UnknownOpcode { opcode: 0xff })
+    ));
+    assert_eq!(1, machine.executed_instructions());
+}
+
+#[test]
+fn test_exit_status() {
+    // 0: loadimm r1 <- #-3
+    // 4: exit r1
+    let mut machine = Machine::new(&[4, 1, 0xfd, 0xff, 17, 1]).unwrap();
+    machine.set_journal_capacity(10);
+    assert_eq!(None, machine.exit_status());
+    run(&mut machine);
+    assert_eq!(Some(-3), machine.exit_status().map(|status| status as i32));
+    machine.step_back();
+    assert_eq!(None, machine.exit_status());
+
+    let mut machine = Machine::new(&[7]).unwrap();
+    run(&mut machine);
+    assert_eq!(Some(0), machine.exit_status());
+}
@@ -0,0 +1,77 @@
+use interpreter::asm::assemble;
+use interpreter::coverage::{Coverage, Report};
+use interpreter::Machine;
+use std::io;
+
+const FACT: &str = include_str!("fact.dis");
+
+// Run fact.dis with r10 = n, accumulating the coverage into `coverage`
+fn run_fact(n: u32, coverage: Coverage) -> Coverage {
+    let mut machine = Machine::new(include_bytes!("fact.bin")).unwrap();
+    machine.set_reg(10, n).unwrap();
+    machine.set_coverage(coverage);
+    machine.run_on(&mut io::sink()).unwrap();
+    machine.take_coverage().unwrap()
+}
+
+#[test]
+fn single_run() {
+    // fact(1) never calls mult
+    let coverage = run_fact(1, Coverage::new());
+    let report = Report::new(FACT, &coverage).unwrap();
+    let annotated = report.annotated();
+    assert!(annotated.starts_with("        1:   0000   loadimm r2 <- #4096\n"));
+    assert!(annotated.contains("        -: mult:\n    #####:   0024   sub r13 <- r1 - r11\n"));
+    assert!(annotated.contains("    #####:   0111   move r12 <- r10 if r0 != 0\n"));
+    assert!(annotated.contains("        1:   0162   load r0 <- [r3]\n"));
+    assert_eq!(FACT.lines().count(), annotated.lines().count());
+
+    let program = assemble(FACT).unwrap();
+    let (found, hit) = report.lines();
+    assert_eq!(program.lines.len(), found);
+    assert_eq!(18, hit);
+    // return_from_fact_1, fact, fact_loop and ite_end_2
+    assert_eq!((10, 4), report.labels());
+    assert_eq!(
+        format!(
+            "Instructions executed: {:.2}% of {found}\nLabels reached: 40.00% of 10\n",
+            100.0 * 18.0 / found as f64
+        ),
+        report.summary()
+    );
+}
+
+#[test]
+fn several_runs() {
+    let mut coverage = run_fact(1, Coverage::new());
+    coverage = run_fact(3, coverage);
+    let report = Report::new(FACT, &coverage).unwrap();
+    let (found, hit) = report.lines();
+    assert_eq!(found, hit);
+    assert!(report
+        .summary()
+        .starts_with("Instructions executed: 100.00% of"));
+    // fact_loop is entered once by the first run and 3 times by the second
+    assert!(report
+        .annotated()
+        .contains("        4:   0091   loadimm r8 <- #1\n"));
+
+    // Merging separately collected coverage gives the same result
+    let mut merged = run_fact(1, Coverage::new());
+    merged.merge(&run_fact(3, Coverage::new()));
+    assert_eq!(coverage, merged);
+}
+
+#[test]
+fn lcov() {
+    let coverage = run_fact(1, Coverage::new());
+    let report = Report::new(FACT, &coverage).unwrap();
+    let lcov = report.lcov("tests/fact.dis");
+    assert!(lcov.starts_with("TN:\nSF:tests/fact.dis\nDA:1,1\nDA:2,1\n"));
+    // Line 10 holds the first instruction of mult
+    assert!(lcov.contains("\nDA:10,0\n"));
+    assert!(lcov.ends_with("\nLH:18\nend_of_record\n"));
+    let (found, _) = report.lines();
+    assert!(lcov.contains(&format!("\nLF:{found}\n")));
+    assert_eq!(found, lcov.matches("DA:").count());
+}
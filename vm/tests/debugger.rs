@@ -0,0 +1,146 @@
+use interpreter::asm::assemble;
+use interpreter::debugger::{format_regs, parse_number, parse_register, Debugger};
+use interpreter::Machine;
+
+fn session(listing: &str, setup: &[(usize, u32)], script: &str) -> (Debugger, String) {
+    let program = assemble(listing).unwrap();
+    let mut machine = Machine::new(&program.bytes).unwrap();
+    machine.set_journal_capacity(1000);
+    for &(reg, value) in setup {
+        machine.set_reg(reg, value).unwrap();
+    }
+    let mut debugger = Debugger::new(machine, program.bytes.len(), program.labels);
+    let mut out = Vec::new();
+    debugger.run(script.as_bytes(), &mut out, true).unwrap();
+    (debugger, String::from_utf8(out).unwrap())
+}
+
+#[test]
+fn breakpoints() {
+    let (debugger, out) = session(
+        include_str!("rfact.dis"),
+        &[(10, 3)],
+        "break rfact\ncontinue\ncontinue\ncontinue\ncontinue\n",
+    );
+    assert_eq!(3, out.matches("Breakpoint reached at 0087 <rfact>").count());
+    assert!(out.ends_with("The program has exited\n(vmdb) \n"));
+    assert_eq!(6, debugger.machine().regs()[11]);
+
+    let (_, out) = session(
+        include_str!("rfact.dis"),
+        &[],
+        "b 24\nb mult\nbreakpoints\nd 24\nbreakpoints\nd\nbreakpoints\nb nowhere",
+    );
+    assert!(out.contains(
+        "Breakpoint at 0024 <mult>\n(vmdb) breakpoints\nBreakpoint at 0024 <mult>\n(vmdb) d 24"
+    ));
+    assert!(out.contains("(vmdb) breakpoints\nNo breakpoints\n"));
+    assert!(out.contains("unknown location `nowhere`"));
+}
+
+#[test]
+fn step_and_next() {
+    let (debugger, out) = session(include_str!("rfact.dis"), &[(10, 5)], "step 5\nnext\nq\n");
+    assert!(out.contains("=> 0019   loadimm r0 <- #rfact\n"));
+    // The whole call to rfact was stepped over
+    assert!(out.contains("=> 0023 <return_from_rfact_1>   exit\n"));
+    assert_eq!(120, debugger.machine().regs()[11]);
+
+    // Not a call: next behaves like step
+    let (debugger, _) = session(include_str!("rfact.dis"), &[], "next\nnext\n");
+    assert_eq!(8, debugger.machine().regs()[0]);
+}
+
+#[test]
+fn inspect_and_modify() {
+    let (debugger, out) = session(
+        include_str!("rfact.dis"),
+        &[],
+        "step 5\nregs\nmem r2 4\nset r10 4\nset [r2] 0x1234\nset byte [4000] 255\nmem 4000 1\n",
+    );
+    assert!(out.contains("r0  = 0x00000013  r1  = 0x00000000  r2  = 0x00000ffc"));
+    assert!(out.contains("4092: 17 00 00 00\n"));
+    assert!(out.contains("4000: ff\n"));
+    assert_eq!(4, debugger.machine().regs()[10]);
+    assert_eq!(
+        &[0x34, 0x12, 0, 0],
+        &debugger.machine().memory()[4092..4096]
+    );
+}
+
+#[test]
+fn list() {
+    let (_, out) = session(
+        include_str!("rfact.dis"),
+        &[],
+        "break 4\ncontinue\nstep\nlist\n",
+    );
+    let expected = "\
+   0000   loadimm r2 <- #4096
+ *0004   loadimm r3 <- #4
+=>0008   sub r2 <- r2 - r3
+  0012   loadimm r3 <- #return_from_rfact_1
+  0016   store [r2] <- r3
+  0019   loadimm r0 <- #rfact
+return_from_rfact_1:
+  0023   exit
+mult:
+  0024   sub r13 <- r1 - r11
+  0028   move r14 <- r12 if r0 != 0
+";
+    assert!(out.contains(expected), "{out}");
+}
+
+#[test]
+fn errors() {
+    let (_, out) = session(
+        "loadimm r1 <- #5000\nload r2 <- [r1]\nexit\n",
+        &[],
+        "c\nfoo\nstep x\n",
+    );
+    assert!(out.contains(
+        "Error: 4-byte access at address 5000 out of memory at 0004: load r2 <- [r1] (03 02 01)\n\
+         => 0007   exit\n"
+    ));
+    assert!(out.contains("unknown command `foo`"));
+    assert!(out.contains("invalid count `x`"));
+}
+
+#[test]
+fn back_and_rewind() {
+    let (debugger, out) = session(
+        include_str!("rfact.dis"),
+        &[(10, 3)],
+        "step 5\nback 2\nc\nback\nrewind rfact\nregs\nrewind 0\nback\nrewind 9999\n",
+    );
+    assert!(out.contains("(vmdb) back 2\n=> 0012   loadimm r3 <- #return_from_rfact_1\n"));
+    assert!(
+        out.contains("The program has exited\n(vmdb) back\n=> 0023 <return_from_rfact_1>   exit\n")
+    );
+    // Last call to rfact, with r10 = 1
+    assert!(out.contains("(vmdb) rewind rfact\n=> 0087 <rfact>   loadimm r8 <- #1\n"));
+    assert!(out.contains("r8  = 0x00000001  r9  = 0x0000006f  r10 = 0x00000001"));
+    assert!(out.contains(
+        "(vmdb) rewind 0\n=> 0000   loadimm r2 <- #4096\n(vmdb) back\nNo more history\n"
+    ));
+    assert!(out.contains("9999 not found in the history\nNo more history\n"));
+    assert_eq!(0, debugger.machine().executed_instructions());
+}
+
+#[test]
+fn registers_and_numbers() {
+    assert_eq!(Some(15), parse_register("r15"));
+    assert_eq!(None, parse_register("r16"));
+    assert_eq!(None, parse_register("15"));
+    assert_eq!(Some(12), parse_number("12"));
+    assert_eq!(Some(u32::MAX), parse_number("-1"));
+    assert_eq!(Some(0x1f), parse_number("0x1f"));
+    assert_eq!(None, parse_number("twelve"));
+
+    let mut regs = [0; 16];
+    regs[5] = 0xabcd;
+    let text = format_regs(&regs);
+    assert_eq!(4, text.lines().count());
+    assert!(text.starts_with("r0  = 0x00000000  r1  = 0x00000000"));
+    assert!(text.contains("  r5  = 0x0000abcd  "));
+}
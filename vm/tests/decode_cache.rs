@@ -0,0 +1,153 @@
+use interpreter::asm::assemble;
+use interpreter::Machine;
+use std::time::Instant;
+
+// Run `image` with `r10` set to `arg`, returning the output, the registers
+// and the number of executed instructions
+fn run(image: &[u8], arg: u32, cache: bool) -> (Vec<u8>, Vec<u32>, u64) {
+    let mut machine = Machine::new(image).unwrap();
+    machine.set_decode_cache(cache);
+    assert_eq!(cache, machine.decode_cache());
+    machine.set_reg(10, arg).unwrap();
+    let mut output = Vec::new();
+    machine.run_on(&mut output).unwrap();
+    (
+        output,
+        machine.regs().to_vec(),
+        machine.executed_instructions(),
+    )
+}
+
+#[test]
+fn same_execution() {
+    let images: [(&[u8], u32); 6] = [
+        (include_bytes!("fact.bin"), 10),
+        (include_bytes!("rfact.bin"), 10),
+        (include_bytes!("rfact_tr.bin"), 10),
+        (include_bytes!("fibo.bin"), 15),
+        (include_bytes!("push_pop.bin"), 0),
+        (include_bytes!("../examples/99bottles.bin"), 0),
+    ];
+    for (image, arg) in images {
+        assert_eq!(run(image, arg, false), run(image, arg, true));
+    }
+}
+
+// The immediate of `patched` is incremented by each iteration, after the
+// instruction has been executed and cached
+const SELF_MODIFYING: &str = "
+    loadimm r5 <- #3
+loop:
+patched:
+    loadimm r4 <- #1
+    out_number r4
+    loadimm r6 <- #patched
+    loadimm r8 <- #-2
+    sub r6 <- r6 - r8
+    loadimm r8 <- #-1
+    sub r7 <- r4 - r8
+    store16 [r6] <- r7
+    loadimm r8 <- #1
+    sub r5 <- r5 - r8
+    loadimm r8 <- #loop
+    move r0 <- r8 if r5 != 0
+    exit
+";
+
+#[test]
+fn self_modifying_code() {
+    let program = assemble(SELF_MODIFYING).unwrap();
+    for cache in [false, true] {
+        let (output, _, _) = run(&program.bytes, 0, cache);
+        assert_eq!(b"123", &output[..]);
+    }
+}
+
+#[test]
+fn memory_changes() {
+    let program = assemble(SELF_MODIFYING).unwrap();
+    let patched = program.labels["patched"] as usize;
+
+    // Patching the program after the first iteration
+    let mut machine = Machine::new(&program.bytes).unwrap();
+    machine.set_decode_cache(true);
+    let mut output = Vec::new();
+    while machine.regs()[5] != 2 {
+        machine.step_on(&mut output).unwrap();
+    }
+    machine.set_memory(patched + 2, &[7, 0]).unwrap();
+    machine.run_on(&mut output).unwrap();
+    assert_eq!(b"178", &output[..]);
+
+    // Undoing the store over the program
+    let mut machine = Machine::new(&program.bytes).unwrap();
+    machine.set_decode_cache(true);
+    machine.set_journal_capacity(100);
+    let mut output = Vec::new();
+    for _ in 0..12 {
+        machine.step_on(&mut output).unwrap();
+    }
+    assert!(machine.run_back_to(patched as u32 + 4));
+    machine.run_on(&mut output).unwrap();
+    assert_eq!(b"1123", &output[..]);
+
+    // Restoring a snapshot taken before the store
+    let mut machine = Machine::new(&program.bytes).unwrap();
+    machine.set_decode_cache(true);
+    let snapshot = machine.snapshot();
+    machine.run_on(&mut Vec::new()).unwrap();
+    machine.restore(&snapshot).unwrap();
+    let mut output = Vec::new();
+    machine.run_on(&mut output).unwrap();
+    assert_eq!(b"123", &output[..]);
+}
+
+#[test]
+fn code_at_the_end_of_a_large_memory() {
+    // Only the pages of the cache holding executed code are allocated, and
+    // the first instruction crosses the boundary of two pages
+    let program = assemble("loadimm r4 <- #5\nout_number r4\nexit\n").unwrap();
+    let address = (1 << 26) - 4096 - 2;
+    let mut machine = Machine::with_memory_size(&[], 1 << 26).unwrap();
+    machine.set_decode_cache(true);
+    machine.set_memory(address, &program.bytes).unwrap();
+    for (immediate, expected) in [(5, b"5"), (7, b"7")] {
+        // Changing the immediate of the cached instruction
+        machine.set_memory(address + 2, &[immediate, 0]).unwrap();
+        machine.set_reg(0, address as u32).unwrap();
+        let mut output = Vec::new();
+        machine.run_on(&mut output).unwrap();
+        assert_eq!(expected, &output[..]);
+    }
+}
+
+// Time 300 runs of 99bottles and a run of fibo(27), with and without the
+// cache: `cargo test --release --test decode_cache -- --ignored --nocapture`
+#[test]
+#[ignore = "benchmark"]
+fn speed() {
+    let images: [(&str, &[u8], u32, usize); 2] = [
+        (
+            "99bottles",
+            include_bytes!("../examples/99bottles.bin"),
+            0,
+            300,
+        ),
+        ("fibo(27)", include_bytes!("fibo.bin"), 27, 1),
+    ];
+    for (name, image, arg, runs) in images {
+        let mut times = [0.0; 2];
+        for (time, cache) in times.iter_mut().zip([false, true]) {
+            let start = Instant::now();
+            for _ in 0..runs {
+                run(image, arg, cache);
+            }
+            *time = start.elapsed().as_secs_f64();
+        }
+        let [plain, cached] = times;
+        println!(
+            "{name}: {plain:.3}s without the cache, {cached:.3}s with it, {:.0}% less",
+            100.0 * (1.0 - cached / plain)
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use interpreter::asm::assemble;
+use interpreter::device::{Console, CycleCounter, Device, Rng};
+use interpreter::{ErrorKind, Machine};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// Output shared between a device and the test
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn machine(listing: &str) -> Machine {
+    Machine::new(&assemble(listing).unwrap().bytes).unwrap()
+}
+
+#[test]
+fn console() {
+    let mut machine = machine(
+        "  loadimm r1 <- #-16
+           loadimm r2 <- #72
+           store [r1] <- r2
+           loadimm r2 <- #0x169
+           store [r1] <- r2
+           load r3 <- [r1]
+           load r4 <- [r1]
+           exit",
+    );
+    let output = Shared::default();
+    let console = Console::new(&b"x"[..], output.clone());
+    machine
+        .map_device(0xffff_fff0..0xffff_fff4, Box::new(console))
+        .unwrap();
+    machine.run_on(&mut io::sink()).unwrap();
+    assert_eq!(b"Hi", &output.0.borrow()[..]);
+    assert_eq!(u32::from(b'x'), machine.regs()[3]);
+    // End of input
+    assert_eq!(u32::MAX, machine.regs()[4]);
+}
+
+// Narrow accesses are made of byte accesses
+#[test]
+fn narrow_console_accesses() {
+    let mut machine = machine(
+        "  loadimm r1 <- #-16
+           loadimm r2 <- #0x4869
+           store8 [r1] <- r2
+           load8 r3 <- [r1]
+           load8s r4 <- [r1]
+           exit",
+    );
+    let output = Shared::default();
+    let console = Console::new(&b"a\xe9"[..], output.clone());
+    machine
+        .map_device(0xffff_fff0..0xffff_fff4, Box::new(console))
+        .unwrap();
+    machine.run_on(&mut io::sink()).unwrap();
+    assert_eq!(b"i", &output.0.borrow()[..]);
+    assert_eq!(u32::from(b'a'), machine.regs()[3]);
+    assert_eq!(0xe9_u8 as i8 as u32, machine.regs()[4]);
+}
+
+#[test]
+fn cycle_counter() {
+    let mut machine = machine(
+        "  loadimm r1 <- #8192
+           loadimm r2 <- #0
+           load r3 <- [r1]
+           store [r1] <- r2
+           load r4 <- [r1]
+           exit",
+    );
+    machine
+        .map_device(8192..8200, Box::new(CycleCounter::new()))
+        .unwrap();
+    machine.run_on(&mut io::sink()).unwrap();
+    assert_eq!(2, machine.regs()[3]);
+    assert_eq!(4, machine.regs()[4]);
+}
+
+#[test]
+fn rng() {
+    let listing = "  loadimm r1 <- #-4
+                     load r3 <- [r1]
+                     load r4 <- [r1]
+                     loadimm r2 <- #42
+                     store [r1] <- r2
+                     load r5 <- [r1]
+                     exit";
+    let mut first = machine(listing);
+    first
+        .map_device(0xffff_fffc..0x1_0000_0000, Box::new(Rng::new(42)))
+        .unwrap();
+    first.run_on(&mut io::sink()).unwrap();
+    let mut second = machine(listing);
+    second
+        .map_device(0xffff_fffc..0x1_0000_0000, Box::new(Rng::new(42)))
+        .unwrap();
+    second.run_on(&mut io::sink()).unwrap();
+    assert_eq!(first.regs(), second.regs());
+    assert_ne!(first.regs()[3], first.regs()[4]);
+    // Reseeding with the initial seed restarts the sequence
+    assert_eq!(first.regs()[3], first.regs()[5]);
+}
+
+// A device relying on the default word accesses
+struct Bytes([u8; 8]);
+
+impl Device for Bytes {
+    fn read_byte(&mut self, offset: u32) -> io::Result<u8> {
+        Ok(self.0[offset as usize])
+    }
+
+    fn write_byte(&mut self, offset: u32, value: u8) -> io::Result<()> {
+        self.0[offset as usize] = value;
+        Ok(())
+    }
+}
+
+#[test]
+fn shadowed_memory() {
+    let mut machine = machine(
+        "  loadimm r1 <- #100
+           loadimm r2 <- #0x1234
+           store [r1] <- r2
+           loadimm r1 <- #101
+           load r3 <- [r1]
+           exit",
+    );
+    machine
+        .map_device(100..108, Box::new(Bytes([0; 8])))
+        .unwrap();
+    machine.run_on(&mut io::sink()).unwrap();
+    assert_eq!(0x12, machine.regs()[3]);
+    assert_eq!(&[0; 8], &machine.memory()[100..108]);
+}
+
+#[test]
+fn partial_access() {
+    let mut machine = machine(
+        "  loadimm r1 <- #98
+           load r3 <- [r1]
+           exit",
+    );
+    machine
+        .map_device(100..108, Box::new(Bytes([0; 8])))
+        .unwrap();
+    assert!(matches!(
+        machine.run_on(&mut io::sink()),
+        Err(e) if matches!(e.kind(), ErrorKind::MisalignedAccess { address: 98, len: 4 })
+    ));
+}
+
+#[test]
+fn invalid_ranges() {
+    let mut machine = Machine::new(&[]).unwrap();
+    assert!(matches!(
+        machine.map_device(8..8, Box::new(CycleCounter::new())),
+        Err(e) if matches!(e.kind(), ErrorKind::InvalidDeviceRange)
+    ));
+    machine
+        .map_device(8..16, Box::new(CycleCounter::new()))
+        .unwrap();
+    assert!(matches!(
+        machine.map_device(12..20, Box::new(CycleCounter::new())),
+        Err(e) if matches!(e.kind(), ErrorKind::InvalidDeviceRange)
+    ));
+    assert!(matches!(
+        machine.map_device(0xffff_fffc..0x1_0000_0001, Box::new(CycleCounter::new())),
+        Err(e) if matches!(e.kind(), ErrorKind::InvalidDeviceRange)
+    ));
+    machine
+        .map_device(16..24, Box::new(CycleCounter::new()))
+        .unwrap();
+}
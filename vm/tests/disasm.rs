@@ -0,0 +1,148 @@
+use interpreter::asm::assemble;
+use interpreter::disasm::{disassemble, disassemble_folded};
+use std::collections::BTreeSet;
+
+/// Replace label references by the address they stand for and drop label
+/// definitions, so that listings can be compared modulo label names.
+fn normalize(listing: &str) -> (Vec<String>, BTreeSet<u32>) {
+    let labels = assemble(listing).unwrap().labels;
+    let lines = listing
+        .lines()
+        .filter(|line| !line.ends_with(':'))
+        .map(|line| match line.split_once('#') {
+            Some((start, label)) if labels.contains_key(label) => {
+                format!("{start}#{}", labels[label])
+            }
+            _ => line.to_owned(),
+        })
+        .collect();
+    (lines, labels.values().copied().collect())
+}
+
+fn check(listing: &str, binary: &[u8]) {
+    let output = disassemble(binary);
+    assert_eq!(binary, &assemble(&output).unwrap().bytes[..]);
+    assert_eq!(normalize(listing), normalize(&output));
+}
+
+#[test]
+fn disassemble_tests() {
+    check(include_str!("afact.dis"), include_bytes!("afact.bin"));
+    check(include_str!("fact.dis"), include_bytes!("fact.bin"));
+    check(include_str!("fibo.dis"), include_bytes!("fibo.bin"));
+    check(include_str!("function.dis"), include_bytes!("function.bin"));
+    check(include_str!("multiply.dis"), include_bytes!("multiply.bin"));
+    check(include_str!("push_pop.dis"), include_bytes!("push_pop.bin"));
+    check(include_str!("rfact.dis"), include_bytes!("rfact.bin"));
+    check(include_str!("rfact_tr.dis"), include_bytes!("rfact_tr.bin"));
+}
+
+#[test]
+fn disassemble_examples() {
+    check(
+        include_str!("../examples/ask_factorial.dis"),
+        include_bytes!("../examples/ask_factorial.bin"),
+    );
+    check(
+        include_str!("../examples/99bottles.dis"),
+        include_bytes!("../examples/99bottles.bin"),
+    );
+    check(
+        include_str!("../examples/count.dis"),
+        include_bytes!("../examples/count.bin"),
+    );
+    check(
+        include_str!("../examples/factorial.dis"),
+        include_bytes!("../examples/factorial.bin"),
+    );
+    check(
+        include_str!("../examples/fibonacci.dis"),
+        include_bytes!("../examples/fibonacci.bin"),
+    );
+    check(
+        include_str!("../examples/hello_world.dis"),
+        include_bytes!("../examples/hello_world.bin"),
+    );
+}
+
+#[test]
+fn synthesized_labels() {
+    // 0: loadimm r5 <- #9
+    // 4: move r0 <- r5 if r1 != 0
+    // 8: exit
+    // 9: out_number r1
+    // 11: exit
+    let output = disassemble(&[4, 5, 9, 0, 1, 0, 5, 1, 7, 8, 1, 7]);
+    let expected = "  0000   loadimm r5 <- #label_1
+  0004   move r0 <- r5 if r1 != 0
+  0008   exit
+label_1:
+  0009   out_number r1
+  0011   exit
+";
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn trailing_data() {
+    // 0: loadimm r1 <- #5
+    // 4: exit
+    // 5: data
+    let mut binary = vec![4, 1, 5, 0, 7];
+    binary.extend(b"it's\n");
+    let output = disassemble(&binary);
+    assert!(output.ends_with("  0004   exit\nstr_1:\n  ???? b\"it's\\n\"\n"));
+
+    // Unreferenced, non-textual data
+    let output = disassemble(&[7, 0, 1, 2]);
+    assert_eq!("  0000   exit\n  ???? [0, 1, 2]\n", output);
+}
+
+#[test]
+fn unreachable_code() {
+    // 0: loadimm r0 <- #5
+    // 4: invalid
+    // 5: exit
+    let output = disassemble(&[4, 0, 5, 0, 0, 7]);
+    let labels = assemble(&output).unwrap().labels;
+    assert_eq!(Some(&5), labels.get("label_1"));
+    assert!(output.contains("  ???? [0]\nlabel_1:\n  0005   exit\n"));
+}
+
+#[test]
+fn folded() {
+    let images: [&[u8]; 6] = [
+        include_bytes!("fact.bin"),
+        include_bytes!("function.bin"),
+        include_bytes!("push_pop.bin"),
+        include_bytes!("rfact.bin"),
+        include_bytes!("../examples/99bottles.bin"),
+        include_bytes!("../examples/factorial.bin"),
+    ];
+    for image in images {
+        for scratch in [3, 5, 9] {
+            let output = disassemble_folded(image, scratch);
+            assert_eq!(image, &assemble(&output).unwrap().bytes[..]);
+        }
+    }
+
+    let output = disassemble_folded(include_bytes!("rfact.bin"), 3);
+    assert!(output.starts_with("  0000   loadimm r2 <- #4096\n  0004   call label_6\nlabel_1:\n"));
+    assert!(output.contains("\nlabel_5:\n  0068   ret\nlabel_6:\n"));
+    assert!(output.contains("  0064   jmp label_3\n"));
+    assert!(output.contains("  0111   push r10\n"));
+
+    // Only sequences using the given scratch register are folded
+    let output = disassemble_folded(include_bytes!("rfact.bin"), 9);
+    assert!(output
+        .starts_with(".scratch r9\n  0000   loadimm r2 <- #4096\n  0004   loadimm r3 <- #4\n"));
+    assert!(output.contains("  0040   jnz r8, label_4\n"));
+
+    // The scratch register cannot be the condition of a `jnz`
+    let image = assemble("loadimm r3 <- #end\nmove r0 <- r3 if r3 != 0\nend:\nexit\n")
+        .unwrap()
+        .bytes;
+    let output = disassemble_folded(&image, 3);
+    assert!(!output.contains("jnz"));
+    assert_eq!(image, assemble(&output).unwrap().bytes);
+}
@@ -0,0 +1,158 @@
+use interpreter::device::Device;
+use interpreter::{Error, ErrorKind, Machine};
+use std::error::Error as _;
+use std::io::{self, Write};
+
+fn run(memory: &[u8]) -> Error {
+    let mut machine = Machine::new(memory).unwrap();
+    machine.run_on(&mut io::sink()).unwrap_err()
+}
+
+#[test]
+fn set_invalid_register() {
+    let mut machine = Machine::new(&[]).unwrap();
+    assert!(machine.set_reg(15, 1).is_ok());
+    let e = machine.set_reg(16, 1).unwrap_err();
+    assert!(matches!(e.kind(), ErrorKind::InvalidRegister { reg: 16 }));
+    assert_eq!(None, e.ip());
+    assert_eq!("invalid register r16", e.to_string());
+}
+
+#[test]
+fn invalid_register() {
+    // 0: loadimm r1 <- #0
+    // 4: sub r1 <- r1 - r20
+    let e = run(&[4, 1, 0, 0, 5, 1, 1, 20]);
+    assert!(matches!(e.kind(), ErrorKind::InvalidRegister { reg: 20 }));
+    assert_eq!(Some(4), e.ip());
+    assert_eq!(&[5, 1, 1, 20], e.bytes());
+    assert_eq!("invalid register r20 at 0004 (05 01 01 14)", e.to_string());
+}
+
+#[test]
+fn unknown_opcode() {
+    let e = run(&[7 + 0x40]);
+    assert!(matches!(
+        e.kind(),
+        ErrorKind::UnknownOpcode { opcode: 0x47 }
+    ));
+    assert_eq!("unknown opcode 71 at 0000 (47)", e.to_string());
+}
+
+#[test]
+fn truncated_instruction() {
+    let mut machine = Machine::with_memory_size(&[4, 1, 0, 0, 5, 1], 6).unwrap();
+    let e = machine.run_on(&mut io::sink()).unwrap_err();
+    assert!(matches!(e.kind(), ErrorKind::TruncatedInstruction));
+    assert_eq!(Some(4), e.ip());
+    assert_eq!(&[5, 1], e.bytes());
+
+    // Running off the end of the memory
+    let mut machine = Machine::with_memory_size(&[4, 1, 0, 0], 4).unwrap();
+    let e = machine.run_on(&mut io::sink()).unwrap_err();
+    assert!(matches!(e.kind(), ErrorKind::TruncatedInstruction));
+    assert_eq!(Some(4), e.ip());
+    assert!(e.bytes().is_empty());
+}
+
+#[test]
+fn out_of_range() {
+    // 0: loadimm r1 <- #4094
+    // 4: load16 r2 <- [r1]
+    // 7: store [r1] <- r2
+    let mut machine = Machine::new(&[4, 1, 0xfe, 0x0f, 13, 2, 1, 2, 1, 2]).unwrap();
+    let e = machine.run_on(&mut io::sink()).unwrap_err();
+    assert!(matches!(
+        e.kind(),
+        ErrorKind::OutOfRange {
+            address: 4094,
+            len: 4
+        }
+    ));
+    assert_eq!(Some(7), e.ip());
+    assert_eq!(
+        "4-byte access at address 4094 out of memory at 0007: store [r1] <- r2 (02 01 02)",
+        e.to_string()
+    );
+}
+
+struct Word;
+
+impl Device for Word {
+    fn read_byte(&mut self, _offset: u32) -> io::Result<u8> {
+        Ok(0)
+    }
+
+    fn write_byte(&mut self, _offset: u32, _value: u8) -> io::Result<()> {
+        Err(io::Error::other("read-only"))
+    }
+}
+
+#[test]
+fn misaligned_access() {
+    // 0: loadimm r1 <- #102
+    // 4: load r2 <- [r1]
+    let mut machine = Machine::new(&[4, 1, 102, 0, 3, 2, 1]).unwrap();
+    machine.map_device(100..104, Box::new(Word)).unwrap();
+    let e = machine.run_on(&mut io::sink()).unwrap_err();
+    assert!(matches!(
+        e.kind(),
+        ErrorKind::MisalignedAccess {
+            address: 102,
+            len: 4
+        }
+    ));
+    assert_eq!(Some(4), e.ip());
+}
+
+#[test]
+fn device_write_failure() {
+    // 0: loadimm r1 <- #100
+    // 4: store8 [r1] <- r1
+    let mut machine = Machine::new(&[4, 1, 100, 0, 15, 1, 1]).unwrap();
+    machine.map_device(100..104, Box::new(Word)).unwrap();
+    let e = machine.run_on(&mut io::sink()).unwrap_err();
+    assert!(matches!(e.kind(), ErrorKind::WriteFailure(_)));
+    assert_eq!("read-only", e.source().unwrap().to_string());
+}
+
+struct Full;
+
+impl Write for Full {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::WriteZero.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn output_write_failure() {
+    // 0: loadimm r1 <- #65
+    // 4: out r1
+    let mut machine = Machine::new(&[4, 1, 65, 0, 6, 1]).unwrap();
+    let e = machine.run_on(&mut Full).unwrap_err();
+    match e.kind() {
+        ErrorKind::WriteFailure(source) => assert_eq!(io::ErrorKind::WriteZero, source.kind()),
+        kind => panic!("unexpected error {kind:?}"),
+    }
+    assert_eq!(Some(4), e.ip());
+    assert_eq!(&[6, 1], e.bytes());
+    assert!(e.to_string().starts_with("write failure: "));
+    assert!(e.to_string().ends_with(" at 0004: out r1 (06 01)"));
+}
+
+#[test]
+fn division_by_zero() {
+    // 0: divu r1 <- r2 / r3
+    let mut machine = Machine::new(&[35, 1, 2, 3]).unwrap();
+    machine.set_arith_extension(true);
+    let e = machine.run_on(&mut io::sink()).unwrap_err();
+    assert!(matches!(e.kind(), ErrorKind::DivisionByZero));
+    assert_eq!(
+        "division by zero at 0000: divu r1 <- r2 / r3 (23 01 02 03)",
+        e.to_string()
+    );
+}
@@ -0,0 +1,154 @@
+use interpreter::asm::assemble;
+use interpreter::executable::{Executable, Section, SectionKind, VERSION};
+use interpreter::{ErrorKind, Machine, MEMORY_SIZE};
+use std::io;
+
+#[test]
+fn sections_and_symbols() {
+    let program = assemble(include_str!("../examples/99bottles.dis")).unwrap();
+    let executable = Executable::from_program(&program);
+    let kinds: Vec<SectionKind> = executable.sections.iter().map(|s| s.kind).collect();
+    assert_eq!(vec![SectionKind::Code, SectionKind::Data], kinds);
+    assert_eq!(program.labels["str_1"], executable.sections[1].address);
+    assert_eq!(program.labels, executable.symbols);
+    assert_eq!(program.bytes, executable.memory(MEMORY_SIZE).unwrap());
+    assert!(!executable.arith_extension);
+
+    let arith = assemble("add r1 <- r2 + r3\nexit\n").unwrap();
+    assert!(Executable::from_program(&arith).arith_extension);
+}
+
+#[test]
+fn pseudo_instruction_sections() {
+    // Every instruction of an expansion is code
+    let program = assemble(PSEUDO).unwrap();
+    let executable = Executable::from_program(&program);
+    let kinds: Vec<SectionKind> = executable.sections.iter().map(|s| s.kind).collect();
+    assert_eq!(vec![SectionKind::Code, SectionKind::Data], kinds);
+    assert_eq!(program.labels["msg"], executable.sections[1].address);
+    assert_eq!(22, program.instructions().len());
+    assert_eq!(Some(&(4..23)), program.ranges.get(&2));
+}
+
+const PSEUDO: &str = "loadimm r2 <- #4096\ncall f\nexit\nf:\npush r4\npop r4\n\
+                      jnz r1, #0\nret\nmsg:\n[1, 2]\n";
+
+#[test]
+fn round_trip() {
+    let program = assemble(include_str!("../examples/99bottles.dis")).unwrap();
+    let mut executable = Executable::from_program(&program);
+    executable.entry = 8;
+    executable.regs[2] = 4096;
+    executable.regs[7] = 99;
+    let bytes = executable.encode();
+    assert!(bytes.starts_with(b"VMEX"));
+    assert_eq!(VERSION.to_le_bytes(), bytes[4..8]);
+    // r0 is stored as the entry point
+    executable.regs[0] = 8;
+    assert_eq!(executable, Executable::decode(&bytes).unwrap());
+
+    // Skipping the setup of the stack and counter at the entry point
+    let mut output = Vec::new();
+    let mut machine = Machine::load_image(&bytes, MEMORY_SIZE).unwrap();
+    machine.run_on(&mut output).unwrap();
+    let mut expected = Vec::new();
+    Machine::new(include_bytes!("../examples/99bottles.bin"))
+        .unwrap()
+        .run_on(&mut expected)
+        .unwrap();
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn raw_images() {
+    let image = include_bytes!("fact.bin");
+    let mut machine = Machine::load_image(image, MEMORY_SIZE).unwrap();
+    assert_eq!(Machine::new(image).unwrap().memory(), machine.memory());
+    assert!(machine.regs().iter().all(|&reg| reg == 0));
+    machine.set_reg(10, 5).unwrap();
+    machine.run_on(&mut io::sink()).unwrap();
+    assert_eq!(120, machine.regs()[11]);
+
+    assert_eq!(Executable::raw(image), Executable::parse(image).unwrap());
+    assert!(Executable::parse(&[]).unwrap().sections.is_empty());
+}
+
+#[test]
+fn loading() {
+    let executable = Executable {
+        entry: 100,
+        regs: [1; 16],
+        arith_extension: true,
+        sections: vec![Section {
+            kind: SectionKind::Data,
+            address: 4000,
+            bytes: vec![1, 2, 3],
+        }],
+        ..Executable::default()
+    };
+    let machine = Machine::load_image(&executable.encode(), MEMORY_SIZE).unwrap();
+    assert_eq!(100, machine.regs()[0]);
+    assert_eq!(&[1; 15], &machine.regs()[1..]);
+    assert!(machine.arith_extension());
+    assert_eq!(&[1, 2, 3, 0], &machine.memory()[4000..4004]);
+
+    // The section does not fit in memory
+    let error = Machine::load_image(&executable.encode(), 4002)
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error.kind(),
+        ErrorKind::ProgramTooLarge {
+            end: 4003,
+            size: 4002
+        }
+    ));
+
+    // Nor is its image allocated, however far it ends
+    let mut far = executable.clone();
+    far.sections[0].address = u32::MAX;
+    let bytes = Executable::decode(&far.encode()).unwrap();
+    let error = bytes.memory(MEMORY_SIZE).unwrap_err();
+    assert!(matches!(
+        error.kind(),
+        ErrorKind::ProgramTooLarge {
+            end: 0x1_0000_0002,
+            size: MEMORY_SIZE
+        }
+    ));
+    assert!(Machine::with_executable(&far, MEMORY_SIZE).is_err());
+}
+
+#[test]
+fn invalid() {
+    let invalid = |bytes: &[u8]| matches!(Executable::parse(bytes), Err(e) if matches!(e.kind(), ErrorKind::InvalidExecutable));
+    let program = assemble(include_str!("../examples/99bottles.dis")).unwrap();
+    let mut executable = Executable::from_program(&program);
+    let bytes = executable.encode();
+    assert!(invalid(b"VMEX"));
+    assert!(invalid(&bytes[..bytes.len() - 1]));
+    let mut extra = bytes.clone();
+    extra.push(0);
+    assert!(invalid(&extra));
+    let mut version = bytes.clone();
+    version[4] = 2;
+    assert!(invalid(&version));
+    let mut flags = bytes.clone();
+    flags[8] = 2;
+    assert!(invalid(&flags));
+
+    executable.sections[1].address -= 1;
+    assert!(invalid(&executable.encode()));
+
+    // A symbol is named twice
+    let executable = Executable {
+        symbols: [("twice".to_owned(), 4)].into(),
+        ..Executable::default()
+    };
+    let mut bytes = executable.encode();
+    let symbol = bytes[bytes.len() - 11..].to_vec();
+    let count = bytes.len() - 15;
+    bytes[count] = 2;
+    bytes.extend(symbol);
+    assert!(invalid(&bytes));
+}
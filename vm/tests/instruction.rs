@@ -0,0 +1,157 @@
+use interpreter::{ErrorKind, Instruction, Width};
+
+#[test]
+fn decode() {
+    assert_eq!(
+        (
+            Instruction::MoveIf {
+                dst: 0,
+                src: 5,
+                cond: 4
+            },
+            4
+        ),
+        Instruction::decode(&[1, 0, 5, 4, 99]).unwrap()
+    );
+    assert_eq!(
+        (Instruction::Store { addr: 2, src: 3 }, 3),
+        Instruction::decode(&[2, 2, 3]).unwrap()
+    );
+    assert_eq!(
+        (Instruction::Load { dst: 11, addr: 3 }, 3),
+        Instruction::decode(&[3, 11, 3]).unwrap()
+    );
+    assert_eq!(
+        (Instruction::LoadImm { dst: 3, imm: -4 }, 4),
+        Instruction::decode(&[4, 3, 0xfc, 0xff]).unwrap()
+    );
+    assert_eq!(
+        (
+            Instruction::Sub {
+                dst: 2,
+                lhs: 2,
+                rhs: 3
+            },
+            4
+        ),
+        Instruction::decode(&[5, 2, 2, 3]).unwrap()
+    );
+    assert_eq!(
+        (Instruction::Out { src: 3 }, 2),
+        Instruction::decode(&[6, 3]).unwrap()
+    );
+    assert_eq!(
+        (Instruction::Exit, 1),
+        Instruction::decode(&[7, 7]).unwrap()
+    );
+    assert_eq!(
+        (Instruction::OutNumber { src: 7 }, 2),
+        Instruction::decode(&[8, 7]).unwrap()
+    );
+    assert_eq!(
+        (Instruction::In { dst: 4 }, 2),
+        Instruction::decode(&[9, 4]).unwrap()
+    );
+    assert_eq!(
+        (Instruction::InNumber { dst: 5 }, 2),
+        Instruction::decode(&[10, 5]).unwrap()
+    );
+}
+
+#[test]
+fn narrow_accesses() {
+    let cases = [
+        (11, Width::Byte, false, "load8 r1 <- [r2]"),
+        (12, Width::Byte, true, "load8s r1 <- [r2]"),
+        (13, Width::Half, false, "load16 r1 <- [r2]"),
+        (14, Width::Half, true, "load16s r1 <- [r2]"),
+    ];
+    for (opcode, width, signed, text) in cases {
+        let instruction = Instruction::LoadNarrow {
+            width,
+            signed,
+            dst: 1,
+            addr: 2,
+        };
+        assert_eq!(
+            (instruction, 3),
+            Instruction::decode(&[opcode, 1, 2]).unwrap()
+        );
+        assert_eq!(vec![opcode, 1, 2], instruction.encode());
+        assert_eq!(text, instruction.to_string());
+    }
+    for (opcode, width, text) in [
+        (15, Width::Byte, "store8 [r3] <- r4"),
+        (16, Width::Half, "store16 [r3] <- r4"),
+    ] {
+        let instruction = Instruction::StoreNarrow {
+            width,
+            addr: 3,
+            src: 4,
+        };
+        assert_eq!(
+            (instruction, 3),
+            Instruction::decode(&[opcode, 3, 4]).unwrap()
+        );
+        assert_eq!(vec![opcode, 3, 4], instruction.encode());
+        assert_eq!(text, instruction.to_string());
+    }
+    assert!(matches!(
+        Instruction::decode(&[15, 3, 16]),
+        Err(e) if matches!(e.kind(), ErrorKind::InvalidRegister { reg: 16 })
+    ));
+}
+
+#[test]
+fn exit_with_status() {
+    let instruction = Instruction::ExitWith { src: 3 };
+    assert_eq!((instruction, 2), Instruction::decode(&[17, 3, 7]).unwrap());
+    assert_eq!(vec![17, 3], instruction.encode());
+    assert_eq!("exit r3", instruction.to_string());
+}
+
+#[test]
+fn decode_errors() {
+    assert!(matches!(
+        Instruction::decode(&[]),
+        Err(e) if matches!(e.kind(), ErrorKind::TruncatedInstruction)
+    ));
+    assert!(matches!(
+        Instruction::decode(&[18, 0, 0, 0]),
+        Err(e) if matches!(e.kind(), ErrorKind::UnknownOpcode { opcode: 18 })
+    ));
+    assert!(matches!(
+        Instruction::decode(&[5, 1, 1]),
+        Err(e) if matches!(e.kind(), ErrorKind::TruncatedInstruction)
+    ));
+    assert!(matches!(
+        Instruction::decode(&[4, 16, 0, 0]),
+        Err(e) if matches!(e.kind(), ErrorKind::InvalidRegister { reg: 16 })
+    ));
+    // The immediate of loadimm is not a register
+    assert!(Instruction::decode(&[4, 1, 200, 200]).is_ok());
+}
+
+#[test]
+fn encode_round_trip() {
+    let program = include_bytes!("rfact.bin");
+    let mut addr = 0;
+    while addr < program.len() {
+        let (instruction, size) = Instruction::decode(&program[addr..]).unwrap();
+        assert_eq!(size, instruction.size());
+        assert_eq!(&program[addr..addr + size], &instruction.encode()[..]);
+        addr += size;
+    }
+}
+
+#[test]
+fn display() {
+    let (instruction, _) = Instruction::decode(&[1, 0, 5, 4]).unwrap();
+    assert_eq!("move r0 <- r5 if r4 != 0", instruction.to_string());
+    let (instruction, _) = Instruction::decode(&[4, 2, 0x00, 0x10]).unwrap();
+    assert_eq!("loadimm r2 <- #4096", instruction.to_string());
+    let (instruction, _) = Instruction::decode(&[2, 2, 3]).unwrap();
+    assert_eq!("store [r2] <- r3", instruction.to_string());
+    let (instruction, _) = Instruction::decode(&[10, 5]).unwrap();
+    assert_eq!("in_number r5", instruction.to_string());
+}
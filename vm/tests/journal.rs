@@ -0,0 +1,75 @@
+use interpreter::snapshot::Snapshot;
+use interpreter::Machine;
+use std::io;
+
+fn rfact_tr(n: u32) -> Machine {
+    let mut machine = Machine::new(include_bytes!("rfact_tr.bin")).unwrap();
+    machine.set_reg(10, n).unwrap();
+    machine
+}
+
+// Step forward to the end while taking snapshots, then step back to the
+// beginning and check every intermediate state.
+#[test]
+fn exact_rewind() {
+    let mut machine = rfact_tr(6);
+    machine.set_journal_capacity(usize::MAX);
+    let mut states: Vec<Snapshot> = vec![machine.snapshot()];
+    while !machine.step_on(&mut io::sink()).unwrap() {
+        states.push(machine.snapshot());
+    }
+    assert_eq!(720, machine.regs()[11]);
+    assert_eq!(states.len(), machine.journal_len());
+    while let Some(state) = states.pop() {
+        assert!(machine.step_back());
+        assert_eq!(state, machine.snapshot());
+    }
+    assert!(!machine.step_back());
+    assert_eq!(0, machine.executed_instructions());
+
+    // Replaying gives the same result
+    machine.run_on(&mut io::sink()).unwrap();
+    assert_eq!(720, machine.regs()[11]);
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut machine = rfact_tr(3);
+    machine.run_on(&mut io::sink()).unwrap();
+    assert_eq!(0, machine.journal_len());
+    assert!(!machine.step_back());
+}
+
+#[test]
+fn bounded_capacity() {
+    let mut machine = rfact_tr(5);
+    machine.set_journal_capacity(100);
+    machine.run_on(&mut io::sink()).unwrap();
+    let executed = machine.executed_instructions();
+    assert!(executed > 100);
+    assert_eq!(100, machine.journal_len());
+    machine.set_journal_capacity(10);
+    assert_eq!(10, machine.journal_len());
+    while machine.step_back() {}
+    assert_eq!(executed - 10, machine.executed_instructions());
+}
+
+#[test]
+fn run_back_to() {
+    let listing = interpreter::asm::assemble(include_str!("rfact_tr.dis")).unwrap();
+    let rfact = listing.labels["rfact_tr"];
+    let mut machine = rfact_tr(4);
+    machine.set_journal_capacity(10_000);
+    machine.run_on(&mut io::sink()).unwrap();
+
+    // rfact_tr is entered once per recursion level: rewind to the last entry,
+    // where r10 is 1, then to the previous ones
+    for n in 1..=4 {
+        assert!(machine.run_back_to(rfact));
+        assert_eq!(rfact, machine.regs()[0]);
+        assert_eq!(n, machine.regs()[10]);
+    }
+    assert!(!machine.run_back_to(rfact));
+    assert_eq!(0, machine.regs()[0]);
+    assert_eq!(0, machine.executed_instructions());
+}
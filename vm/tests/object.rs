@@ -0,0 +1,94 @@
+use interpreter::asm::assemble_object;
+use interpreter::object::{link, Error, Object};
+use interpreter::Machine;
+
+fn object(source: &str) -> Object {
+    assemble_object(source).unwrap()
+}
+
+#[test]
+fn link_library() {
+    let objects = [
+        object(include_str!("rfact_linked.dis")),
+        object(include_str!("../examples/lib/mult.dis")),
+    ];
+    let program = link(&objects).unwrap();
+    assert_eq!(objects[0].bytes.len() as u32, program.labels["mult"]);
+    for (n, fact) in [(1, 1), (5, 120), (10, 3_628_800)] {
+        let mut machine = Machine::new(&program.bytes).unwrap();
+        machine.set_reg(10, n).unwrap();
+        machine.run().unwrap();
+        assert_eq!(fact, machine.regs()[11]);
+    }
+
+    // Objects survive their encoding, and the library may come first
+    let objects = [
+        Object::decode(&object(include_str!("../examples/hello_linked.dis")).encode()).unwrap(),
+        Object::decode(&object(include_str!("../examples/lib/print.dis")).encode()).unwrap(),
+    ];
+    let program = link(&objects).unwrap();
+    let mut machine = Machine::new(&program.bytes).unwrap();
+    let mut output = Vec::new();
+    machine.run_on(&mut output).unwrap();
+    assert_eq!(b"Hello, world!\n", &output[..]);
+}
+
+#[test]
+fn encoding() {
+    let mult = object(include_str!("../examples/lib/mult.dis"));
+    let bytes = mult.encode();
+    assert!(bytes.starts_with(b"VMOB\x01\0\0\0"));
+    assert_eq!(mult, Object::decode(&bytes).unwrap());
+    assert!(mult.symbols["mult"].exported);
+    assert!(mult.symbols.values().filter(|s| s.exported).count() == 1);
+
+    assert_eq!(Err(Error::InvalidObject), Object::decode(b"VMOB"));
+    assert_eq!(
+        Err(Error::InvalidObject),
+        Object::decode(&bytes[..bytes.len() - 1])
+    );
+    let mut version = bytes.clone();
+    version[4] = 2;
+    assert_eq!(Err(Error::InvalidObject), Object::decode(&version));
+}
+
+#[test]
+fn link_errors() {
+    let rfact = object(include_str!("rfact_linked.dis"));
+    let mult = object(include_str!("../examples/lib/mult.dis"));
+    assert_eq!(
+        Err(Error::UnresolvedSymbol {
+            name: "mult".to_owned(),
+            module: 0
+        }),
+        link(std::slice::from_ref(&rfact))
+    );
+    assert_eq!(
+        Err(Error::DuplicateSymbol {
+            name: "mult".to_owned(),
+            modules: (1, 2)
+        }),
+        link(&[rfact, mult.clone(), mult])
+    );
+
+    // Private labels do not clash
+    let main = object(".import f\nstart:\ncall f\nexit\n");
+    let f = object(".export f\nstart:\nf:\nret\n");
+    assert!(link(&[main, f]).is_ok());
+}
+
+#[test]
+fn object_errors() {
+    let error = |source| assemble_object(source).unwrap_err().to_string();
+    assert_eq!("1:16: undefined label `f`", error("loadimm r0 <- #f\n"));
+    assert_eq!(
+        "1:9: imported label `f` is also defined",
+        error(".import f\nf:\nexit\n")
+    );
+    assert_eq!(
+        "1:9: exported label `f` is not defined",
+        error(".export f\nexit\n")
+    );
+    // Imports are only resolved by the linker
+    assert!(interpreter::asm::assemble(".import f\ncall f\n").is_err());
+}
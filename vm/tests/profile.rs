@@ -0,0 +1,72 @@
+use interpreter::asm::assemble;
+use interpreter::profile::Profiler;
+use interpreter::{Machine, RunOutcome};
+use std::io;
+
+#[test]
+fn factorial_example() {
+    let program = assemble(include_str!("../examples/factorial.dis")).unwrap();
+    let mut machine = Machine::new(&program.bytes).unwrap();
+    let mut profiler = Profiler::new();
+    let mut output = Vec::new();
+    let outcome = profiler.run_for(&mut machine, &mut io::empty(), &mut output, 100_000);
+    assert!(matches!(outcome, RunOutcome::Exited));
+    assert!(output.ends_with(b"I'm done!\n"));
+
+    let profile = profiler.profile();
+    assert_eq!(machine.executed_instructions(), profile.total);
+    assert_eq!(profile.total, profile.counts.values().sum::<u64>());
+    assert_eq!(profile.total, profile.stacks.values().sum::<u64>());
+    let function = |label: &str| profile.functions[&program.labels[label]];
+    assert_eq!(1, profile.functions[&0].calls);
+    assert_eq!(profile.total, profile.functions[&0].total_cost);
+    assert_eq!(10, function("fact").calls);
+    assert_eq!(45, function("mult").calls);
+    assert_eq!(32, function("print").calls);
+    assert_eq!(
+        function("fact").total_cost,
+        function("fact").self_cost + function("mult").total_cost
+    );
+    // Every call of mult leaves its loop once
+    assert_eq!(
+        profile.counts[&program.labels["ite_then_2"]] + function("mult").calls,
+        profile.counts[&program.labels["mult_loop"]]
+    );
+
+    let collapsed = profile.collapsed(&program.labels);
+    let stacks: Vec<&str> = collapsed
+        .lines()
+        .map(|line| line.split(' ').next().unwrap())
+        .collect();
+    assert_eq!(
+        vec!["main", "main;fact", "main;fact;mult", "main;print"],
+        stacks
+    );
+
+    let report = profile.report(&program.bytes, &program.labels);
+    assert!(report.starts_with(&format!("{} instructions executed\n", profile.total)));
+    assert!(report.contains("        10 "));
+    assert!(report.contains("  fact\n"));
+    assert!(report.contains("loadimm r0 <- #mult_loop\n"));
+    assert!(report.contains("\nmult_loop:\n"));
+}
+
+// Recursive calls are charged once in the total cost
+#[test]
+fn recursion() {
+    let program = assemble(include_str!("rfact.dis")).unwrap();
+    let mut machine = Machine::new(&program.bytes).unwrap();
+    machine.set_reg(10, 5).unwrap();
+    let mut profiler = Profiler::new();
+    profiler.run_for(&mut machine, &mut io::empty(), &mut io::sink(), 100_000);
+    let profile = profiler.profile();
+    let rfact = profile.functions[&program.labels["rfact"]];
+    let mult = profile.functions[&program.labels["mult"]];
+    assert_eq!(5, rfact.calls);
+    assert_eq!(4, mult.calls);
+    assert_eq!(rfact.self_cost + mult.total_cost, rfact.total_cost);
+    assert_eq!(profile.total, profile.functions[&0].total_cost);
+    let deepest = profile.stacks.keys().map(Vec::len).max().unwrap();
+    // main, then rfact for n = 5 down to 1
+    assert_eq!(6, deepest);
+}
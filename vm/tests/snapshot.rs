@@ -0,0 +1,82 @@
+use interpreter::snapshot::{Snapshot, VERSION};
+use interpreter::{ErrorKind, Machine, RunOutcome};
+use std::io;
+
+fn fact(n: u32) -> u32 {
+    (2..=n).product()
+}
+
+// Checkpoint in the middle of a run, then resume from the checkpoint
+#[test]
+fn resume_from_checkpoint() {
+    let mut machine = Machine::new(include_bytes!("rfact.bin")).unwrap();
+    machine.set_reg(10, 10).unwrap();
+    assert!(matches!(
+        machine.run_for_on(&mut io::sink(), 500),
+        RunOutcome::BudgetExhausted
+    ));
+    let bytes = machine.snapshot().encode();
+
+    let mut resumed = Machine::new(&[]).unwrap();
+    resumed.restore(&Snapshot::decode(&bytes).unwrap()).unwrap();
+    assert_eq!(machine.regs(), resumed.regs());
+    assert_eq!(machine.memory(), resumed.memory());
+    assert_eq!(500, resumed.executed_instructions());
+    assert!(matches!(
+        resumed.run_for_on(&mut io::sink(), 1_000_000),
+        RunOutcome::Exited
+    ));
+    assert_eq!(fact(10), resumed.regs()[11]);
+
+    machine.run_on(&mut io::sink()).unwrap();
+    assert_eq!(
+        machine.executed_instructions(),
+        resumed.executed_instructions()
+    );
+}
+
+#[test]
+fn round_trip() {
+    let mut machine = Machine::with_memory_size(&[7, 1, 2, 3], 1 << 20).unwrap();
+    machine.set_arith_extension(true);
+    machine.set_reg(15, 0xdead_beef).unwrap();
+    machine.set_memory(1000, &[42]).unwrap();
+    let snapshot = machine.snapshot();
+    let bytes = snapshot.encode();
+    // Trailing zeros are not stored
+    assert_eq!(100 + 1001, bytes.len());
+    assert_eq!(b"VMSS", &bytes[..4]);
+    assert_eq!(VERSION.to_le_bytes(), bytes[4..8]);
+    let decoded = Snapshot::decode(&bytes).unwrap();
+    assert_eq!(snapshot, decoded);
+
+    let mut restored = Machine::new(&[]).unwrap();
+    restored.restore(&decoded).unwrap();
+    assert!(restored.arith_extension());
+    assert_eq!(1 << 20, restored.memory().len());
+    assert_eq!(0xdead_beef, restored.regs()[15]);
+}
+
+#[test]
+fn invalid_snapshots() {
+    let bytes = Machine::new(&[1, 2, 3]).unwrap().snapshot().encode();
+    let decode = |bytes: &[u8]| Snapshot::decode(bytes);
+    assert!(decode(&bytes).is_ok());
+    assert!(matches!(decode(&[]), Err(e) if matches!(e.kind(), ErrorKind::InvalidSnapshot)));
+    assert!(matches!(
+        decode(&bytes[..bytes.len() - 1]),
+        Err(e) if matches!(e.kind(), ErrorKind::InvalidSnapshot)
+    ));
+    let mut extra = bytes.clone();
+    extra.push(1);
+    assert!(matches!(decode(&extra), Err(e) if matches!(e.kind(), ErrorKind::InvalidSnapshot)));
+    let mut magic = bytes.clone();
+    magic[0] = b'X';
+    assert!(matches!(decode(&magic), Err(e) if matches!(e.kind(), ErrorKind::InvalidSnapshot)));
+    let mut version = bytes.clone();
+    version[4] = 2;
+    assert!(matches!(decode(&version), Err(e) if matches!(e.kind(), ErrorKind::InvalidSnapshot)));
+    let mut size = bytes;
+    size[84..92].copy_from_slice(&(1u64 << 33).to_le_bytes());
+    assert!(matches!(decode(&size), Err(e) if matches!(e.kind(), ErrorKind::MemoryOverflow)));
+}
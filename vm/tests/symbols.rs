@@ -0,0 +1,129 @@
+use interpreter::asm::assemble;
+use interpreter::profile::Profiler;
+use interpreter::symbols::SymbolTable;
+use interpreter::trace::{JsonTracer, TextTracer};
+use interpreter::{Machine, RunOutcome};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// Shared buffer, to read what a tracer owning it wrote.
+#[derive(Clone, Default)]
+struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.borrow().clone())
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+#[test]
+fn resolve() {
+    let symbols = SymbolTable::parse(include_str!("../examples/99bottles.dis")).unwrap();
+    assert_eq!(Some("print"), symbols.label(1165));
+    assert_eq!(None, symbols.label(1169));
+    assert_eq!(Some(("print", 4)), symbols.resolve(1169));
+    assert_eq!("print+4", symbols.symbolize(1169));
+    assert_eq!("loop", symbols.symbolize(8));
+    // No label before the first instructions
+    assert_eq!(None, symbols.resolve(4));
+    assert_eq!("0004", symbols.symbolize(4));
+
+    assert!(SymbolTable::parse("loadimm r0 <- #nowhere\n").is_err());
+    assert!(SymbolTable::default().is_empty());
+}
+
+#[test]
+fn errors() {
+    let program =
+        assemble("loadimm r1 <- #1\nloop:\nloadimm r2 <- #0\ndivu r3 <- r1 / r2\n").unwrap();
+    let symbols = SymbolTable::new(&program.labels);
+    let mut machine = Machine::new(&program.bytes).unwrap();
+    machine.set_arith_extension(true);
+    let RunOutcome::Faulted(error) = machine.run_for(10) else {
+        panic!("the division should fail");
+    };
+    assert_eq!(
+        "division by zero at 0008: divu r3 <- r1 / r2 (23 03 01 02)",
+        error.to_string()
+    );
+    assert_eq!(
+        "division by zero at loop+4: divu r3 <- r1 / r2 (23 03 01 02)",
+        error.symbolize(&symbols).to_string()
+    );
+}
+
+#[test]
+fn traces() {
+    let program = assemble(include_str!("fact.dis")).unwrap();
+    let symbols = SymbolTable::new(&program.labels);
+    for json in [false, true] {
+        let buffer = Buffer::default();
+        let mut machine = Machine::new(&program.bytes).unwrap();
+        machine.set_reg(10, 1).unwrap();
+        if json {
+            machine.set_tracer(JsonTracer::with_symbols(buffer.clone(), symbols.clone()));
+        } else {
+            machine.set_tracer(TextTracer::with_symbols(buffer.clone(), symbols.clone()));
+        }
+        machine.run().unwrap();
+        let lines = buffer.lines();
+        if json {
+            assert!(lines[0].starts_with("{\"ip\":0,\"symbol\":\"0000\",\"instruction\""));
+            assert!(lines[8].starts_with("{\"ip\":95,\"symbol\":\"fact_loop+4\","));
+        } else {
+            assert_eq!(
+                "0000            loadimm r2 <- #4096          r2=0x00001000",
+                lines[0]
+            );
+            assert_eq!(
+                "fact_loop+4     sub r8 <- r10 - r8           r8=0x00000000",
+                lines[8]
+            );
+        }
+    }
+}
+
+#[test]
+fn json_symbols() {
+    // Symbols of an executable file may hold any character
+    let program = assemble("exit\n").unwrap();
+    let labels = [("say \"hi\"\\\n".to_owned(), 0)].into();
+    let buffer = Buffer::default();
+    let mut machine = Machine::new(&program.bytes).unwrap();
+    machine.set_tracer(JsonTracer::with_symbols(
+        buffer.clone(),
+        SymbolTable::new(&labels),
+    ));
+    machine.run().unwrap();
+    assert_eq!(
+        "{\"ip\":0,\"symbol\":\"say \\\"hi\\\"\\\\\\n\",\"instruction\":\"exit\",\"regs\":[],\"memory\":[]}",
+        buffer.lines()[0]
+    );
+}
+
+#[test]
+fn profile() {
+    let program = assemble(include_str!("../examples/99bottles.dis")).unwrap();
+    let mut machine = Machine::new(&program.bytes).unwrap();
+    let mut profiler = Profiler::new();
+    let outcome = profiler.run_for(&mut machine, &mut io::empty(), &mut io::sink(), u64::MAX);
+    assert!(matches!(outcome, RunOutcome::Exited));
+    let report = profiler.profile().report(&program.bytes, &program.labels);
+    let hottest = report.split("\nhottest instructions:\n").nth(1).unwrap();
+    assert!(hottest.contains("%  print+4   move r0 <- r8 if r11 != 0\n"));
+}
@@ -0,0 +1,113 @@
+use interpreter::trace::{MemWrite, RegWrite, Step};
+use interpreter::Machine;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Run `code` and collect the trace with `format`.
+fn trace(code: &[u8], format: fn(&Step) -> String) -> Vec<String> {
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let mut machine = Machine::new(code).unwrap();
+    let collected = Rc::clone(&lines);
+    machine.set_tracer(move |step: &Step| {
+        collected.borrow_mut().push(format(step));
+        Ok(())
+    });
+    machine.run_on(&mut Vec::new()).unwrap();
+    machine.remove_tracer();
+    Rc::try_unwrap(lines).unwrap().into_inner()
+}
+
+#[test]
+fn text_trace() {
+    let lines = trace(include_bytes!("push_pop.bin"), |step| step.to_string());
+    assert_eq!(18, lines.len());
+    assert_eq!(
+        "0000   loadimm r2 <- #4096          r2=0x00001000",
+        lines[0]
+    );
+    assert_eq!("0012   store [r2] <- r0             [4092]=0x0f", lines[3]);
+    assert_eq!("0064   exit                        ", lines[17]);
+}
+
+#[test]
+fn json_trace() {
+    let lines = trace(include_bytes!("push_pop.bin"), |step| step.to_json());
+    assert_eq!(
+        r#"{"ip":0,"instruction":"loadimm r2 <- #4096","regs":[{"reg":2,"value":4096}],"memory":[]}"#,
+        lines[0]
+    );
+    assert_eq!(
+        r#"{"ip":12,"instruction":"store [r2] <- r0","regs":[],"memory":[{"addr":4092,"value":15}]}"#,
+        lines[3]
+    );
+}
+
+#[test]
+fn recorded_effects() {
+    // 0: move r1 <- r2 if r5 != 0
+    // 4: loadimm r3 <- #-1
+    // 8: move r1 <- r3 if r3 != 0
+    // 12: store [r4] <- r3
+    // 15: store [r4] <- r3
+    // 18: exit
+    let code = [
+        1, 1, 2, 5, 4, 3, 0xff, 0xff, 1, 1, 3, 3, 2, 4, 3, 2, 4, 3, 7,
+    ];
+    let steps = Rc::new(RefCell::new(Vec::new()));
+    let mut machine = Machine::new(&code).unwrap();
+    machine.set_reg(4, 100).unwrap();
+    machine.set_reg(3, 0xff).unwrap();
+    let collected = Rc::clone(&steps);
+    machine.set_tracer(move |step: &Step| {
+        collected
+            .borrow_mut()
+            .push((step.ip, step.regs.to_vec(), step.memory.to_vec()));
+        Ok(())
+    });
+    machine.run_on(&mut Vec::new()).unwrap();
+    let steps = steps.borrow();
+    // Condition not met: nothing written
+    assert_eq!((0, vec![], vec![]), steps[0]);
+    assert_eq!(
+        vec![RegWrite {
+            reg: 1,
+            old: 0,
+            new: 0xffff_ffff
+        }],
+        steps[2].1
+    );
+    // Only the bytes whose value changes are reported
+    assert_eq!(
+        vec![
+            MemWrite {
+                addr: 100,
+                old: 0,
+                new: 0xff
+            },
+            MemWrite {
+                addr: 101,
+                old: 0,
+                new: 0xff
+            },
+            MemWrite {
+                addr: 102,
+                old: 0,
+                new: 0xff
+            },
+            MemWrite {
+                addr: 103,
+                old: 0,
+                new: 0xff
+            },
+        ],
+        steps[3].2
+    );
+    assert!(steps[4].2.is_empty());
+}
+
+#[test]
+fn tracer_failure_stops_machine() {
+    let mut machine = Machine::new(&[7]).unwrap();
+    machine.set_tracer(|_: &Step| Err(std::io::Error::other("full")));
+    assert!(machine.step_on(&mut Vec::new()).is_err());
+}